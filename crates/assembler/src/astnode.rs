@@ -2,6 +2,7 @@ use crate::opcode::Opcode;
 use crate::lexer::{Token, ImmediateValue};
 use crate::dynsym::RelocationType;
 use crate::debuginfo::{DebugInfo, RegisterHint, RegisterType};
+use crate::utils::hash_symbol_name;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -98,10 +99,7 @@ impl Instruction {
         match self.opcode {
             Opcode::Call => true,
             Opcode::Lddw => {
-                match &self.operands[1] {
-                    Token::Identifier(_, _) => true,
-                    _ => false,
-                }
+                matches!(&self.operands[1], Token::Identifier(_, _))
             },
             _ => false,
         }
@@ -157,8 +155,30 @@ impl ASTNode {
                 bytes.push(opcode.to_bytecode());  // 1 byte opcode
                 
                 if *opcode == Opcode::Call {
-                    // currently hardcoded to call sol_log_
-                    bytes.extend_from_slice(&[0x10, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
+                    match &operands[..] {
+                        [Token::ImmediateValue(imm, _)] => {
+                            // internal call: src = 1, pc-relative immediate
+                            let imm32 = match imm {
+                                ImmediateValue::Int(val) => *val as i32,
+                                ImmediateValue::Addr(val) => *val as i32,
+                            };
+                            bytes.extend_from_slice(&[0x10, 0x00, 0x00]);
+                            bytes.extend_from_slice(&imm32.to_le_bytes());
+                        }
+                        [Token::Identifier(name, _)] => {
+                            // syscall: src = 0, immediate is the hash of its name,
+                            // the relocation lets the loader resolve it as well
+                            bytes.extend_from_slice(&[0x00, 0x00, 0x00]);
+                            bytes.extend_from_slice(&hash_symbol_name(name).to_le_bytes());
+                        }
+                        _ => {}
+                    }
+                } else if *opcode == Opcode::Callx {
+                    // target register is encoded in the immediate
+                    if let [Token::Register(reg, _)] = &operands[..] {
+                        bytes.extend_from_slice(&[0x00, 0x00, 0x00]);
+                        bytes.extend_from_slice(&(*reg as i32).to_le_bytes());
+                    }
                 } else {
                     match &operands[..] {
                         [Token::ImmediateValue(imm, _)] => {
@@ -201,6 +221,34 @@ impl ASTNode {
                                 }
                             };
                             bytes.extend_from_slice(&imm32.to_le_bytes());
+
+                            if *opcode == Opcode::Lddw {
+                                // high 32 bits go in the immediate of the second slot
+                                let imm64 = match imm {
+                                    ImmediateValue::Int(val) => *val,
+                                    ImmediateValue::Addr(val) => *val,
+                                };
+                                bytes.extend_from_slice(&[0, 0, 0, 0]);
+                                bytes.extend_from_slice(&((imm64 >> 32) as i32).to_le_bytes());
+                            }
+                        },
+
+                        [Token::Register(dst, _), Token::ImmediateValue(offset, _), Token::ImmediateValue(imm, _)]
+                            if matches!(opcode, Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw) => {
+                            // Store immediate: base register, 2 bytes of offset, 4 bytes of value
+                            bytes.push(*dst);
+
+                            let offset16 = match offset {
+                                ImmediateValue::Int(val) => *val as u16,
+                                ImmediateValue::Addr(val) => *val as u16,
+                            };
+                            bytes.extend_from_slice(&offset16.to_le_bytes());
+
+                            let imm32 = match imm {
+                                ImmediateValue::Int(val) => *val as i32,
+                                ImmediateValue::Addr(val) => *val as i32,
+                            };
+                            bytes.extend_from_slice(&imm32.to_le_bytes());
                         },
 
                         [Token::Register(reg, _), Token::ImmediateValue(imm, _), Token::ImmediateValue(offset, _)] => {
@@ -250,7 +298,18 @@ impl ASTNode {
                             };
                             bytes.extend_from_slice(&offset16.to_le_bytes());
                         },
-                        
+                        [Token::Register(dst, _), Token::ImmediateValue(offset, _), Token::Register(src, _)] => {
+                            // Store: base register in the low nibble, source register in the high nibble
+                            let reg_byte = (src << 4) | dst;
+                            bytes.push(reg_byte);
+
+                            let offset16 = match offset {
+                                ImmediateValue::Int(val) => *val as u16,
+                                ImmediateValue::Addr(val) => *val as u16,
+                            };
+                            bytes.extend_from_slice(&offset16.to_le_bytes());
+                        },
+
                         _ => {}
                     }
                 }
@@ -265,10 +324,9 @@ impl ASTNode {
                 
                 Some((bytes, debug_map))
             },
-            ASTNode::ROData { rodata: ROData { name: _, args, line_number }, offset } => {
+            ASTNode::ROData { rodata: ROData { args, .. }, .. } => {
                 let mut bytes = Vec::new();
-                let mut line_map = HashMap::<u64, usize>::new();
-                let mut debug_map = HashMap::<u64, DebugInfo>::new();
+                let debug_map = HashMap::<u64, DebugInfo>::new();
                 for arg in args {
                     if let Token::StringLiteral(s, _) = arg {
                         // Convert string to bytes and add null terminator
@@ -287,17 +345,3 @@ impl ASTNode {
         self.bytecode_with_debug_map().map(|(bytes, _)| bytes)
     }
 }
-
-fn parse_expression(expr: &String) -> Option<(String, i32)> {
-    // Split the expression by '+' and trim whitespace
-    let parts: Vec<&str> = expr.split('+').map(str::trim).collect();
-    
-    if parts.len() == 2 {
-        // Assume the first part is the register and the second part is the offset
-        let base_reg = parts[0].to_string();
-        if let Ok(offset) = parts[1].parse::<i32>() {
-            return Some((base_reg, offset));
-        }
-    }
-    None
-}
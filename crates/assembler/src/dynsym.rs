@@ -44,6 +44,12 @@ pub struct DynamicSymbolMap {
     symbols: HashMap<String, Vec<(SymbolKind, u64)>>,
 }
 
+impl Default for DynamicSymbolMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DynamicSymbolMap {
     pub fn new() -> Self {
         Self {
@@ -138,6 +144,12 @@ pub struct RelDynMap {
     rel_dyns: HashMap<u64, Vec<(RelocationType, String)>>,
 }
 
+impl Default for RelDynMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RelDynMap {
     pub fn new() -> Self {
         Self { rel_dyns: HashMap::new() }
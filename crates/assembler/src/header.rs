@@ -28,6 +28,12 @@ pub struct ProgramHeader {
     pub p_align: u64,     // Alignment of segment
 }
 
+impl Default for ElfHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ElfHeader {
     const SOLANA_IDENT: [u8; 16] = [
         0x7f, 0x45, 0x4c, 0x46,  // EI_MAG0..EI_MAG3 ("\x7FELF")
@@ -176,6 +182,7 @@ impl SectionHeader {
     pub const SHF_ALLOC: u64 = 0x2;       // Occupies memory during execution
     pub const SHF_EXECINSTR: u64 = 0x4;   // Executable
     
+    #[allow(clippy::too_many_arguments)]
     pub fn new(name_offset: u32, sh_type: u32, flags: u64, addr: u64, offset: u64, size: u64, link: u32, info: u32, addralign: u64, entsize: u64) -> Self {
         Self {
             sh_name: name_offset,
@@ -1,7 +1,43 @@
 use crate::opcode::Opcode;
 use crate::lexer::Token;
+use crate::version::SbpfVersion;
 
-pub fn verify_instruction(opcode: &Opcode, operands: &[Token]) -> Result<(), String> {
+// Rejects instructions the targeted SBPF version does not have
+pub fn verify_sbpf_version(opcode: &Opcode, operands: &[Token], version: SbpfVersion) -> Result<(), String> {
+    if !version.supports(*opcode) {
+        let hint = match opcode {
+            Opcode::Lddw => ", load 64-bit values with mov32 and hor64",
+            Opcode::Mul32 | Opcode::Mul64 | Opcode::Mul32Imm | Opcode::Mul64Imm | Opcode::Mul32Reg | Opcode::Mul64Reg => ", use lmul",
+            Opcode::Div32 | Opcode::Div64 | Opcode::Div32Imm | Opcode::Div64Imm | Opcode::Div32Reg | Opcode::Div64Reg => ", use udiv or sdiv",
+            Opcode::Mod32 | Opcode::Mod64 | Opcode::Mod32Imm | Opcode::Mod64Imm | Opcode::Mod32Reg | Opcode::Mod64Reg => ", use urem or srem",
+            Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw
+            | Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw
+            | Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => ", the assembler does not emit its new encoding yet",
+            _ => "",
+        };
+        return Err(format!("{} is not available in SBPF {}{}", opcode.to_str(), version, hint));
+    }
+    // encoded differently from v2, which the assembler does not emit yet
+    if *opcode == Opcode::Callx && version.callx_uses_src_reg() {
+        return Err(format!("callx is not available in SBPF {}, the assembler does not emit its new encoding yet", version));
+    }
+    // from v2 `sub dst, imm` computes imm - dst
+    let subtracts_immediate = matches!(opcode, Opcode::Sub32 | Opcode::Sub32Imm | Opcode::Sub64 | Opcode::Sub64Imm)
+        && matches!(operands, [_, Token::ImmediateValue(_, _)]);
+    if subtracts_immediate && version.swap_sub_reg_imm_operands() {
+        return Err(format!("{} with an immediate is not available in SBPF {}, add the negated immediate instead", opcode.to_str(), version));
+    }
+    // the only write to r10 a program can make, with dynamic stack frames
+    let adjusts_stack_pointer = matches!(opcode, Opcode::Add64 | Opcode::Add64Imm)
+        && matches!(operands, [Token::Register(10, _), Token::ImmediateValue(_, _)]);
+    if adjusts_stack_pointer && !version.dynamic_stack_frames() {
+        return Err(format!("add64 r10 is not available in SBPF {}, stack frames are dynamic from {}", version, SbpfVersion::V1));
+    }
+    Ok(())
+}
+
+pub fn verify_instruction(opcode: &Opcode, operands: &[Token], version: SbpfVersion) -> Result<(), String> {
+    verify_sbpf_version(opcode, operands, version)?;
     match opcode {
         Opcode::Add32 | Opcode::Sub32 | Opcode::Mul32 | Opcode::Div32 | Opcode::Or32 | Opcode::And32 | Opcode::Lsh32 | Opcode::Rsh32 | Opcode::Mod32 | Opcode::Xor32 | Opcode::Mov32 | Opcode::Arsh32 | Opcode::Lmul32 | Opcode::Udiv32 | Opcode::Urem32 | Opcode::Sdiv32 | Opcode::Srem32 | Opcode::Neg32 => {
             if operands.len() != 2 {
@@ -83,7 +119,7 @@ pub fn verify_instruction(opcode: &Opcode, operands: &[Token]) -> Result<(), Str
         },
         // le be
         Opcode::Le | Opcode::Be => {
-            return Err(format!("Unsure how to handle {}", opcode.to_str()));
+            Err(format!("Unsure how to handle {}", opcode.to_str()))
         },
         _ => Err(format!("Unsupported opcode: {:?}", opcode)),
     }
@@ -1,4 +1,5 @@
 use crate::opcode::Opcode;
+use std::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub enum Op {
@@ -57,9 +58,9 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
 
         // Handle comments - skip rest of line
         let line = if let Some(comment_pos) = line.find("//") {
-            &line[..comment_pos].trim()
+            line[..comment_pos].trim()
         } else if let Some(comment_pos) = line.find("#") {
-            &line[..comment_pos].trim()
+            line[..comment_pos].trim()
         } else {
             line.trim()
         };
@@ -73,22 +74,22 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
 
         while let Some(&c) = chars.peek() {
             match c {
-                c if c.is_digit(10) => {
+                c if c.is_ascii_digit() => {
                     let mut number = String::new();
-                    let mut isAddr = false;
+                    let mut is_addr = false;
                     while let Some(&c) = chars.peek() {
-                        if c.is_digit(10) {
+                        if c.is_ascii_digit() {
                             number.push(chars.next().unwrap());
                         } else if number == "0" && c == 'x' {
                             chars.next();
-                            isAddr = true; /*  */ number = String::new();
-                        } else if isAddr && (c == 'a' || c == 'b' || c == 'c' || c == 'd' || c == 'e' || c == 'f') {
+                            is_addr = true; /*  */ number = String::new();
+                        } else if is_addr && (c == 'a' || c == 'b' || c == 'c' || c == 'd' || c == 'e' || c == 'f') {
                             number.push(chars.next().unwrap());
                         } else {
                             break;
                         }
                     }
-                    if isAddr {
+                    if is_addr {
                         tokens.push(Token::ImmediateValue(ImmediateValue::Addr(i64::from_str_radix(&number, 16).map_err(|_| "Invalid number")?), line_number)); 
                     } else {
                         tokens.push(Token::ImmediateValue(ImmediateValue::Int(number.parse::<i64>().map_err(|_| "Invalid number")?), line_number));
@@ -108,7 +109,7 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
                     if identifier.ends_with(':') {
                         let label_name = identifier.trim_end_matches(':').to_string();
                         tokens.push(Token::Label(label_name, line_number));
-                    } else if identifier.starts_with('r') && identifier[1..].chars().all(|c| c.is_digit(10)) {
+                    } else if identifier.starts_with('r') && identifier[1..].chars().all(|c| c.is_ascii_digit()) {
                         tokens.push(Token::Register(identifier[1..].parse::<u8>().map_err(|_| "Invalid register")?, line_number));
                     } else if let Ok(opcode) = Opcode::from_str(&identifier) {
                         tokens.push(Token::Opcode(opcode, line_number));
@@ -3,6 +3,7 @@ pub mod parser;
 pub mod lexer;
 pub mod opcode;
 pub mod instruction_verifier;
+pub mod version;
 pub mod utils;
 
 // Intermediate Representation
@@ -29,5 +30,6 @@ pub use self::{
     parser::Parser,
     program::Program,
     lexer::tokenize,
+    version::SbpfVersion,
 };
 
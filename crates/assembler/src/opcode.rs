@@ -1,4 +1,5 @@
 use num_derive::FromPrimitive;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
 #[repr(u8)]
@@ -168,8 +169,10 @@ pub enum Opcode {
     Exit,
 }
 
-impl Opcode {
-    pub fn from_str(s: &str) -> Result<Self, &'static str> {
+impl FromStr for Opcode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "lddw" => Ok(Opcode::Lddw),
             "ldxb" => Ok(Opcode::Ldxb),
@@ -243,7 +246,9 @@ impl Opcode {
             _ => Err("Invalid opcode"),
         }
     }
+}
 
+impl Opcode {
     pub fn from_u8(u: u8) -> Option<Self> {
         match u {
             0x18 => Some(Opcode::Lddw),
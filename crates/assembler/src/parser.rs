@@ -4,12 +4,13 @@ use crate::lexer::{Token, ImmediateValue};
 use crate::section::{CodeSection, DataSection};
 use crate::astnode::{ASTNode, Directive, GlobalDecl, EquDecl, ExternDecl, RodataDecl, Label, Instruction, ROData};
 use crate::dynsym::{DynamicSymbolMap, RelDynMap, RelocationType};
+use crate::instruction_verifier::verify_sbpf_version;
+use crate::version::SbpfVersion;
 use num_traits::FromPrimitive;
 use std::collections::HashMap;
 
 pub struct Parser {
     tokens: Vec<Token>,
-    current: usize,
 
     pub m_prog_is_static: bool,
     pub m_accum_offset: u64,
@@ -24,6 +25,8 @@ pub struct Parser {
     m_rel_dyns: RelDynMap,
 
     m_rodata_size: u64,
+
+    m_sbpf_version: SbpfVersion,
 }
 
 pub struct ParseResult {
@@ -38,6 +41,8 @@ pub struct ParseResult {
 
     // TODO: this should determine by if there's any dynamic symbol
     pub prog_is_static: bool,
+
+    pub sbpf_version: SbpfVersion,
 }
 
 pub trait Parse {
@@ -79,7 +84,7 @@ impl Parse for EquDecl {
             (
                 Token::Identifier(name, line_number),
                 Token::Comma(_),
-                Token::ImmediateValue(value, num_line_number)
+                Token::ImmediateValue(_, _)
             ) => {
                 Some((
                     EquDecl {
@@ -164,10 +169,10 @@ impl Parse for ROData {
 
 impl ParseInstruction for Instruction {
     fn parse_instruction<'a>(tokens: &'a [Token], const_map: &HashMap<String, ImmediateValue>) -> Option<(Self, &'a [Token])> {
-        let mut next_token_num = 1;
+        let next_token_num;
         match &tokens[0] {
             Token::Opcode(opcode, line_number) => {
-                let mut opcode = opcode.clone();
+                let mut opcode = *opcode;
                 let mut operands = Vec::new();
                 match opcode {
                     Opcode::Lddw => {
@@ -220,7 +225,8 @@ impl ParseInstruction for Instruction {
                         if tokens.len() < 8 {
                             return None;
                         }
-                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 6);
+                        // fold from zero so the operator before the first term sets its sign
+                        let (value, advance_token_num) = inline_and_fold_constant_helper(tokens, const_map, ImmediateValue::Int(0), 4);
                         if let Some(value) = value {
                             match (
                                 &tokens[1],
@@ -258,33 +264,49 @@ impl ParseInstruction for Instruction {
                         if tokens.len() < 8 {
                             return None;
                         }
+                        // fold from zero so the operator before the first term sets its sign
+                        let (offset, advance_token_num) = inline_and_fold_constant_helper(tokens, const_map, ImmediateValue::Int(0), 2);
+                        if tokens.len() < advance_token_num + 3 {
+                            return None;
+                        }
                         match (
                             &tokens[1],
                             &tokens[2],
-                            &tokens[3],
-                            &tokens[4],
-                            &tokens[5],
-                            &tokens[6],
-                            &tokens[7],
+                            // Offset is folded to an immediate value
+                            &tokens[advance_token_num],
+                            &tokens[advance_token_num + 1],
                         ) {
                             (
                                 Token::LeftBracket(_),
                                 Token::Register(_, _),
-                                Token::BinaryOp(_, _),
-                                Token::ImmediateValue(_, _),
+                                // Offset is folded to an immediate value
                                 Token::RightBracket(_),
-                                Token::Comma(_),
-                                Token::Register(_, _)
+                                Token::Comma(_)
                             ) => {
                                 operands.push(tokens[2].clone());
-                                operands.push(tokens[4].clone());
-                                operands.push(tokens[7].clone());
+                                operands.push(Token::ImmediateValue(offset?, 0));
                             }
                             _ => {
                                 return None;
                             }
                         }
-                        next_token_num = 8;
+                        if matches!(opcode, Opcode::Stw | Opcode::Sth | Opcode::Stb | Opcode::Stdw) {
+                            // st* stores an immediate, which may be folded from constants
+                            let (value, value_advance_token_num) = inline_and_fold_constant(tokens, const_map, advance_token_num + 2);
+                            operands.push(Token::ImmediateValue(value?, 0));
+                            next_token_num = value_advance_token_num;
+                        } else {
+                            // stx* stores a register
+                            match &tokens[advance_token_num + 2] {
+                                Token::Register(_, _) => {
+                                    operands.push(tokens[advance_token_num + 2].clone());
+                                }
+                                _ => {
+                                    return None;
+                                }
+                            }
+                            next_token_num = advance_token_num + 3;
+                        }
                     }
                     Opcode::Add32 | Opcode::Sub32 | Opcode::Mul32 
                     | Opcode::Div32 | Opcode::Or32 | Opcode::And32 
@@ -388,6 +410,22 @@ impl ParseInstruction for Instruction {
                         }
                         next_token_num = 6;
                     }
+                    Opcode::Hor64Imm => {
+                        if tokens.len() < 4 {
+                            return None;
+                        }
+                        let (value, advance_token_num) = inline_and_fold_constant(tokens, const_map, 3);
+                        match (&tokens[1], &tokens[2], value) {
+                            (Token::Register(_, _), Token::Comma(_), Some(value)) => {
+                                operands.push(tokens[1].clone());
+                                operands.push(Token::ImmediateValue(value, 0));
+                            }
+                            _ => {
+                                return None;
+                            }
+                        }
+                        next_token_num = advance_token_num;
+                    }
                     Opcode::Ja => {
                         if tokens.len() < 2 {
                             return None;
@@ -416,6 +454,20 @@ impl ParseInstruction for Instruction {
                         }
                         next_token_num = 2;
                     }
+                    Opcode::Callx => {
+                        if tokens.len() < 2 {
+                            return None;
+                        }
+                        match &tokens[1] {
+                            Token::Register(_, _) => {
+                                operands.push(tokens[1].clone());
+                            }
+                            _ => {
+                                return None;
+                            }
+                        }
+                        next_token_num = 2;
+                    }
                     Opcode::Exit => {
                         next_token_num = 1;
                     }
@@ -443,7 +495,7 @@ fn inline_and_fold_constant_helper(tokens: &[Token]
                                 , const_map: &HashMap<String, ImmediateValue>   //
                                 , value: ImmediateValue                         //
                                 , idx: usize) -> (Option<ImmediateValue>, usize) {
-    if tokens.len() < idx + 1 {
+    if tokens.len() < idx + 3 {
         return (Some(value), idx + 1);
     }
     match (
@@ -457,7 +509,17 @@ fn inline_and_fold_constant_helper(tokens: &[Token]
             let result = match op {
                 Op::Add => value + value2.clone(),
                 Op::Sub => value - value2.clone(),
-                _ => return (Some(value), idx + 1),
+            };
+            inline_and_fold_constant_helper(tokens, const_map, result, idx + 2)
+        }
+        (
+            Token::BinaryOp(op, _),
+            Token::Identifier(name, _)
+        ) if const_map.contains_key(name) => {
+            let value2 = const_map[name].clone();
+            let result = match op {
+                Op::Add => value + value2,
+                Op::Sub => value - value2,
             };
             inline_and_fold_constant_helper(tokens, const_map, result, idx + 2)
         }
@@ -470,6 +532,8 @@ fn inline_and_fold_constant(tokens: &[Token]                            //
                         , idx: usize) -> (Option<ImmediateValue>, usize) {
     let value = match &tokens[idx] {
         Token::ImmediateValue(value, _) => value.clone(),
+        // a leading minus folds from zero, e.g. add64 r10, -64
+        Token::BinaryOp(Op::Sub, _) => return inline_and_fold_constant_helper(tokens, const_map, ImmediateValue::Int(0), idx - 1),
         Token::Identifier(name, _) => {
             if let Some(val) = const_map.get(name) {
                 val.clone()
@@ -484,7 +548,7 @@ fn inline_and_fold_constant(tokens: &[Token]                            //
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0
+        Self { tokens
             , m_prog_is_static: true
             , m_accum_offset: 0
             , m_entry_label: None
@@ -493,9 +557,14 @@ impl Parser {
             , m_rodata_size: 0
             , m_dynamic_symbols: DynamicSymbolMap::new()
             , m_rel_dyns: RelDynMap::new()
+            , m_sbpf_version: SbpfVersion::default()
         }
     }
 
+    pub fn set_sbpf_version(&mut self, version: SbpfVersion) {
+        self.m_sbpf_version = version;
+    }
+
     pub fn parse(&mut self) -> Result<ParseResult, String> {
         let mut nodes = Vec::new();
         let mut rodata_nodes = Vec::new();
@@ -564,7 +633,10 @@ impl Parser {
                 }
                 Token::Opcode(opcode, line_number) => {
                     if let Some((inst, rest)) = Instruction::parse_instruction(tokens, &self.m_const_map) {
-                        if inst.needs_relocation() {
+                        verify_sbpf_version(&inst.opcode, &inst.operands, self.m_sbpf_version)
+                            .map_err(|e| format!("Invalid instruction at line {}: {}", line_number, e))?;
+                        // calls are resolved once all labels are known
+                        if inst.needs_relocation() && inst.opcode != Opcode::Call {
                             self.m_prog_is_static = false;
                             let (reloc_type, label) = inst.get_relocation_info();
                             self.m_rel_dyns.add_rel_dyn(self.m_accum_offset, reloc_type, label.clone());
@@ -577,6 +649,9 @@ impl Parser {
                         nodes.push(ASTNode::Instruction { instruction: inst, offset });
                         tokens = rest;
                     } else {
+                        // an instruction of another version is worth a clearer error
+                        verify_sbpf_version(opcode, &[], self.m_sbpf_version)
+                            .map_err(|e| format!("Invalid instruction at line {}: {}", line_number, e))?;
                         return Err(format!("Invalid instruction at line {}", line_number));
                     }
                 }
@@ -586,42 +661,62 @@ impl Parser {
             }
         }
 
+        // Calls to labels defined in this file are internal calls, anything else is a syscall.
+        // Static syscalls are encoded by the hash of their name alone.
+        for node in nodes.iter().filter(|_| !self.m_sbpf_version.static_syscalls()) {
+            if let ASTNode::Instruction { instruction: Instruction { opcode: Opcode::Call, operands, .. }, offset } = node {
+                if let Some(Token::Identifier(name, _)) = operands.first() {
+                    if !self.m_label_offsets.contains_key(name) {
+                        self.m_prog_is_static = false;
+                        self.m_rel_dyns.add_rel_dyn(*offset, RelocationType::RSbfSyscall, name.clone());
+                        self.m_dynamic_symbols.add_call_target(name.clone(), *offset);
+                    }
+                }
+            }
+        }
+
         // Second pass to resolve labels
         for node in &mut nodes {
-            match node {
-                ASTNode::Instruction { instruction: Instruction { opcode, operands, line_number }, offset } => {
-                    // For jump instructions, replace label operands with relative offsets
-                    if *opcode == Opcode::Ja || *opcode == Opcode::JeqImm || *opcode == Opcode::JgtImm || *opcode == Opcode::JgeImm 
-                    || *opcode == Opcode::JltImm || *opcode == Opcode::JleImm || *opcode == Opcode::JsetImm || *opcode == Opcode::JneImm     
-                    || *opcode == Opcode::JsgtImm || *opcode == Opcode::JsgeImm || *opcode == Opcode::JsltImm || *opcode == Opcode::JsleImm
-                    || *opcode == Opcode::JeqReg || *opcode == Opcode::JgtReg || *opcode == Opcode::JgeReg || *opcode == Opcode::JltReg 
-                    || *opcode == Opcode::JleReg || *opcode == Opcode::JsetReg || *opcode == Opcode::JneReg || *opcode == Opcode::JsgtReg 
-                    || *opcode == Opcode::JsgeReg || *opcode == Opcode::JsltReg || *opcode == Opcode::JsleReg {
-                        if let Some(Token::Identifier(label, _)) = operands.last() {
-                            let label = label.clone(); // Clone early to avoid borrow conflict
-                            if let Some(target_offset) = self.m_label_offsets.get(&label) {
-                                let rel_offset = (*target_offset as i64 - *offset as i64) / 8 - 1;
-                                // Replace label with immediate value
-                                let last_idx = operands.len() - 1;
-                                operands[last_idx] = Token::ImmediateValue(ImmediateValue::Int(rel_offset), 0);
-                            }
+            if let ASTNode::Instruction { instruction: Instruction { opcode, operands, .. }, offset } = node {
+                // For jump instructions, replace label operands with relative offsets
+                if *opcode == Opcode::Ja || *opcode == Opcode::JeqImm || *opcode == Opcode::JgtImm || *opcode == Opcode::JgeImm 
+                || *opcode == Opcode::JltImm || *opcode == Opcode::JleImm || *opcode == Opcode::JsetImm || *opcode == Opcode::JneImm     
+                || *opcode == Opcode::JsgtImm || *opcode == Opcode::JsgeImm || *opcode == Opcode::JsltImm || *opcode == Opcode::JsleImm
+                || *opcode == Opcode::JeqReg || *opcode == Opcode::JgtReg || *opcode == Opcode::JgeReg || *opcode == Opcode::JltReg 
+                || *opcode == Opcode::JleReg || *opcode == Opcode::JsetReg || *opcode == Opcode::JneReg || *opcode == Opcode::JsgtReg 
+                || *opcode == Opcode::JsgeReg || *opcode == Opcode::JsltReg || *opcode == Opcode::JsleReg {
+                    if let Some(Token::Identifier(label, _)) = operands.last() {
+                        let label = label.clone(); // Clone early to avoid borrow conflict
+                        if let Some(target_offset) = self.m_label_offsets.get(&label) {
+                            let rel_offset = (*target_offset as i64 - *offset as i64) / 8 - 1;
+                            // Replace label with immediate value
+                            let last_idx = operands.len() - 1;
+                            operands[last_idx] = Token::ImmediateValue(ImmediateValue::Int(rel_offset), 0);
                         }
                     }
-                    if *opcode == Opcode::Lddw {
-                        if let Some(Token::Identifier(name, _)) = operands.last() {
-                            let label = name.clone();
-                            if let Some(target_offset) = self.m_label_offsets.get(&label) {
-                                let ph_count = if self.m_prog_is_static { 1 } else { 3 };
-                                let ph_offset = 64 + (ph_count as u64 * 56) as i64;
-                                let abs_offset = *target_offset as i64 + ph_offset;
-                                // Replace label with immediate value
-                                let last_idx = operands.len() - 1;
-                                operands[last_idx] = Token::ImmediateValue(ImmediateValue::Addr(abs_offset), 0);
-                            }
+                }
+                if *opcode == Opcode::Call {
+                    if let Some(Token::Identifier(label, _)) = operands.first() {
+                        if let Some(target_offset) = self.m_label_offsets.get(label) {
+                            // pc-relative internal call, same encoding as a jump offset
+                            let rel_offset = (*target_offset as i64 - *offset as i64) / 8 - 1;
+                            operands[0] = Token::ImmediateValue(ImmediateValue::Int(rel_offset), 0);
+                        }
+                    }
+                }
+                if *opcode == Opcode::Lddw {
+                    if let Some(Token::Identifier(name, _)) = operands.last() {
+                        let label = name.clone();
+                        if let Some(target_offset) = self.m_label_offsets.get(&label) {
+                            let ph_count = if self.m_prog_is_static { 1 } else { 3 };
+                            let ph_offset = 64 + (ph_count as u64 * 56) as i64;
+                            let abs_offset = *target_offset as i64 + ph_offset;
+                            // Replace label with immediate value
+                            let last_idx = operands.len() - 1;
+                            operands[last_idx] = Token::ImmediateValue(ImmediateValue::Addr(abs_offset), 0);
                         }
                     }
                 }
-                _ => {}
             }
         }
 
@@ -638,6 +733,7 @@ impl Parser {
             dynamic_symbols: DynamicSymbolMap::copy(&self.m_dynamic_symbols),
             relocation_data: RelDynMap::copy(&self.m_rel_dyns),
             prog_is_static: self.m_prog_is_static,
+            sbpf_version: self.m_sbpf_version,
         })
     }
 }
\ No newline at end of file
@@ -24,11 +24,16 @@ impl Program {
             dynamic_symbols,
             relocation_data,
             prog_is_static: is_static,
+            sbpf_version,
         }: ParseResult,
     ) -> Self {
         let mut elf_header = ElfHeader::new();
-       
-        let ph_count = if is_static { 1 } else { 3 };
+        elf_header.e_flags = sbpf_version.e_flags();
+
+        // the strict layout keeps read-only data out of the executable segment,
+        // it is not the runtime's v3 layout, see SbpfVersion::strict_elf
+        let strict = sbpf_version.strict_elf();
+        let ph_count = if !is_static { 3 } else if strict && data_section.size() > 0 { 2 } else { 1 };
         elf_header.e_phnum = ph_count;
         
         // Calculate base offset after ELF header and program headers
@@ -36,10 +41,11 @@ impl Program {
         elf_header.e_entry = current_offset;
 
         // Create program headers vector starting with the Read+Execute header
+        let executable_size = if strict { code_section.size() } else { code_section.size() + data_section.size() };
         let mut program_headers = vec![
             ProgramHeader::new_load(
                 elf_header.e_entry,
-                code_section.size() + data_section.size(),
+                executable_size,
                 true,   // executable
             )
         ];
@@ -61,6 +67,9 @@ impl Program {
         if data_section.size() > 0 {
             let mut rodata_section = SectionType::Data(data_section);
             rodata_section.set_offset(current_offset);
+            if strict {
+                program_headers.push(ProgramHeader::new_load(current_offset, rodata_section.size(), false));
+            }
             current_offset += rodata_section.size();
             section_names.push(rodata_section.name().to_string());
             sections.push(rodata_section);
@@ -121,7 +130,14 @@ impl Program {
             current_offset += rel_dyn_section.size();
             section_names.push(rel_dyn_section.name().to_string());
 
+            // .dynamic, .dynsym and .dynstr follow the sections pushed so far
+            let dynsym_index = sections.len() as u32 + 1;
+            let dynstr_index = dynsym_index + 1;
+            dynsym_section.set_link(dynstr_index);
+            rel_dyn_section.set_link(dynsym_index);
+
             if let SectionType::Dynamic(ref mut dynamic_section) = dynamic_section {
+                dynamic_section.set_link(dynstr_index);
                 dynamic_section.set_rel_offset(rel_dyn_section.offset());
                 dynamic_section.set_rel_size(rel_dyn_section.size());
                 dynamic_section.set_dynsym_offset(dynsym_section.offset());
@@ -195,9 +211,18 @@ impl Program {
             bytes.extend(section.bytecode());
         }
 
-        // Emit section headers
+        // Emit section headers, sh_name depends on which sections exist so
+        // it is taken from the string table
+        let shstrtab = self.sections.iter().find_map(|section| match section {
+            SectionType::ShStrTab(shstrtab) => Some(shstrtab),
+            _ => None,
+        });
         for section in &self.sections {
-            bytes.extend(section.section_header_bytecode());
+            let mut header = section.section_header_bytecode();
+            if let Some(name_offset) = shstrtab.and_then(|shstrtab| shstrtab.name_offset(section.name())) {
+                header[..4].copy_from_slice(&name_offset.to_le_bytes());
+            }
+            bytes.extend(header);
         }
 
         bytes
@@ -233,6 +258,15 @@ impl Program {
             panic!("Code section not found");
         }
     }
+
+    pub fn get_label_map(&self) -> HashMap<String, u64> {
+        let code = self.sections.iter().find(|s| s.name() == ".text").unwrap();
+        if let SectionType::Code(code_section) = code {
+            code_section.get_label_map().clone()
+        } else {
+            panic!("Code section not found");
+        }
+    }
     
     pub fn save_to_file(&self, input_path: &str) -> std::io::Result<()> {
         // Get the file stem (name without extension) from input path
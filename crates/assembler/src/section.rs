@@ -31,17 +31,30 @@ pub struct CodeSection {
     offset: u64,
     line_map: HashMap<u64, usize>,
     debug_map: HashMap<u64, DebugInfo>,
+    // offset of the instruction each label points at
+    label_map: HashMap<String, u64>,
 }
 
 impl CodeSection {
     pub fn new(nodes: Vec<ASTNode>, size: u64) -> Self {
-        let mut line_map = HashMap::new();
+        let line_map = HashMap::new();
         let mut debug_map = HashMap::new();
+        let mut label_map = HashMap::new();
+        let mut pending_labels = Vec::new();
         for node in &nodes {
             if let Some((_, node_debug_map)) = node.bytecode_with_debug_map() {
                 debug_map.extend(node_debug_map);
             }
+            match node {
+                ASTNode::Label(label) => pending_labels.push(label.name.clone()),
+                ASTNode::Instruction { offset, .. } => {
+                    label_map.extend(pending_labels.drain(..).map(|name| (name, *offset)));
+                }
+                _ => {}
+            }
         }
+        // labels at the very end point past the last instruction
+        label_map.extend(pending_labels.into_iter().map(|name| (name, size)));
         Self {
             name: String::from(".text"),
             nodes,
@@ -49,6 +62,7 @@ impl CodeSection {
             offset: 0,
             line_map,
             debug_map,
+            label_map,
         }
     }
 
@@ -72,6 +86,10 @@ impl CodeSection {
         &self.debug_map
     }
 
+    pub fn get_label_map(&self) -> &HashMap<String, u64> {
+        &self.label_map
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -178,9 +196,9 @@ impl DataSection {
     pub fn rodata(&self) -> Vec<(String, usize, String)> {
         let mut ro_data_labels = Vec::new();
         for node in &self.nodes {    
-            if let ASTNode::ROData { rodata: ROData { name, args, line_number }, offset } = node {
+            if let ASTNode::ROData { rodata: ROData { name, args, .. }, offset } = node {
                 if let Some(Token::StringLiteral(str_literal, _)) = args.get(1) {
-                    ro_data_labels.push((name.clone(), offset.clone() as usize, str_literal.clone()));
+                    ro_data_labels.push((name.clone(), *offset as usize, str_literal.clone()));
                 }
             }
         }
@@ -209,8 +227,9 @@ impl Section for DataSection {
         &self.name
     }
 
+    // bytecode() pads the section to a multiple of 8
     fn size(&self) -> u64 {
-        self.size
+        self.size.div_ceil(8) * 8
     }
 
     fn bytecode(&self) -> Vec<u8> {
@@ -235,6 +254,12 @@ pub struct NullSection {
     offset: u64,
 }
 
+impl Default for NullSection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NullSection {
     pub fn new() -> Self {
         Self {
@@ -309,6 +334,18 @@ impl ShStrTabSection {
     pub fn offset(&self) -> u64 {
         self.offset
     }
+
+    // Offset of `name` in the table, the sh_name of its section header
+    pub fn name_offset(&self, name: &str) -> Option<u32> {
+        let mut offset = 1;
+        for section_name in self.section_names.iter().filter(|section_name| !section_name.is_empty()) {
+            if section_name == name {
+                return Some(offset);
+            }
+            offset += section_name.len() as u32 + 1;
+        }
+        None
+    }
 }
 
 impl Section for ShStrTabSection {
@@ -362,6 +399,14 @@ pub struct DynamicSection {
     dynsym_offset: u64,
     dynstr_offset: u64,
     dynstr_size: u64,
+    // section index of .dynstr
+    link: u32,
+}
+
+impl Default for DynamicSection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DynamicSection {
@@ -374,9 +419,14 @@ impl DynamicSection {
             dynsym_offset: 0,
             dynstr_offset: 0,
             dynstr_size: 0,
+            link: 0,
         }
     }
 
+    pub fn set_link(&mut self, link: u32) {
+        self.link = link;
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -409,7 +459,7 @@ impl DynamicSection {
             self.offset,
             self.offset,
             self.size(),
-            5,
+            self.link,
             0,
             8,
             16
@@ -446,7 +496,7 @@ impl Section for DynamicSection {
         bytes.extend_from_slice(&0x10_u64.to_le_bytes());  // Constant: 16 bytes per entry
         
         // DT_RELCOUNT
-        bytes.extend_from_slice(&0x6fffff_fa_u64.to_le_bytes());
+        bytes.extend_from_slice(&0x6fff_fffa_u64.to_le_bytes());
         bytes.extend_from_slice(&0x01_u64.to_le_bytes());  // Constant: 1 relocation
         
         // DT_SYMTAB
@@ -562,6 +612,8 @@ pub struct DynSymSection {
     name: String,
     offset: u64,
     symbols: Vec<DynamicSymbol>,
+    // section index of .dynstr
+    link: u32,
 }
 
 impl DynSymSection {
@@ -570,9 +622,14 @@ impl DynSymSection {
             name: String::from(".dynsym"),
             offset: 0,
             symbols,
+            link: 0,
         }
     }
 
+    pub fn set_link(&mut self, link: u32) {
+        self.link = link;
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -586,7 +643,7 @@ impl DynSymSection {
             self.offset,
             self.offset,
             self.size(),
-            5,
+            self.link,
             1,
             8,
             24
@@ -623,6 +680,8 @@ pub struct RelDynSection {
     name: String,
     offset: u64,
     entries: Vec<RelDyn>,
+    // section index of .dynsym
+    link: u32,
 }
 
 impl RelDynSection {
@@ -631,9 +690,14 @@ impl RelDynSection {
             name: String::from(".rel.dyn"),
             offset: 0,
             entries,
+            link: 0,
         }
     }
 
+    pub fn set_link(&mut self, link: u32) {
+        self.link = link;
+    }
+
     pub fn set_offset(&mut self, offset: u64) {
         self.offset = offset;
     }
@@ -651,7 +715,7 @@ impl RelDynSection {
             self.offset,
             self.offset,
             self.size(),
-            4,
+            self.link,
             0,
             8,
             16
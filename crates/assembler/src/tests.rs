@@ -0,0 +1,86 @@
+use crate::astnode::ASTNode;
+use crate::lexer::{ImmediateValue, Token};
+use crate::parser::ParseResult;
+use crate::utils::hash_symbol_name;
+use crate::{tokenize, Parser};
+
+fn parse(source: &str) -> ParseResult {
+    let tokens = tokenize(source).expect("tokenize");
+    Parser::new(tokens).parse().expect("parse")
+}
+
+// Encoded instructions of the code section, in order
+fn instructions(source: &str) -> Vec<Vec<u8>> {
+    parse(source).code_section.get_nodes().iter()
+        .filter(|node| matches!(node, ASTNode::Instruction { .. }))
+        .filter_map(ASTNode::bytecode)
+        .collect()
+}
+
+#[test]
+fn test_tokenize_skips_comments_and_reads_hex() {
+    let tokens = tokenize("mov64 r1, 0x1f // comment\n# whole line\n").unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert!(matches!(tokens[1], Token::Register(1, 1)));
+    assert!(matches!(tokens[3], Token::ImmediateValue(ImmediateValue::Addr(0x1f), 1)));
+}
+
+#[test]
+fn test_alu_selects_imm_or_reg_encoding() {
+    let code = instructions("mov64 r0, 5\nmov64 r1, r0\nexit\n");
+    assert_eq!(code[0], [0xb7, 0x00, 0, 0, 5, 0, 0, 0]);
+    assert_eq!(code[1], [0xbf, 0x01, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(code[2], [0x95, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_equ_constants_are_folded() {
+    let code = instructions(".equ N, 4\nmov64 r0, N + 3\nmov64 r1, r0\nexit\n");
+    assert_eq!(code[0][4..8], 7u32.to_le_bytes());
+}
+
+#[test]
+fn test_jump_label_becomes_relative_offset() {
+    let code = instructions("ja end\nmov64 r1, r0\nmov64 r2, r0\nend:\nexit\n");
+    assert_eq!(code[0][0], 0x05);
+    assert_eq!(i16::from_le_bytes([code[0][2], code[0][3]]), 2);
+}
+
+fn parse_fails(source: &str) -> bool {
+    let tokens = tokenize(source).expect("tokenize");
+    Parser::new(tokens).parse().is_err()
+}
+
+#[test]
+fn test_store_immediate_goes_in_bytes_4_to_8() {
+    let code = instructions("stw [r1+8], 5\nexit\n");
+    assert_eq!(code[0], [0x62, 0x01, 8, 0, 5, 0, 0, 0]);
+}
+
+#[test]
+fn test_store_register_goes_in_high_nibble() {
+    let code = instructions("stxdw [r10-8], r2\nexit\n");
+    assert_eq!(code[0], [0x7b, 0x2a, 0xf8, 0xff, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_store_operand_kind_must_match_opcode() {
+    assert!(parse_fails("stw [r1+0], r2\nexit\n"));
+    assert!(parse_fails("stxw [r1+0], 5\nexit\n"));
+}
+
+#[test]
+fn test_store_offsets_and_values_are_folded() {
+    let code = instructions(".equ OFF, 16\n.equ V, 2\nsth [r1-OFF+4], V + 1\nstxb [r1+OFF], r3\nexit\n");
+    assert_eq!(code[0], [0x6a, 0x01, 0xf4, 0xff, 3, 0, 0, 0]);
+    assert_eq!(code[1], [0x73, 0x31, 16, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_call_to_local_label_is_pc_relative() {
+    let code = instructions("call f\nmov64 r1, r0\nf:\nexit\ncall sol_log_\nexit\n");
+    assert_eq!(code[0], [0x85, 0x10, 0, 0, 1, 0, 0, 0]);
+    // unknown targets are syscalls, called by the hash of their name
+    let hash = hash_symbol_name("sol_log_").to_le_bytes();
+    assert_eq!(code[3], [0x85, 0, 0, 0, hash[0], hash[1], hash[2], hash[3]]);
+}
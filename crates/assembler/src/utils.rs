@@ -1,5 +1,10 @@
 use std::str::FromStr;
 
+// Syscalls are identified by the murmur3-32 hash of their name
+pub fn hash_symbol_name(name: &str) -> u32 {
+    murmur3::murmur3_32(&mut name.as_bytes(), 0).unwrap()
+}
+
 pub fn evaluate_constant_expression(expr: &str) -> Result<String, String> {
 
     let mut tokens = Vec::new(); // let mut tokens = vec![];
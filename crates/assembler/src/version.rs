@@ -0,0 +1,137 @@
+use crate::opcode::Opcode;
+use std::fmt;
+
+// SBPF versions, each enabled on a cluster by its own feature gate. The
+// ELF e_flags field holds the version number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum SbpfVersion {
+    #[default]
+    V0,
+    // dynamic stack frames
+    V1,
+    // drops lddw, le and neg, moves mul/div/mod to the PQR class, adds hor64,
+    // moves loads and stores into the freed opcodes, reads the callx register
+    // from src and swaps the operands of sub with an immediate
+    V2,
+    // static syscalls and a strict ELF layout without relocations
+    V3,
+}
+
+impl SbpfVersion {
+    pub const ALL: [SbpfVersion; 4] = [SbpfVersion::V0, SbpfVersion::V1, SbpfVersion::V2, SbpfVersion::V3];
+
+    pub fn from_e_flags(e_flags: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|version| version.e_flags() == e_flags)
+    }
+
+    pub fn e_flags(self) -> u32 {
+        self as u32
+    }
+
+    pub fn enable_lddw(self) -> bool {
+        self < SbpfVersion::V2
+    }
+
+    pub fn enable_le(self) -> bool {
+        self < SbpfVersion::V2
+    }
+
+    pub fn enable_neg(self) -> bool {
+        self < SbpfVersion::V2
+    }
+
+    // lmul, uhmul, shmul, udiv, urem, sdiv and srem replace mul, div and mod
+    pub fn enable_pqr(self) -> bool {
+        self >= SbpfVersion::V2
+    }
+
+    pub fn enable_hor64(self) -> bool {
+        self >= SbpfVersion::V2
+    }
+
+    // r10 is adjusted with `add64 r10, imm` instead of a fixed frame per call
+    pub fn dynamic_stack_frames(self) -> bool {
+        self >= SbpfVersion::V1
+    }
+
+    // calls to syscalls carry the hash of the name without a relocation
+    pub fn static_syscalls(self) -> bool {
+        self >= SbpfVersion::V3
+    }
+
+    // loads and stores use the opcodes of mul, div, neg and mod instead
+    pub fn move_memory_instruction_classes(self) -> bool {
+        self >= SbpfVersion::V2
+    }
+
+    // callx names its register in the src field instead of the immediate
+    pub fn callx_uses_src_reg(self) -> bool {
+        self >= SbpfVersion::V2
+    }
+
+    // `sub dst, imm` computes imm - dst
+    pub fn swap_sub_reg_imm_operands(self) -> bool {
+        self >= SbpfVersion::V2
+    }
+
+    // This toolchain's strict layout: read-only data in its own segment and
+    // no relocations. It is not the runtime's v3 ELF format, which fixes the
+    // program headers and virtual addresses, so v3 programs built here only
+    // run in this VM.
+    pub fn strict_elf(self) -> bool {
+        self >= SbpfVersion::V3
+    }
+
+    // Whether the instruction exists in this version
+    pub fn supports(self, opcode: Opcode) -> bool {
+        match opcode {
+            Opcode::Lddw => self.enable_lddw(),
+            Opcode::Le => self.enable_le(),
+            Opcode::Neg32 | Opcode::Neg64 => self.enable_neg(),
+            Opcode::Mul32 | Opcode::Mul32Imm | Opcode::Mul32Reg
+            | Opcode::Div32 | Opcode::Div32Imm | Opcode::Div32Reg
+            | Opcode::Mod32 | Opcode::Mod32Imm | Opcode::Mod32Reg
+            | Opcode::Mul64 | Opcode::Mul64Imm | Opcode::Mul64Reg
+            | Opcode::Div64 | Opcode::Div64Imm | Opcode::Div64Reg
+            | Opcode::Mod64 | Opcode::Mod64Imm | Opcode::Mod64Reg => !self.enable_pqr(),
+            Opcode::Lmul32 | Opcode::Lmul32Imm | Opcode::Lmul32Reg
+            | Opcode::Udiv32 | Opcode::Udiv32Imm | Opcode::Udiv32Reg
+            | Opcode::Urem32 | Opcode::Urem32Imm | Opcode::Urem32Reg
+            | Opcode::Sdiv32 | Opcode::Sdiv32Imm | Opcode::Sdiv32Reg
+            | Opcode::Srem32 | Opcode::Srem32Imm | Opcode::Srem32Reg
+            | Opcode::Lmul64 | Opcode::Lmul64Imm | Opcode::Lmul64Reg
+            | Opcode::Uhmul64 | Opcode::Uhmul64Imm | Opcode::Uhmul64Reg
+            | Opcode::Shmul64 | Opcode::Shmul64Imm | Opcode::Shmul64Reg
+            | Opcode::Udiv64 | Opcode::Udiv64Imm | Opcode::Udiv64Reg
+            | Opcode::Urem64 | Opcode::Urem64Imm | Opcode::Urem64Reg
+            | Opcode::Sdiv64 | Opcode::Sdiv64Imm | Opcode::Sdiv64Reg
+            | Opcode::Srem64 | Opcode::Srem64Imm | Opcode::Srem64Reg => self.enable_pqr(),
+            Opcode::Hor64Imm => self.enable_hor64(),
+            // the opcodes of the v0 encoding, see move_memory_instruction_classes
+            Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw
+            | Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw
+            | Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => !self.move_memory_instruction_classes(),
+            _ => true,
+        }
+    }
+}
+
+impl std::str::FromStr for SbpfVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "v0" | "0" => Ok(SbpfVersion::V0),
+            "v1" | "1" => Ok(SbpfVersion::V1),
+            "v2" | "2" => Ok(SbpfVersion::V2),
+            "v3" | "3" => Ok(SbpfVersion::V3),
+            _ => Err(format!("Unsupported SBPF version: {}, expected v0, v1, v2 or v3", s)),
+        }
+    }
+}
+
+impl fmt::Display for SbpfVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", *self as u32)
+    }
+}
@@ -0,0 +1,240 @@
+use crate::error::Location;
+use crate::vm::VMState;
+use crate::watchpoint::WatchChange;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+// Where a breakpoint stops, resolved against the loaded program every time it
+// is checked so that breakpoints survive reloading the program
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum BreakpointLocation {
+    // source line, through the debug map
+    Line(usize),
+    // pc, the byte offset in the ELF
+    Address(usize),
+    Label(String),
+}
+
+impl fmt::Display for BreakpointLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakpointLocation::Line(line) => write!(f, "line {}", line),
+            BreakpointLocation::Address(pc) => write!(f, "pc 0x{:x}", pc),
+            BreakpointLocation::Label(label) => write!(f, "label {}", label),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Comparison {
+    const ALL: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        (">=", Comparison::Ge),
+        ("<=", Comparison::Le),
+        (">", Comparison::Gt),
+        ("<", Comparison::Lt),
+    ];
+
+    fn symbol(self) -> &'static str {
+        Self::ALL.iter().find(|(_, comparison)| *comparison == self).unwrap().0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(usize),
+    Immediate(u64),
+}
+
+impl Operand {
+    fn value(self, vm: &VMState) -> u64 {
+        match self {
+            Operand::Register(register) => vm.registers[register].value,
+            Operand::Immediate(value) => value,
+        }
+    }
+}
+
+// `r3 > 10`, `r1 == r2`, ... compared as unsigned 64-bit values, negative
+// immediates are taken as their two's complement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: usize,
+    pub comparison: Comparison,
+    pub operand: Operand,
+}
+
+impl Condition {
+    pub fn holds(&self, vm: &VMState) -> bool {
+        let left = vm.registers[self.register].value;
+        let right = self.operand.value(vm);
+        match self.comparison {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+        }
+    }
+}
+
+fn parse_register(register: &str) -> Option<usize> {
+    register.strip_prefix('r')?.parse().ok().filter(|&register| register <= 10)
+}
+
+fn parse_immediate(value: &str) -> Option<u64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid condition '{}', expected e.g. 'r3 > 10'", condition);
+        let (symbol, comparison) = Comparison::ALL.iter()
+            .find(|(symbol, _)| condition.contains(symbol))
+            .ok_or_else(invalid)?;
+        let (left, right) = condition.split_once(symbol).ok_or_else(invalid)?;
+        let register = parse_register(left.trim()).ok_or_else(invalid)?;
+        let right = right.trim();
+        let operand = match parse_register(right) {
+            Some(register) => Operand::Register(register),
+            None => Operand::Immediate(parse_immediate(right).ok_or_else(invalid)?),
+        };
+        Ok(Condition { register, comparison: *comparison, operand })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "r{} {} ", self.register, self.comparison.symbol())?;
+        match self.operand {
+            Operand::Register(register) => write!(f, "r{}", register),
+            Operand::Immediate(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Serialize for Condition {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub location: BreakpointLocation,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+    // how many times execution stopped here
+    pub hit_count: u64,
+}
+
+// Why `continue_execution` returned, errors are returned as such
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum StopReason {
+    Breakpoint { id: usize, location: Location },
+    // location of the instruction that triggered it, pc has moved past it
+    Watchpoint { id: usize, location: Location, change: WatchChange },
+    Exit { code: u64 },
+    // stepping back reached the oldest recorded instruction
+    HistoryStart { location: Location },
+}
+
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, location: BreakpointLocation, condition: Option<Condition>) -> usize {
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id: self.next_id, location, condition, enabled: true, hit_count: 0 });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        self.breakpoints.iter_mut()
+            .find(|breakpoint| breakpoint.id == id)
+            .map(|breakpoint| breakpoint.enabled = enabled)
+            .is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    // Counts a hit on every enabled breakpoint at `location` whose condition
+    // holds, returns the first of them. `resolve` maps a breakpoint to its pc.
+    pub fn hit(&mut self, vm: &VMState, location: &Location, resolve: impl Fn(&BreakpointLocation) -> Option<usize>) -> Option<usize> {
+        let mut hit = None;
+        for breakpoint in self.breakpoints.iter_mut().filter(|breakpoint| breakpoint.enabled) {
+            let here = match &breakpoint.location {
+                BreakpointLocation::Line(line) => location.line == Some(*line),
+                other => resolve(other) == Some(location.pc),
+            };
+            if here && breakpoint.condition.is_none_or(|condition| condition.holds(vm)) {
+                breakpoint.hit_count += 1;
+                hit.get_or_insert(breakpoint.id);
+            }
+        }
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_condition() {
+        let condition: Condition = "r3 > 10".parse().unwrap();
+        assert_eq!(condition, Condition { register: 3, comparison: Comparison::Gt, operand: Operand::Immediate(10) });
+        let condition: Condition = "r1>=r2".parse().unwrap();
+        assert_eq!(condition.to_string(), "r1 >= r2");
+        let condition: Condition = "r0 == -1".parse().unwrap();
+        assert_eq!(condition.operand, Operand::Immediate(u64::MAX));
+        assert_eq!("r1 != 0x10".parse::<Condition>().unwrap().operand, Operand::Immediate(16));
+        assert!("r11 > 1".parse::<Condition>().is_err());
+        assert!("r1 = 1".parse::<Condition>().is_err());
+        assert!("r1 > x".parse::<Condition>().is_err());
+    }
+}
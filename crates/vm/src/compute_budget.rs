@@ -0,0 +1,46 @@
+// Compute unit costs, matching the Solana runtime's defaults
+pub const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ComputeBudget {
+    pub compute_unit_limit: u64,
+    // every syscall costs at least this much
+    pub syscall_base_cost: u64,
+    pub log_64_units: u64,
+    pub log_pubkey_units: u64,
+    pub mem_op_base_cost: u64,
+    // bytes copied per compute unit
+    pub cpi_bytes_per_unit: u64,
+    // shared by sha256, keccak256 and blake3
+    pub sha256_base_cost: u64,
+    pub sha256_byte_cost: u64,
+    pub sha256_max_slices: u64,
+    // charged again for every bump seed tried
+    pub create_program_address_units: u64,
+    // plus one unit per byte of the sysvar
+    pub sysvar_base_cost: u64,
+    // charged on every CPI, before the instruction data
+    pub invoke_units: u64,
+    // the top level program counts as the first level
+    pub max_invoke_stack_height: usize,
+}
+
+impl Default for ComputeBudget {
+    fn default() -> Self {
+        Self {
+            compute_unit_limit: DEFAULT_COMPUTE_UNIT_LIMIT,
+            syscall_base_cost: 100,
+            log_64_units: 100,
+            log_pubkey_units: 100,
+            mem_op_base_cost: 10,
+            cpi_bytes_per_unit: 250,
+            sha256_base_cost: 85,
+            sha256_byte_cost: 1,
+            sha256_max_slices: 20_000,
+            create_program_address_units: 1500,
+            sysvar_base_cost: 100,
+            invoke_units: 1000,
+            max_invoke_stack_height: 5,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::serialization::{Account, Pubkey, MAX_PERMITTED_DATA_INCREASE};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AccountChange {
+    pub index: usize,
+    pub key: Pubkey,
+    // (before, after) for each field that changed
+    pub lamports: Option<(u64, u64)>,
+    pub data_len: Option<(usize, usize)>,
+    pub owner: Option<(Pubkey, Pubkey)>,
+    // (offset, before, after), grown bytes start out as zero
+    pub data: Vec<(usize, u8, u8)>,
+}
+
+impl AccountChange {
+    fn is_empty(&self) -> bool {
+        self.lamports.is_none() && self.data_len.is_none() && self.owner.is_none() && self.data.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum AccountViolation {
+    ReadonlyModified { index: usize },
+    LamportsNotConserved { before: u128, after: u128 },
+    DataTooLarge { index: usize, len: usize, max: usize },
+}
+
+impl fmt::Display for AccountViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountViolation::ReadonlyModified { index } => {
+                write!(f, "Account {} was modified but is not writable", index)
+            }
+            AccountViolation::LamportsNotConserved { before, after } => {
+                write!(f, "Lamports not conserved: {} before, {} after", before, after)
+            }
+            AccountViolation::DataTooLarge { index, len, max } => {
+                write!(f, "Account {} data grew to {} bytes, max is {}", index, len, max)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AccountsDiff {
+    // only accounts with at least one change
+    pub changes: Vec<AccountChange>,
+    pub violations: Vec<AccountViolation>,
+}
+
+// Compare accounts before and after execution. Duplicates are skipped since
+// they alias the account at their first position.
+pub fn diff_accounts(pre: &[Account], post: &[Account]) -> AccountsDiff {
+    let mut diff = AccountsDiff::default();
+    let mut lamports_before: u128 = 0;
+    let mut lamports_after: u128 = 0;
+
+    for (index, (before, after)) in pre.iter().zip(post).enumerate() {
+        if pre[..index].iter().any(|other| other.key == before.key) {
+            continue;
+        }
+        lamports_before += before.lamports as u128;
+        lamports_after += after.lamports as u128;
+
+        let mut change = AccountChange { index, key: before.key, ..AccountChange::default() };
+        if before.lamports != after.lamports {
+            change.lamports = Some((before.lamports, after.lamports));
+        }
+        if before.data.len() != after.data.len() {
+            change.data_len = Some((before.data.len(), after.data.len()));
+        }
+        if before.owner != after.owner {
+            change.owner = Some((before.owner, after.owner));
+        }
+        for (offset, &new) in after.data.iter().enumerate() {
+            let old = before.data.get(offset).copied().unwrap_or(0);
+            if old != new {
+                change.data.push((offset, old, new));
+            }
+        }
+
+        let max = before.data.len() + MAX_PERMITTED_DATA_INCREASE;
+        if after.data.len() > max {
+            diff.violations.push(AccountViolation::DataTooLarge { index, len: after.data.len(), max });
+        }
+        if change.is_empty() {
+            continue;
+        }
+        if !before.is_writable {
+            diff.violations.push(AccountViolation::ReadonlyModified { index });
+        }
+        diff.changes.push(change);
+    }
+
+    if lamports_before != lamports_after {
+        diff.violations.push(AccountViolation::LamportsNotConserved { before: lamports_before, after: lamports_after });
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_flags_invalid_changes() {
+        let pre = vec![
+            Account { key: [1u8; 32], lamports: 10, data: vec![1, 2], is_writable: true, ..Account::default() },
+            Account { key: [2u8; 32], lamports: 5, ..Account::default() },
+        ];
+        let mut post = pre.clone();
+        post[0].lamports = 8;
+        post[0].data = vec![1, 3, 4];
+        post[1].lamports = 6;
+
+        let diff = diff_accounts(&pre, &post);
+        assert_eq!(diff.changes.len(), 2);
+        assert_eq!(diff.changes[0].lamports, Some((10, 8)));
+        assert_eq!(diff.changes[0].data_len, Some((2, 3)));
+        assert_eq!(diff.changes[0].data, vec![(1, 2, 3), (2, 0, 4)]);
+        assert_eq!(diff.violations, vec![
+            AccountViolation::ReadonlyModified { index: 1 },
+            AccountViolation::LamportsNotConserved { before: 15, after: 14 },
+        ]);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::instruction::moved_memory_opcode;
+use crate::syscalls::SyscallRegistry;
+use sbpf_assembler::opcode::Opcode;
+use sbpf_assembler::SbpfVersion;
+
+// eBPF instruction classes, the low 3 bits of the opcode
+const CLASS_JMP: u8 = 0x05;
+// source operand is a register
+const SOURCE_REG: u8 = 0x08;
+
+fn signed_offset(offset: i16) -> String {
+    if offset < 0 {
+        format!("-{:#x}", -(offset as i32))
+    } else {
+        format!("+{:#x}", offset)
+    }
+}
+
+// Disassembles the instruction at the start of `bytes` the way the
+// solana-sbpf disassembler does. `slot` is its index in the text section,
+// jump targets are shown as `lbb_<slot>`.
+pub fn disassemble(bytes: &[u8], slot: usize, syscalls: &SyscallRegistry, version: SbpfVersion) -> String {
+    let opcode = bytes.first().and_then(|&byte| {
+        version.move_memory_instruction_classes().then(|| moved_memory_opcode(byte)).flatten().or_else(|| Opcode::from_u8(byte))
+    });
+    let Some(opcode) = opcode.filter(|_| bytes.len() >= 8) else {
+        return "[invalid]".to_string();
+    };
+    let dst = bytes[1] & 0x0F;
+    let src = bytes[1] >> 4;
+    let offset = i16::from_le_bytes([bytes[2], bytes[3]]);
+    let imm = i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let target = |relative: i64| slot as i64 + relative + 1;
+    let name = opcode.to_str();
+
+    match opcode {
+        Opcode::Lddw => match bytes.get(12..16) {
+            Some(high) => {
+                let high = u32::from_le_bytes(high.try_into().unwrap()) as u64;
+                format!("lddw r{}, {:#x}", dst, (high << 32) | imm as u32 as u64)
+            }
+            None => "[invalid]".to_string(),
+        },
+        Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw => {
+            format!("{} r{}, [r{}{}]", name, dst, src, signed_offset(offset))
+        }
+        Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw => {
+            format!("{} [r{}{}], {}", name, dst, signed_offset(offset), imm)
+        }
+        Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => {
+            format!("{} [r{}{}], r{}", name, dst, signed_offset(offset), src)
+        }
+        Opcode::Neg32 | Opcode::Neg64 => format!("{} r{}", name, dst),
+        Opcode::Le | Opcode::Be => format!("{}{} r{}", name, imm, dst),
+        Opcode::Ja => format!("ja lbb_{}", target(offset as i64)),
+        Opcode::Call if src == 0 => match syscalls.name(imm as u32) {
+            Some(syscall) => format!("syscall {}", syscall),
+            None => format!("syscall {:#x}", imm as u32),
+        },
+        Opcode::Call => format!("call function_{}", target(imm as i64)),
+        Opcode::Callx if version.callx_uses_src_reg() => format!("callx r{}", src),
+        Opcode::Callx => format!("callx r{}", imm),
+        Opcode::Exit => "exit".to_string(),
+        _ if bytes[0] & 0x07 == CLASS_JMP => {
+            let operand = if bytes[0] & SOURCE_REG != 0 { format!("r{}", src) } else { imm.to_string() };
+            format!("{} r{}, {}, lbb_{}", name, dst, operand, target(offset as i64))
+        }
+        _ if bytes[0] & SOURCE_REG != 0 => format!("{} r{}, r{}", name, dst, src),
+        _ => format!("{} r{}, {}", name, dst, imm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        let syscalls = SyscallRegistry::default();
+        let disassemble = |bytes: &[u8]| disassemble(bytes, 10, &syscalls, SbpfVersion::V0);
+        assert_eq!(disassemble(&[0xb7, 0x03, 0, 0, 0xff, 0xff, 0xff, 0xff]), "mov64 r3, -1");
+        assert_eq!(disassemble(&[0x0f, 0x21, 0, 0, 0, 0, 0, 0]), "add64 r1, r2");
+        assert_eq!(disassemble(&[0x79, 0x21, 0xf8, 0xff, 0, 0, 0, 0]), "ldxdw r1, [r2-0x8]");
+        assert_eq!(disassemble(&[0x7b, 0x1a, 0x10, 0, 0, 0, 0, 0]), "stxdw [r10+0x10], r1");
+        assert_eq!(disassemble(&[0xa5, 0x03, 0xfc, 0xff, 5, 0, 0, 0]), "jlt r3, 5, lbb_7");
+        assert_eq!(disassemble(&[0x85, 0, 0, 0, 0xbd, 0x59, 0x75, 0x20]), "syscall sol_log_");
+        assert_eq!(disassemble(&[0x18, 0x01, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]), "lddw r1, 0x100000010");
+        assert_eq!(disassemble(&[0x95, 0, 0, 0, 0, 0, 0, 0]), "exit");
+
+        let v2 = |bytes: &[u8]| super::disassemble(bytes, 10, &syscalls, SbpfVersion::V2);
+        assert_eq!(v2(&[0x9c, 0x21, 0xf8, 0xff, 0, 0, 0, 0]), "ldxdw r1, [r2-0x8]");
+        assert_eq!(v2(&[0x9f, 0x1a, 0x10, 0, 0, 0, 0, 0]), "stxdw [r10+0x10], r1");
+        assert_eq!(v2(&[0x8d, 0x30, 0, 0, 0, 0, 0, 0]), "callx r3");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::memory::AccessViolation;
+use serde::Serialize;
+use std::fmt;
+
+// Where an error happened: `pc` is the byte offset in the ELF, `offset` the
+// offset from the start of .text that `debug_map` is keyed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub pc: usize,
+    pub offset: u64,
+    pub line: Option<usize>,
+}
+
+// Errors are raised without a location, the run loop attaches the one of the
+// instruction that was executing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    InvalidOpcode { opcode: u8, location: Option<Location> },
+    // truncated instruction, bad register or operand
+    InvalidInstruction { message: String, location: Option<Location> },
+    AccessViolation { violation: AccessViolation, location: Option<Location> },
+    DivideByZero { location: Option<Location> },
+    DivideOverflow { location: Option<Location> },
+    CallDepthExceeded { max: usize, location: Option<Location> },
+    InvalidCallTarget { target: u64, location: Option<Location> },
+    BudgetExceeded { limit: u64, location: Option<Location> },
+    UnsupportedSyscall { hash: u32, location: Option<Location> },
+    // raised by a syscall, e.g. sol_panic_ or a failed CPI
+    SyscallError { message: String, location: Option<Location> },
+    // a CPI callee returned a non-zero exit code
+    ProgramError { code: u64, location: Option<Location> },
+    ElfError { message: String, location: Option<Location> },
+    AssemblyError { message: String, location: Option<Location> },
+    // a bad value handed in by the frontend, e.g. a program id or fixture
+    InvalidArgument { message: String, location: Option<Location> },
+}
+
+impl VmError {
+    pub fn invalid_instruction(message: impl Into<String>) -> Self {
+        VmError::InvalidInstruction { message: message.into(), location: None }
+    }
+
+    pub fn syscall(message: impl Into<String>) -> Self {
+        VmError::SyscallError { message: message.into(), location: None }
+    }
+
+    pub fn elf(message: impl Into<String>) -> Self {
+        VmError::ElfError { message: message.into(), location: None }
+    }
+
+    pub fn assembly(message: impl Into<String>) -> Self {
+        VmError::AssemblyError { message: message.into(), location: None }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        VmError::InvalidArgument { message: message.into(), location: None }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            VmError::InvalidOpcode { .. } => "InvalidOpcode",
+            VmError::InvalidInstruction { .. } => "InvalidInstruction",
+            VmError::AccessViolation { .. } => "AccessViolation",
+            VmError::DivideByZero { .. } => "DivideByZero",
+            VmError::DivideOverflow { .. } => "DivideOverflow",
+            VmError::CallDepthExceeded { .. } => "CallDepthExceeded",
+            VmError::InvalidCallTarget { .. } => "InvalidCallTarget",
+            VmError::BudgetExceeded { .. } => "BudgetExceeded",
+            VmError::UnsupportedSyscall { .. } => "UnsupportedSyscall",
+            VmError::SyscallError { .. } => "SyscallError",
+            VmError::ProgramError { .. } => "ProgramError",
+            VmError::ElfError { .. } => "ElfError",
+            VmError::AssemblyError { .. } => "AssemblyError",
+            VmError::InvalidArgument { .. } => "InvalidArgument",
+        }
+    }
+
+    fn location_mut(&mut self) -> &mut Option<Location> {
+        match self {
+            VmError::InvalidOpcode { location, .. }
+            | VmError::InvalidInstruction { location, .. }
+            | VmError::AccessViolation { location, .. }
+            | VmError::DivideByZero { location }
+            | VmError::DivideOverflow { location }
+            | VmError::CallDepthExceeded { location, .. }
+            | VmError::InvalidCallTarget { location, .. }
+            | VmError::BudgetExceeded { location, .. }
+            | VmError::UnsupportedSyscall { location, .. }
+            | VmError::SyscallError { location, .. }
+            | VmError::ProgramError { location, .. }
+            | VmError::ElfError { location, .. }
+            | VmError::AssemblyError { location, .. }
+            | VmError::InvalidArgument { location, .. } => location,
+        }
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            VmError::InvalidOpcode { location, .. }
+            | VmError::InvalidInstruction { location, .. }
+            | VmError::AccessViolation { location, .. }
+            | VmError::DivideByZero { location }
+            | VmError::DivideOverflow { location }
+            | VmError::CallDepthExceeded { location, .. }
+            | VmError::InvalidCallTarget { location, .. }
+            | VmError::BudgetExceeded { location, .. }
+            | VmError::UnsupportedSyscall { location, .. }
+            | VmError::SyscallError { location, .. }
+            | VmError::ProgramError { location, .. }
+            | VmError::ElfError { location, .. }
+            | VmError::AssemblyError { location, .. }
+            | VmError::InvalidArgument { location, .. } => *location,
+        }
+    }
+
+    // Keeps the location if there already is one
+    pub fn at(mut self, location: Location) -> Self {
+        self.location_mut().get_or_insert(location);
+        self
+    }
+
+    // Drops the location, e.g. of an error raised inside a CPI callee
+    pub fn without_location(mut self) -> Self {
+        *self.location_mut() = None;
+        self
+    }
+
+    // The error without the location
+    pub fn message(&self) -> String {
+        match self {
+            VmError::InvalidOpcode { opcode, .. } => format!("Invalid opcode: 0x{:02x}", opcode),
+            VmError::InvalidInstruction { message, .. }
+            | VmError::SyscallError { message, .. }
+            | VmError::ElfError { message, .. }
+            | VmError::AssemblyError { message, .. }
+            | VmError::InvalidArgument { message, .. } => message.clone(),
+            VmError::AccessViolation { violation, .. } => violation.to_string(),
+            VmError::DivideByZero { .. } => "Division by zero".to_string(),
+            VmError::DivideOverflow { .. } => "Division overflow".to_string(),
+            VmError::CallDepthExceeded { max, .. } => format!("Exceeded max call depth of {}", max),
+            VmError::InvalidCallTarget { target, .. } => format!("Invalid call target: 0x{:x}", target),
+            VmError::BudgetExceeded { .. } => "ComputationalBudgetExceeded".to_string(),
+            VmError::UnsupportedSyscall { hash, .. } => format!("Unsupported syscall: 0x{:08x}", hash),
+            VmError::ProgramError { code, .. } => format!("custom program error: {:#x}", code),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())?;
+        match self.location() {
+            Some(Location { pc, line: Some(line), .. }) => write!(f, " (line {}, pc 0x{:x})", line, pc),
+            Some(Location { pc, .. }) => write!(f, " (pc 0x{:x})", pc),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<AccessViolation> for VmError {
+    fn from(violation: AccessViolation) -> Self {
+        VmError::AccessViolation { violation, location: None }
+    }
+}
+
+// Shape of the error handed to JavaScript
+#[derive(Serialize)]
+struct JsVmError {
+    kind: &'static str,
+    message: String,
+    location: Option<Location>,
+}
+
+impl From<VmError> for wasm_bindgen::JsValue {
+    fn from(err: VmError) -> Self {
+        let js_err = JsVmError { kind: err.kind(), message: err.message(), location: err.location() };
+        serde_wasm_bindgen::to_value(&js_err).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location_is_attached_once() {
+        let callee = Location { pc: 0x100, offset: 8, line: Some(3) };
+        let caller = Location { pc: 0x88, offset: 16, line: None };
+        let err = VmError::DivideByZero { location: None }.at(callee).at(caller);
+        assert_eq!(err.location(), Some(callee));
+        assert_eq!(err.to_string(), "Division by zero (line 3, pc 0x100)");
+        assert_eq!(err.without_location().at(caller).to_string(), "Division by zero (pc 0x88)");
+    }
+}
@@ -0,0 +1,185 @@
+use crate::error::VmError;
+use crate::instruction::InstructionType;
+use crate::log_buffer::{get_log_len, truncate_log};
+use crate::memory::AccessType;
+use crate::observer::{ExecutionObserver, MemoryAccess};
+use crate::vm::{CallFrame, ReturnData, VMState};
+use sbpf_assembler::debuginfo::RegisterType;
+use std::collections::VecDeque;
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 64 * 1024 * 1024;
+
+// What one instruction changed, enough to put the VM back the way it was
+#[derive(Debug)]
+struct UndoEntry {
+    pc: usize,
+    instruction_count: u64,
+    compute_units_consumed: u64,
+    heap_allocated: u64,
+    log_len: usize,
+    // old values of the registers it wrote
+    registers: Vec<(u8, u64, RegisterType)>,
+    // old bytes of every store, in order
+    memory: Vec<(u64, Vec<u8>)>,
+    // the frame an exit returned from
+    popped_frame: Option<CallFrame>,
+    pushed_frame: bool,
+    // syscalls may replace it
+    return_data: Option<ReturnData>,
+}
+
+impl UndoEntry {
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.registers.len() * std::mem::size_of::<(u8, u64, RegisterType)>()
+            + self.memory.iter().map(|(_, data)| std::mem::size_of::<(u64, Vec<u8>)>() + data.len()).sum::<usize>()
+            + self.return_data.as_ref().map_or(0, |return_data| return_data.data.len())
+    }
+
+    fn undo(self, vm: &mut VMState) {
+        // stores are undone newest first, in case they overlap
+        for (addr, data) in self.memory.into_iter().rev() {
+            vm.memory.store(addr, &data).expect("undone store was valid");
+        }
+        for (register, value, register_type) in self.registers {
+            vm.registers[register as usize].value = value;
+            vm.registers[register as usize].register_type = register_type;
+        }
+        if self.pushed_frame {
+            vm.call_frames.pop();
+        }
+        if let Some(frame) = self.popped_frame {
+            vm.call_frames.push(frame);
+        }
+        if let Some(return_data) = self.return_data {
+            vm.return_data = return_data;
+        }
+        vm.pc = self.pc;
+        vm.instruction_count = self.instruction_count;
+        vm.compute_units_consumed = self.compute_units_consumed;
+        vm.heap_allocated = self.heap_allocated;
+        vm.exited = false;
+        truncate_log(self.log_len);
+    }
+}
+
+// Undo log recorded as an observer of the VM. The oldest entries are
+// dropped once the log grows past `limit` bytes.
+#[derive(Debug)]
+pub struct History {
+    entries: VecDeque<UndoEntry>,
+    size: usize,
+    limit: usize,
+    // registers and call depth before the current instruction
+    registers: [(u64, RegisterType); 11],
+    call_depth: usize,
+    // the current instruction has not been finished yet
+    pending: bool,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            limit,
+            registers: [(0, RegisterType::Null); 11],
+            call_depth: 0,
+            pending: false,
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.evict();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+        self.pending = false;
+    }
+
+    // Number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Puts the VM back to before the last recorded instruction
+    pub fn undo(&mut self, vm: &mut VMState) -> bool {
+        let Some(entry) = self.entries.pop_back() else {
+            return false;
+        };
+        self.size -= entry.size();
+        self.pending = false;
+        entry.undo(vm);
+        true
+    }
+
+    fn finish(&mut self, vm: &VMState) {
+        if !std::mem::take(&mut self.pending) {
+            return;
+        }
+        let entry = self.entries.back_mut().expect("pending entry");
+        entry.registers = (0..11)
+            .filter(|&i| vm.registers[i].value != self.registers[i].0 || vm.registers[i].register_type != self.registers[i].1)
+            .map(|i| (i as u8, self.registers[i].0, self.registers[i].1))
+            .collect();
+        entry.pushed_frame = vm.call_frames.len() > self.call_depth;
+        self.size += entry.size();
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.limit {
+            match self.entries.pop_front() {
+                Some(entry) => self.size -= entry.size(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl ExecutionObserver for History {
+    fn before_instruction(&mut self, vm: &VMState, pc: usize, instruction: &InstructionType) {
+        self.registers = std::array::from_fn(|i| (vm.registers[i].value, vm.registers[i].register_type));
+        self.call_depth = vm.call_frames.len();
+        self.pending = true;
+        self.entries.push_back(UndoEntry {
+            pc,
+            instruction_count: vm.instruction_count,
+            compute_units_consumed: vm.compute_units_consumed,
+            heap_allocated: vm.heap_allocated,
+            log_len: get_log_len(),
+            registers: Vec::new(),
+            memory: Vec::new(),
+            popped_frame: if matches!(instruction, InstructionType::Exit(_)) { vm.call_frames.last().cloned() } else { None },
+            pushed_frame: false,
+            return_data: matches!(instruction, InstructionType::Call(_)).then(|| vm.return_data.clone()),
+        });
+    }
+
+    fn after_instruction(&mut self, vm: &VMState, _pc: usize, _instruction: &InstructionType) {
+        self.finish(vm);
+    }
+
+    fn on_memory_access(&mut self, _vm: &VMState, access: &MemoryAccess) {
+        if let (true, AccessType::Store, Some(old_data)) = (self.pending, access.access, &access.old_data) {
+            let entry = self.entries.back_mut().expect("pending entry");
+            entry.memory.push((access.addr, old_data.clone()));
+        }
+    }
+
+    // a failed instruction can be stepped back over too
+    fn on_exit(&mut self, vm: &VMState, _result: &Result<u64, VmError>) {
+        self.finish(vm);
+    }
+}
@@ -1,44 +1,48 @@
-use crate::vm::VMState;
-use crate::vm::MEMORY_INPUT_DATA_START;
+use crate::vm::{VMState, PROGRAM_START};
 use crate::program::Program;
-use crate::log_buffer::log_message;
+use crate::error::VmError;
 use sbpf_assembler::opcode::Opcode;
-use sbpf_assembler::debuginfo::{RegisterType, RegisterHint, DebugInfo};
+use sbpf_assembler::SbpfVersion;
+use sbpf_assembler::debuginfo::{RegisterType, DebugInfo};
+
+// Where execution continues after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Next {
+    FallThrough,
+    // absolute pc of the branch target
+    Jump(usize),
+}
 
 pub trait Instruction {
-    fn execute(&self, vm: &mut VMState, program: &Program, debug_info: Option<&DebugInfo>) -> Result<(), String>;
+    fn execute(&self, vm: &mut VMState, program: &Program, debug_info: Option<&DebugInfo>) -> Result<Next, VmError>;
 }
 
 #[derive(Debug)]
 pub enum InstructionType {
     Lddw(Lddw),
-    Ldxb(Ldxb),
-    Ldxdw(Ldxdw),
-    AddImm(AddImm),
-    AddReg(AddReg),
-    SubImm(SubImm),
-    SubReg(SubReg),
-    MoveImm(MoveImm),
-    MoveReg(MoveReg),
+    Load(Load),
+    Store(Store),
+    StoreImm(StoreImm),
+    AluImm(AluImm),
+    AluReg(AluReg),
     Jump(Jump),
     Call(Call),
+    Callx(Callx),
     Exit(Exit),
 }
 
 impl Instruction for InstructionType {
-    fn execute(&self, vm: &mut VMState, program: &Program, debug_info: Option<&DebugInfo>) -> Result<(), String> {
+    fn execute(&self, vm: &mut VMState, program: &Program, debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
         match self {
             InstructionType::Lddw(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::Ldxb(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::Ldxdw(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::AddImm(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::AddReg(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::SubImm(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::SubReg(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::MoveImm(instr) => instr.execute(vm, program, debug_info),
-            InstructionType::MoveReg(instr) => instr.execute(vm, program, debug_info),
+            InstructionType::Load(instr) => instr.execute(vm, program, debug_info),
+            InstructionType::Store(instr) => instr.execute(vm, program, debug_info),
+            InstructionType::StoreImm(instr) => instr.execute(vm, program, debug_info),
+            InstructionType::AluImm(instr) => instr.execute(vm, program, debug_info),
+            InstructionType::AluReg(instr) => instr.execute(vm, program, debug_info),
             InstructionType::Jump(instr) => instr.execute(vm, program, debug_info),
             InstructionType::Call(instr) => instr.execute(vm, program, debug_info),
+            InstructionType::Callx(instr) => instr.execute(vm, program, debug_info),
             InstructionType::Exit(instr) => instr.execute(vm, program, debug_info),
         }
     }
@@ -51,21 +55,22 @@ pub struct Lddw {
 }
 
 impl Lddw {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+    pub fn decode(bytes: &[u8]) -> Result<Self, VmError> {
         if bytes.len() < 16 {
-            return Err("Not enough bytes for Lddw instruction".to_string());
+            return Err(VmError::invalid_instruction("Not enough bytes for Lddw instruction"));
         }
         Ok(Lddw {
             register: bytes[1] as usize,
-            value: u64::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7], bytes[8], bytes[9], bytes[10], bytes[11]]),
+            // low 32 bits in the first slot's immediate, high 32 bits in the second's
+            value: u64::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7], bytes[12], bytes[13], bytes[14], bytes[15]]),
         })
     }
 }
 
 impl Instruction for Lddw {
-    fn execute(&self, vm: &mut VMState, _program: &Program, debug_info: Option<&DebugInfo>) -> Result<(), String> {
+    fn execute(&self, vm: &mut VMState, _program: &Program, debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
         if self.register >= vm.registers.len() {
-            return Err("Invalid register index".to_string());
+            return Err(VmError::invalid_instruction("Invalid register index"));
         }
         
         let register_type = debug_info
@@ -74,224 +79,305 @@ impl Instruction for Lddw {
             .unwrap_or(RegisterType::Int);
             
         vm.update_register(self.register, self.value, register_type);
-        Ok(())
+        Ok(Next::FallThrough)
     }
 }
 
-#[derive(Debug)]
-pub struct Ldxb {
-    pub register: usize,
-    pub base_reg: usize,
-    pub offset: u16,
-}
-
-impl Ldxb {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
-        if bytes.len() < 16 {
-            return Err("Not enough bytes for Ldxb instruction".to_string());
-        }
-        Ok(Ldxb {
-            register: (bytes[1] & 0x0F) as usize,
-            base_reg: (bytes[1] >> 4) as usize,
-            offset: u16::from_le_bytes([bytes[2], bytes[3]]),
-        })
-    }
-}
-
-impl Instruction for Ldxb {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        let base_addr = vm.registers[self.base_reg].value as usize;
-        let offset = self.offset as usize;
-        vm.update_register(self.register, vm.memory[base_addr + offset] as u64, RegisterType::Int);
-        Ok(())
+// Access width in bytes for the load and store opcodes
+fn access_size(opcode: Opcode) -> usize {
+    match opcode {
+        Opcode::Ldxb | Opcode::Stb | Opcode::Stxb => 1,
+        Opcode::Ldxh | Opcode::Sth | Opcode::Stxh => 2,
+        Opcode::Ldxw | Opcode::Stw | Opcode::Stxw => 4,
+        _ => 8,
     }
 }
 
 #[derive(Debug)]
-pub struct Ldxdw {
+pub struct Load {
     pub register: usize,
     pub base_reg: usize,
-    pub offset: u16,
+    pub offset: i16,
+    pub opcode: Opcode,
 }
 
-impl Ldxdw {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
-        if bytes.len() < 16 {
-            return Err("Not enough bytes for Ldxdw instruction".to_string());
+impl Load {
+    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, VmError> {
+        if bytes.len() < 8 {
+            return Err(VmError::invalid_instruction(format!("Not enough bytes for {} instruction", opcode.to_str())));
         }
-        Ok(Ldxdw {
+        Ok(Load {
             register: (bytes[1] & 0x0F) as usize,
             base_reg: (bytes[1] >> 4) as usize,
-            offset: u16::from_le_bytes([bytes[2], bytes[3]]),
+            offset: i16::from_le_bytes([bytes[2], bytes[3]]),
+            opcode,
         })
     }
 }
 
-impl Instruction for Ldxdw {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        let base_addr = vm.registers[self.base_reg].value as usize;
-        let offset = self.offset as usize;
-        vm.update_register(self.register, vm.memory[base_addr + offset] as u64, RegisterType::Int);
-        Ok(())
+impl Instruction for Load {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.register >= vm.registers.len() || self.base_reg >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
+        }
+        let addr = vm.registers[self.base_reg].value.wrapping_add(self.offset as i64 as u64);
+        let bytes = vm.read_memory(addr, access_size(self.opcode))?;
+        // little-endian, zero-extended to 64 bits
+        let mut value = [0u8; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        vm.update_register(self.register, u64::from_le_bytes(value), RegisterType::Int);
+        Ok(Next::FallThrough)
     }
 }
 
 #[derive(Debug)]
-pub struct AddImm {    
-    pub register: usize,
-    pub value: u64,
+pub struct Store {
+    pub base_reg: usize,
+    pub offset: i16,
+    pub src: usize,
+    pub opcode: Opcode,
 }
 
-impl AddImm {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+impl Store {
+    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, VmError> {
         if bytes.len() < 8 {
-            return Err("Not enough bytes for AddImm instruction".to_string());
+            return Err(VmError::invalid_instruction(format!("Not enough bytes for {} instruction", opcode.to_str())));
         }
-        Ok(AddImm {
-            register: bytes[1] as usize,
-            value: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as u64,
+        Ok(Store {
+            base_reg: (bytes[1] & 0x0F) as usize,
+            offset: i16::from_le_bytes([bytes[2], bytes[3]]),
+            src: (bytes[1] >> 4) as usize,
+            opcode,
         })
     }
 }
 
-impl Instruction for AddImm {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        let addition = vm.registers[self.register].value + self.value;
-        vm.update_register(self.register, addition, vm.registers[self.register].register_type);
-        Ok(())
+impl Instruction for Store {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.src >= vm.registers.len() || self.base_reg >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
+        }
+        let addr = vm.registers[self.base_reg].value.wrapping_add(self.offset as i64 as u64);
+        let value = vm.registers[self.src].value.to_le_bytes();
+        vm.write_memory(addr, &value[..access_size(self.opcode)])?;
+        Ok(Next::FallThrough)
     }
 }
 
 #[derive(Debug)]
-pub struct AddReg {
-    pub src: usize,
-    pub dest: usize,
+pub struct StoreImm {
+    pub base_reg: usize,
+    pub offset: i16,
+    pub value: u64,
+    pub opcode: Opcode,
 }
 
-impl AddReg {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+impl StoreImm {
+    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, VmError> {
         if bytes.len() < 8 {
-            return Err("Not enough bytes for AddReg instruction".to_string());
+            return Err(VmError::invalid_instruction(format!("Not enough bytes for {} instruction", opcode.to_str())));
         }
-        Ok(AddReg {
-            src: (bytes[1] >> 4) as usize,  // high nibble
-            dest: (bytes[1] & 0x0F) as usize,  // low nibble
+        Ok(StoreImm {
+            base_reg: (bytes[1] & 0x0F) as usize,
+            offset: i16::from_le_bytes([bytes[2], bytes[3]]),
+            // stdw stores the sign-extended immediate
+            value: i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as i64 as u64,
+            opcode,
         })
     }
 }
 
-impl Instruction for AddReg {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        let addition = vm.registers[self.dest].value + vm.registers[self.src].value;
-        vm.update_register(self.dest, addition, vm.registers[self.dest].register_type);
-        Ok(())
+impl Instruction for StoreImm {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.base_reg >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
+        }
+        let addr = vm.registers[self.base_reg].value.wrapping_add(self.offset as i64 as u64);
+        let value = self.value.to_le_bytes();
+        vm.write_memory(addr, &value[..access_size(self.opcode)])?;
+        Ok(Next::FallThrough)
     }
 }
 
 #[derive(Debug)]
-pub struct SubImm {
+pub struct AluImm {
     pub register: usize,
     pub value: u64,
+    pub opcode: Opcode,
+    // the immediate is the left operand, sub from SBPF v2
+    pub swap_operands: bool,
 }
 
-
-impl SubImm {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+impl AluImm {
+    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, VmError> {
         if bytes.len() < 8 {
-            return Err("Not enough bytes for SubImm instruction".to_string());
+            return Err(VmError::invalid_instruction(format!("Not enough bytes for {} instruction", opcode.to_str())));
         }
-        Ok(SubImm {
-            register: bytes[1] as usize,
-            value: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as u64,
+        let imm = i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let value = match opcode {
+            // unsigned PQR ops take the immediate zero-extended
+            Opcode::Udiv32Imm | Opcode::Udiv64Imm | Opcode::Urem32Imm
+            | Opcode::Urem64Imm | Opcode::Uhmul64Imm => imm as u32 as u64,
+            // everything else sign-extends it to 64 bits
+            _ => imm as i64 as u64,
+        };
+        Ok(AluImm {
+            register: (bytes[1] & 0x0F) as usize,
+            value,
+            opcode,
+            swap_operands: false,
         })
     }
 }
 
-impl Instruction for SubImm {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        let subtraction = vm.registers[self.register].value - self.value;
-        vm.update_register(self.register, subtraction, vm.registers[self.register].register_type);
-        Ok(())
+impl Instruction for AluImm {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.register >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
+        }
+        let dst = vm.registers[self.register].value;
+        let result = if self.swap_operands { alu(self.opcode, self.value, dst)? } else { alu(self.opcode, dst, self.value)? };
+        let register_type = match self.opcode {
+            Opcode::Mov32Imm | Opcode::Mov64Imm => RegisterType::Int,
+            _ => vm.registers[self.register].register_type,
+        };
+        vm.update_register(self.register, result, register_type);
+        Ok(Next::FallThrough)
     }
 }
 
 #[derive(Debug)]
-pub struct SubReg {
+pub struct AluReg {
     pub src: usize,
     pub dest: usize,
+    pub opcode: Opcode,
 }
 
-impl SubReg {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+impl AluReg {
+    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, VmError> {
         if bytes.len() < 8 {
-            return Err("Not enough bytes for SubReg instruction".to_string());
+            return Err(VmError::invalid_instruction(format!("Not enough bytes for {} instruction", opcode.to_str())));
         }
-        Ok(SubReg {
+        Ok(AluReg {
             src: (bytes[1] >> 4) as usize,  // high nibble
             dest: (bytes[1] & 0x0F) as usize,  // low nibble
+            opcode,
         })
     }
 }
 
-impl Instruction for SubReg {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        let subtraction = vm.registers[self.dest].value - vm.registers[self.src].value;
-        vm.update_register(self.dest, subtraction, vm.registers[self.dest].register_type);
-        Ok(())
-    }
-}
-
-#[derive(Debug)]
-pub struct MoveImm {
-    pub register: usize,
-    pub value: u64,
-}
-
-impl MoveImm {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
-        if bytes.len() < 8 {
-            return Err("Not enough bytes for MoveImm instruction".to_string());
+impl Instruction for AluReg {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.src >= vm.registers.len() || self.dest >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
         }
-        Ok(MoveImm {
-            register: bytes[1] as usize,
-            value: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as u64,
-        })
+        let result = alu(self.opcode, vm.registers[self.dest].value, vm.registers[self.src].value)?;
+        let register_type = match self.opcode {
+            Opcode::Mov32Reg | Opcode::Mov64Reg => RegisterType::Int,
+            _ => vm.registers[self.dest].register_type,
+        };
+        vm.update_register(self.dest, result, register_type);
+        Ok(Next::FallThrough)
     }
 }
 
-impl Instruction for MoveImm {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        if self.register >= vm.registers.len() {
-            return Err("Invalid register index".to_string());
+// Computes `dst op src` for every ALU opcode. 32-bit forms operate on the low
+// halves and zero-extend the result; all arithmetic wraps so that overflow
+// never panics, even with overflow-checks enabled.
+fn alu(opcode: Opcode, dst: u64, src: u64) -> Result<u64, VmError> {
+    let result = match opcode {
+        // 32-bit
+        Opcode::Add32Imm | Opcode::Add32Reg => (dst as u32).wrapping_add(src as u32) as u64,
+        Opcode::Sub32Imm | Opcode::Sub32Reg => (dst as u32).wrapping_sub(src as u32) as u64,
+        Opcode::Mul32Imm | Opcode::Mul32Reg
+        | Opcode::Lmul32Imm | Opcode::Lmul32Reg => (dst as u32).wrapping_mul(src as u32) as u64,
+        Opcode::Div32Imm | Opcode::Div32Reg
+        | Opcode::Udiv32Imm | Opcode::Udiv32Reg => {
+            (dst as u32).checked_div(src as u32).ok_or_else(divide_by_zero)? as u64
         }
-        vm.update_register(self.register, self.value, RegisterType::Int);
-        Ok(())
-    }
-}
-
-#[derive(Debug)]
-pub struct MoveReg {
-    pub src: usize,
-    pub dest: usize,
+        Opcode::Mod32Imm | Opcode::Mod32Reg
+        | Opcode::Urem32Imm | Opcode::Urem32Reg => {
+            (dst as u32).checked_rem(src as u32).ok_or_else(divide_by_zero)? as u64
+        }
+        Opcode::Sdiv32Imm | Opcode::Sdiv32Reg => {
+            if src as u32 == 0 {
+                return Err(divide_by_zero());
+            }
+            (dst as i32).checked_div(src as i32).ok_or_else(divide_overflow)? as u32 as u64
+        }
+        Opcode::Srem32Imm | Opcode::Srem32Reg => {
+            if src as u32 == 0 {
+                return Err(divide_by_zero());
+            }
+            (dst as i32).checked_rem(src as i32).ok_or_else(divide_overflow)? as u32 as u64
+        }
+        Opcode::Or32Imm | Opcode::Or32Reg => (dst as u32 | src as u32) as u64,
+        Opcode::And32Imm | Opcode::And32Reg => (dst as u32 & src as u32) as u64,
+        Opcode::Xor32Imm | Opcode::Xor32Reg => (dst as u32 ^ src as u32) as u64,
+        Opcode::Lsh32Imm | Opcode::Lsh32Reg => (dst as u32).wrapping_shl(src as u32) as u64,
+        Opcode::Rsh32Imm | Opcode::Rsh32Reg => (dst as u32).wrapping_shr(src as u32) as u64,
+        Opcode::Arsh32Imm | Opcode::Arsh32Reg => (dst as i32).wrapping_shr(src as u32) as u32 as u64,
+        Opcode::Mov32Imm | Opcode::Mov32Reg => src as u32 as u64,
+        Opcode::Neg32 => (dst as u32).wrapping_neg() as u64,
+        // 64-bit
+        Opcode::Add64Imm | Opcode::Add64Reg => dst.wrapping_add(src),
+        Opcode::Sub64Imm | Opcode::Sub64Reg => dst.wrapping_sub(src),
+        Opcode::Mul64Imm | Opcode::Mul64Reg
+        | Opcode::Lmul64Imm | Opcode::Lmul64Reg => dst.wrapping_mul(src),
+        Opcode::Uhmul64Imm | Opcode::Uhmul64Reg => ((dst as u128 * src as u128) >> 64) as u64,
+        Opcode::Shmul64Imm | Opcode::Shmul64Reg => {
+            ((dst as i64 as i128).wrapping_mul(src as i64 as i128) >> 64) as u64
+        }
+        Opcode::Div64Imm | Opcode::Div64Reg
+        | Opcode::Udiv64Imm | Opcode::Udiv64Reg => dst.checked_div(src).ok_or_else(divide_by_zero)?,
+        Opcode::Mod64Imm | Opcode::Mod64Reg
+        | Opcode::Urem64Imm | Opcode::Urem64Reg => dst.checked_rem(src).ok_or_else(divide_by_zero)?,
+        Opcode::Sdiv64Imm | Opcode::Sdiv64Reg => {
+            if src == 0 {
+                return Err(divide_by_zero());
+            }
+            (dst as i64).checked_div(src as i64).ok_or_else(divide_overflow)? as u64
+        }
+        Opcode::Srem64Imm | Opcode::Srem64Reg => {
+            if src == 0 {
+                return Err(divide_by_zero());
+            }
+            (dst as i64).checked_rem(src as i64).ok_or_else(divide_overflow)? as u64
+        }
+        Opcode::Or64Imm | Opcode::Or64Reg => dst | src,
+        Opcode::And64Imm | Opcode::And64Reg => dst & src,
+        Opcode::Xor64Imm | Opcode::Xor64Reg => dst ^ src,
+        Opcode::Lsh64Imm | Opcode::Lsh64Reg => dst.wrapping_shl(src as u32),
+        Opcode::Rsh64Imm | Opcode::Rsh64Reg => dst.wrapping_shr(src as u32),
+        Opcode::Arsh64Imm | Opcode::Arsh64Reg => (dst as i64).wrapping_shr(src as u32) as u64,
+        Opcode::Mov64Imm | Opcode::Mov64Reg => src,
+        Opcode::Neg64 => dst.wrapping_neg(),
+        // sets the high half, e.g. after a mov32 of the low half
+        Opcode::Hor64Imm => dst | (src << 32),
+        // byte swaps, the immediate selects the width
+        Opcode::Le => match src {
+            16 => (dst as u16).to_le() as u64,
+            32 => (dst as u32).to_le() as u64,
+            64 => dst.to_le(),
+            _ => return Err(VmError::invalid_instruction(format!("Invalid le width: {}", src))),
+        },
+        Opcode::Be => match src {
+            16 => (dst as u16).to_be() as u64,
+            32 => (dst as u32).to_be() as u64,
+            64 => dst.to_be(),
+            _ => return Err(VmError::invalid_instruction(format!("Invalid be width: {}", src))),
+        },
+        _ => return Err(VmError::invalid_instruction(format!("Invalid ALU opcode: {}", opcode.to_str()))),
+    };
+    Ok(result)
 }
 
-impl MoveReg {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
-        if bytes.len() < 8 {
-            return Err("Not enough bytes for MoveReg instruction".to_string());
-        }
-        Ok(MoveReg {
-            src: (bytes[1] >> 4) as usize,  // high nibble
-            dest: (bytes[1] & 0x0F) as usize,  // low nibble
-        })
-    }
+fn divide_by_zero() -> VmError {
+    VmError::DivideByZero { location: None }
 }
 
-impl Instruction for MoveReg {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        vm.update_register(self.dest, vm.registers[self.src].value, RegisterType::Int);
-        Ok(())
-    }
+fn divide_overflow() -> VmError {
+    VmError::DivideOverflow { location: None }
 }
 
 #[derive(Debug)]
@@ -303,11 +389,11 @@ pub struct Jump {
 }
 
 impl Jump {
-    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, String> {
-        if bytes.len() < 16 {
-            return Err("Not enough bytes for Jump instruction".to_string());
+    pub fn decode(bytes: &[u8], opcode: Opcode) -> Result<Self, VmError> {
+        if bytes.len() < 8 {
+            return Err(VmError::invalid_instruction("Not enough bytes for Jump instruction"));
         }
-        let register = bytes[1] as usize;
+        let register = (bytes[1] & 0x0F) as usize;
         let offset = i16::from_le_bytes([bytes[2], bytes[3]]);
         let value = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
         Ok(Jump { register, value, offset, opcode })
@@ -315,7 +401,11 @@ impl Jump {
 }
 
 impl Instruction for Jump {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.register >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
+        }
+
         let condition_met = match self.opcode {
             Opcode::Ja => true,
             Opcode::JeqImm => vm.registers[self.register].value == self.value as u64,
@@ -324,65 +414,96 @@ impl Instruction for Jump {
             Opcode::JgeImm => vm.registers[self.register].value >= self.value as u64,
             Opcode::JltImm => vm.registers[self.register].value < self.value as u64,
             Opcode::JleImm => vm.registers[self.register].value <= self.value as u64,
-            _ => return Err("Invalid jump opcode".to_string()),
+            _ => return Err(VmError::invalid_instruction("Invalid jump opcode")),
         };
 
-        if condition_met {
-            // Since each instruction is 8 bytes, multiply offset by 8 to get byte offset
-            vm.pc = (vm.pc as i32 + (self.offset as i32 * 8)) as usize;
+        if !condition_met {
+            return Ok(Next::FallThrough);
+        }
+        // the offset counts 8-byte slots from the next instruction
+        let target = vm.pc as i64 + (self.offset as i64 + 1) * 8;
+        if target < 0 {
+            return Err(VmError::invalid_instruction(format!("Jump target out of bounds: {}", target)));
         }
-        Ok(())
+        Ok(Next::Jump(target as usize))
     }
 }
 
 #[derive(Debug)]
 pub struct Call {
-    pub function_id: u32,
+    pub src: usize,
+    pub imm: i32,
 }
 
 impl Call {
-    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+    pub fn decode(bytes: &[u8]) -> Result<Self, VmError> {
         if bytes.len() < 8 {
-            return Err("Not enough bytes for Call instruction".to_string());
+            return Err(VmError::invalid_instruction("Not enough bytes for Call instruction"));
         }
-        // Only read the 3 bytes for function ID (10 00 00), ignoring pAddImming
-        let function_id = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], 0]);
         Ok(Call {
-            function_id,
+            src: (bytes[1] >> 4) as usize,
+            imm: i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
         })
     }
 }
 
 impl Instruction for Call {
-    fn execute(&self, vm: &mut VMState, program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
-        match self.function_id {
-            0x10 => {
-                // sol_log_ implementation
-                let r1 = vm.registers[1].value; // pointer to buffer
-                let r2 = vm.registers[2].value; // length of buffer
-                let mut buffer = Vec::new();
-                if r2 > 0 {
-                    if r1 < MEMORY_INPUT_DATA_START {
-                        // Read memory at r1 for r2 bytes
-                        buffer = program.read(r1 as u64, r2 as u64)
-                            .map_err(|e| format!("Failed to read memory: {}", e))?;
-                    } else {
-                        // TODO : add a read function to vm
-                        // Read memory at r1 for r2 bytes
-                        let memory_ptr = r1 - MEMORY_INPUT_DATA_START;
-                        buffer = vm.memory[memory_ptr as usize..memory_ptr as usize + r2 as usize].to_vec();
-                    }
-
-                    // Convert buffer to string and print
-                    let message = String::from_utf8_lossy(&buffer);
-                    log_message(&format!("sol_log_: {}", message));
-                } else {
-                    log_message(&format!("sol_log_64_: {}", r1));
-                }
-                Ok(())
+    fn execute(&self, vm: &mut VMState, program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        // syscall relocations are resolved at load time and clear the src field,
+        // src = 1 marks a pc-relative call to a local function
+        if self.src == 0 {
+            let hash = self.imm as u32;
+            let syscall = vm.syscalls.get(hash)
+                .ok_or(VmError::UnsupportedSyscall { hash, location: None })?;
+            let args = std::array::from_fn(|i| vm.registers[i + 1].value);
+            let name = vm.syscalls.name(hash).unwrap_or_default().to_string();
+            vm.observers.notify(|observer| observer.on_syscall(vm, &name, args));
+            let result = syscall.execute(vm, args)?;
+            vm.update_register(0, result, RegisterType::Int);
+            Ok(Next::FallThrough)
+        } else {
+            // like jumps, the target is relative to the next instruction
+            let target = vm.pc as i64 + (self.imm as i64 + 1) * 8;
+            if target < 0 || !program.is_instruction(target as u64) {
+                return Err(VmError::InvalidCallTarget { target: target as u64, location: None });
             }
-            _ => Err(format!("Unsupported function ID: 0x{:x}", self.function_id)),
+            vm.push_call_frame()?;
+            Ok(Next::Jump(target as usize))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Callx {
+    pub register: usize,
+}
+
+impl Callx {
+    pub fn decode(bytes: &[u8], version: SbpfVersion) -> Result<Self, VmError> {
+        if bytes.len() < 8 {
+            return Err(VmError::invalid_instruction("Not enough bytes for Callx instruction"));
+        }
+        // the target register is in the src field from v2, in the immediate before
+        let register = if version.callx_uses_src_reg() {
+            (bytes[1] >> 4) as usize
+        } else {
+            u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize
+        };
+        Ok(Callx { register })
+    }
+}
+
+impl Instruction for Callx {
+    fn execute(&self, vm: &mut VMState, program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        if self.register >= vm.registers.len() {
+            return Err(VmError::invalid_instruction("Invalid register index"));
         }
+        let addr = vm.registers[self.register].value;
+        let target = addr.checked_sub(PROGRAM_START)
+            .filter(|target| program.is_instruction(*target))
+            .ok_or(VmError::InvalidCallTarget { target: addr, location: None })?;
+        vm.push_call_frame()?;
+        Ok(Next::Jump(target as usize))
     }
 }
 
@@ -390,55 +511,102 @@ impl Instruction for Call {
 pub struct Exit;
 
 impl Instruction for Exit {
-    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<(), String> {
+    fn execute(&self, vm: &mut VMState, _program: &Program, _debug_info: Option<&DebugInfo>) -> Result<Next, VmError> {
+        // return to the caller, or terminate from the outermost frame
+        if let Some(return_pc) = vm.pop_call_frame() {
+            return Ok(Next::Jump(return_pc));
+        }
         vm.exit();
-        Ok(())
+        Ok(Next::FallThrough)
     }
 }
 
-// Function to decode a single instruction from bytecode
-pub fn decode_instruction(bytes: &[u8]) -> Result<(InstructionType, usize), String> {
+// Loads and stores in the opcodes SBPF v2 freed up from mul, div, neg and mod
+pub fn moved_memory_opcode(byte: u8) -> Option<Opcode> {
+    Some(match byte {
+        0x2c => Opcode::Ldxb,
+        0x3c => Opcode::Ldxh,
+        0x8c => Opcode::Ldxw,
+        0x9c => Opcode::Ldxdw,
+        0x27 => Opcode::Stb,
+        0x37 => Opcode::Sth,
+        0x87 => Opcode::Stw,
+        0x97 => Opcode::Stdw,
+        0x2f => Opcode::Stxb,
+        0x3f => Opcode::Stxh,
+        0x8f => Opcode::Stxw,
+        0x9f => Opcode::Stxdw,
+        _ => return None,
+    })
+}
+
+// Function to decode a single instruction from bytecode, rejecting the ones
+// the program's SBPF version does not have
+pub fn decode_instruction(bytes: &[u8], version: SbpfVersion) -> Result<(InstructionType, usize), VmError> {
     if bytes.is_empty() {
-        return Err("Empty bytecode".to_string());
+        return Err(VmError::invalid_instruction("Empty bytecode"));
     }
 
-    let opcode = Opcode::from_u8(bytes[0]).ok_or_else(|| format!("Unknown opcode: 0x{:02x}", bytes[0]))?;
+    let moved = version.move_memory_instruction_classes().then(|| moved_memory_opcode(bytes[0])).flatten();
+    let opcode = match moved {
+        Some(opcode) => opcode,
+        None => {
+            let opcode = Opcode::from_u8(bytes[0]).ok_or(VmError::InvalidOpcode { opcode: bytes[0], location: None })?;
+            if !version.supports(opcode) {
+                return Err(VmError::invalid_instruction(format!("{} is not available in SBPF {}", opcode.to_str(), version)));
+            }
+            opcode
+        }
+    };
     let (instr, size) = match opcode {
         Opcode::Lddw => {
             let lddw = Lddw::decode(bytes)?;
             (InstructionType::Lddw(lddw), 16)
         }
-        Opcode::Ldxb => {
-            let ldxb = Ldxb::decode(bytes)?;
-            (InstructionType::Ldxb(ldxb), 8)
-        }
-        Opcode::Ldxdw => {
-            let ldxdw = Ldxdw::decode(bytes)?;
-            (InstructionType::Ldxdw(ldxdw), 8)
-        }
-        Opcode::Add64Imm | Opcode::Add32Imm => {
-            let AddImm = AddImm::decode(bytes)?;
-            (InstructionType::AddImm(AddImm), 8)
+        Opcode::Ldxb | Opcode::Ldxh | Opcode::Ldxw | Opcode::Ldxdw => {
+            let load = Load::decode(bytes, opcode)?;
+            (InstructionType::Load(load), 8)
         }
-        Opcode::Add64Reg | Opcode::Add32Reg => {
-            let AddReg = AddReg::decode(bytes)?;
-            (InstructionType::AddReg(AddReg), 8)
+        Opcode::Stxb | Opcode::Stxh | Opcode::Stxw | Opcode::Stxdw => {
+            let store = Store::decode(bytes, opcode)?;
+            (InstructionType::Store(store), 8)
         }
-        Opcode::Sub64Imm | Opcode::Sub32Imm => {
-            let SubImm = SubImm::decode(bytes)?;
-            (InstructionType::SubImm(SubImm), 8)
+        Opcode::Stb | Opcode::Sth | Opcode::Stw | Opcode::Stdw => {
+            let store = StoreImm::decode(bytes, opcode)?;
+            (InstructionType::StoreImm(store), 8)
         }
-        Opcode::Sub64Reg | Opcode::Sub32Reg => {
-            let SubReg = SubReg::decode(bytes)?;
-            (InstructionType::SubReg(SubReg), 8)
-        }
-        Opcode::Mov64Imm | Opcode::Mov32Imm => {
-            let mov = MoveImm::decode(bytes)?;
-            (InstructionType::MoveImm(mov), 8)
+        Opcode::Add32Imm | Opcode::Sub32Imm | Opcode::Mul32Imm | Opcode::Div32Imm
+        | Opcode::Or32Imm | Opcode::And32Imm | Opcode::Lsh32Imm | Opcode::Rsh32Imm
+        | Opcode::Mod32Imm | Opcode::Xor32Imm | Opcode::Mov32Imm | Opcode::Arsh32Imm
+        | Opcode::Lmul32Imm | Opcode::Udiv32Imm | Opcode::Urem32Imm | Opcode::Sdiv32Imm
+        | Opcode::Srem32Imm | Opcode::Neg32 | Opcode::Le | Opcode::Be
+        | Opcode::Add64Imm | Opcode::Sub64Imm | Opcode::Mul64Imm | Opcode::Div64Imm
+        | Opcode::Or64Imm | Opcode::And64Imm | Opcode::Lsh64Imm | Opcode::Rsh64Imm
+        | Opcode::Mod64Imm | Opcode::Xor64Imm | Opcode::Mov64Imm | Opcode::Arsh64Imm
+        | Opcode::Lmul64Imm | Opcode::Uhmul64Imm | Opcode::Shmul64Imm | Opcode::Udiv64Imm
+        | Opcode::Urem64Imm | Opcode::Sdiv64Imm | Opcode::Srem64Imm | Opcode::Neg64
+        | Opcode::Hor64Imm => {
+            let mut alu = AluImm::decode(bytes, opcode)?;
+            alu.swap_operands = matches!(opcode, Opcode::Sub32Imm | Opcode::Sub64Imm) && version.swap_sub_reg_imm_operands();
+            if alu.opcode == Opcode::Add64Imm && alu.register == 10 && !version.dynamic_stack_frames() {
+                return Err(VmError::invalid_instruction(format!(
+                    "add64 r10 is not available in SBPF {}, stack frames are dynamic from {}", version, SbpfVersion::V1
+                )));
+            }
+            (InstructionType::AluImm(alu), 8)
         }
-        Opcode::Mov64Reg | Opcode::Mov32Reg => {
-            let mov = MoveReg::decode(bytes)?;
-            (InstructionType::MoveReg(mov), 8)
+        Opcode::Add32Reg | Opcode::Sub32Reg | Opcode::Mul32Reg | Opcode::Div32Reg
+        | Opcode::Or32Reg | Opcode::And32Reg | Opcode::Lsh32Reg | Opcode::Rsh32Reg
+        | Opcode::Mod32Reg | Opcode::Xor32Reg | Opcode::Mov32Reg | Opcode::Arsh32Reg
+        | Opcode::Lmul32Reg | Opcode::Udiv32Reg | Opcode::Urem32Reg | Opcode::Sdiv32Reg
+        | Opcode::Srem32Reg
+        | Opcode::Add64Reg | Opcode::Sub64Reg | Opcode::Mul64Reg | Opcode::Div64Reg
+        | Opcode::Or64Reg | Opcode::And64Reg | Opcode::Lsh64Reg | Opcode::Rsh64Reg
+        | Opcode::Mod64Reg | Opcode::Xor64Reg | Opcode::Mov64Reg | Opcode::Arsh64Reg
+        | Opcode::Lmul64Reg | Opcode::Uhmul64Reg | Opcode::Shmul64Reg | Opcode::Udiv64Reg
+        | Opcode::Urem64Reg | Opcode::Sdiv64Reg | Opcode::Srem64Reg => {
+            let alu = AluReg::decode(bytes, opcode)?;
+            (InstructionType::AluReg(alu), 8)
         }
         Opcode::Ja | Opcode::JeqImm | Opcode::JneImm | Opcode::JgtImm | 
         Opcode::JgeImm | Opcode::JltImm | Opcode::JleImm => {
@@ -449,11 +617,263 @@ pub fn decode_instruction(bytes: &[u8]) -> Result<(InstructionType, usize), Stri
             let call = Call::decode(bytes)?;
             (InstructionType::Call(call), 8)
         }
+        Opcode::Callx => {
+            let callx = Callx::decode(bytes, version)?;
+            (InstructionType::Callx(callx), 8)
+        }
         Opcode::Exit => {
             (InstructionType::Exit(Exit), 8)
         }
-        _ => return Err(format!("Unsupported opcode: {}", opcode.to_str())),
+        _ => return Err(VmError::InvalidOpcode { opcode: bytes[0], location: None }),
     };
 
     Ok((instr, size))
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{VM, MAX_CALL_DEPTH, PROGRAM_START, STACK_FRAME_SIZE, STACK_START};
+    use codespan_reporting::files::SimpleFile;
+
+    #[test]
+    fn test_alu32_zero_extends() {
+        assert_eq!(alu(Opcode::Add32Imm, u64::MAX, 1).unwrap(), 0);
+        assert_eq!(alu(Opcode::Sub32Reg, 0, 1).unwrap(), 0xffff_ffff);
+        assert_eq!(alu(Opcode::Mov32Imm, 0, -1i64 as u64).unwrap(), 0xffff_ffff);
+        assert_eq!(alu(Opcode::Arsh32Imm, 0x8000_0000, 4).unwrap(), 0xf800_0000);
+        assert_eq!(alu(Opcode::Neg32, 1, 0).unwrap(), 0xffff_ffff);
+    }
+
+    #[test]
+    fn test_alu64_wraps() {
+        assert_eq!(alu(Opcode::Add64Imm, u64::MAX, 1).unwrap(), 0);
+        assert_eq!(alu(Opcode::Mul64Reg, u64::MAX, 2).unwrap(), u64::MAX - 1);
+        assert_eq!(alu(Opcode::Lsh64Reg, 1, 65).unwrap(), 2);
+        assert_eq!(alu(Opcode::Uhmul64Reg, u64::MAX, u64::MAX).unwrap(), u64::MAX - 1);
+        assert_eq!(alu(Opcode::Shmul64Reg, -1i64 as u64, 2).unwrap(), u64::MAX);
+        assert_eq!(alu(Opcode::Sdiv64Reg, -7i64 as u64, 2).unwrap(), -3i64 as u64);
+    }
+
+    #[test]
+    fn test_alu_division_errors() {
+        assert!(alu(Opcode::Div64Reg, 1, 0).is_err());
+        assert!(alu(Opcode::Urem32Imm, 1, 1 << 32).is_err());
+        assert!(alu(Opcode::Sdiv64Reg, i64::MIN as u64, -1i64 as u64).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_pqr_imm_zero_extends() {
+        let imm = (-1i32).to_le_bytes();
+        let bytes = |opcode: u8| [opcode, 0x01, 0, 0, imm[0], imm[1], imm[2], imm[3]];
+        let udiv = AluImm::decode(&bytes(0x46), Opcode::Udiv64Imm).unwrap();
+        assert_eq!(udiv.value, 0xffff_ffff);
+        assert_eq!(alu(udiv.opcode, u64::MAX, udiv.value).unwrap(), 0x1_0000_0001);
+        let uhmul = AluImm::decode(&bytes(0x36), Opcode::Uhmul64Imm).unwrap();
+        assert_eq!(alu(uhmul.opcode, u64::MAX, uhmul.value).unwrap(), 0xffff_fffe);
+        // signed ops still sign-extend
+        let add = AluImm::decode(&bytes(0x07), Opcode::Add64Imm).unwrap();
+        assert_eq!(add.value, u64::MAX);
+    }
+
+    fn assemble(source: &str) -> Vec<u8> {
+        let file = SimpleFile::new("test.s".to_string(), source.to_string());
+        let tokens = sbpf_assembler::tokenize(source).unwrap();
+        let parse_result = sbpf_assembler::Parser::new(tokens, &file).parse().unwrap();
+        sbpf_assembler::Program::from_parse_result(parse_result).emit_bytecode()
+    }
+
+    fn load_source(source: &str) -> VM {
+        let mut vm = VM::new();
+        vm.load_program(assemble(source)).unwrap();
+        vm
+    }
+
+    // Assembles and runs `source`, returning the VM after exit
+    fn run_source(source: &str) -> VM {
+        let mut vm = load_source(source);
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_load_store_widths() {
+        let vm = run_source("
+.globl e
+e:
+  mov64 r2, 0
+  sub64 r2, 1
+  stxdw [r1+8], r2
+  mov64 r2, 0x12
+  stxb [r1+8], r2
+  mov64 r2, 0x123456
+  stxh [r1+10], r2
+  ldxb r3, [r1+8]
+  ldxh r4, [r1+10]
+  ldxw r5, [r1+8]
+  ldxdw r6, [r1+8]
+  stw [r1+16], 0xfffffffe
+  ldxdw r7, [r1+16]
+  exit
+");
+        let registers: Vec<u64> = vm.get_registers().iter().map(|register| register.value).collect();
+        // stores truncate to their width, loads zero-extend
+        assert_eq!(registers[3..8], [0x12, 0x3456, 0x3456_ff12, 0xffff_ffff_3456_ff12, 0xffff_fffe]);
+    }
+
+    #[test]
+    fn test_negative_offsets_address_below_base() {
+        let vm = run_source("
+.globl e
+e:
+  mov64 r2, r1
+  add64 r2, 16
+  stdw [r2-8], 0xffffffff
+  ldxdw r3, [r1+8]
+  exit
+");
+        // stdw sign-extends its immediate
+        assert_eq!(vm.get_registers()[3].value, u64::MAX);
+    }
+
+    #[test]
+    fn test_decode_sign_extends_offsets() {
+        let load = Load::decode(&[0x79, 0xa1, 0xf8, 0xff, 0, 0, 0, 0], Opcode::Ldxdw).unwrap();
+        assert_eq!((load.register, load.base_reg, load.offset), (1, 10, -8));
+        let store = Store::decode(&[0x7b, 0x1a, 0x00, 0x80, 0, 0, 0, 0], Opcode::Stxdw).unwrap();
+        assert_eq!((store.base_reg, store.src, store.offset), (10, 1, i16::MIN));
+        let store = StoreImm::decode(&[0x7a, 0x0a, 0xf0, 0xff, 0xfe, 0xff, 0xff, 0xff], Opcode::Stdw).unwrap();
+        assert_eq!((store.offset, store.value), (-16, -2i64 as u64));
+    }
+
+    #[test]
+    fn test_jump_register_is_the_dst_field() {
+        let program = Program::new(assemble(".globl e\ne:\n  exit\n")).unwrap();
+        let mut vm = VMState::new();
+        // the src nibble of an immediate jump is ignored
+        let jump = Jump::decode(&[0x55, 0xf1, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00], Opcode::JneImm).unwrap();
+        assert_eq!(jump.register, 1);
+        vm.registers[1].value = 1;
+        assert_eq!(jump.execute(&mut vm, &program, None).unwrap(), Next::Jump(24));
+
+        let jump = Jump::decode(&[0x15, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], Opcode::JeqImm).unwrap();
+        assert_eq!(jump.execute(&mut vm, &program, None).unwrap_err().message(), "Invalid register index");
+    }
+
+    #[test]
+    fn test_call_restores_caller_frame() {
+        let mut vm = load_source("
+.globl e
+e:
+  mov64 r6, 7
+  mov64 r1, 5
+  call double
+  add64 r0, r6
+  exit
+double:
+  mov64 r6, 100
+  stxdw [r10-8], r1
+  ldxdw r0, [r10-8]
+  add64 r0, r1
+  exit
+");
+        // the callee's r6 and stack frame are dropped on exit
+        assert_eq!(vm.run().unwrap(), 17);
+        assert_eq!(vm.get_registers()[10].value, STACK_START + STACK_FRAME_SIZE);
+    }
+
+    #[test]
+    fn test_callx_and_exit() {
+        let program = Program::new(assemble(".globl e\ne:\n  exit\n  exit\n  exit\n")).unwrap();
+        let entry = program.entry_point as usize;
+        let mut vm = VMState::new();
+        vm.pc = entry;
+        vm.registers[2].value = PROGRAM_START + entry as u64 + 16;
+        vm.registers[6].value = 1;
+        let callx = Callx { register: 2 };
+        assert_eq!(callx.execute(&mut vm, &program, None).unwrap(), Next::Jump(entry + 16));
+        assert_eq!(vm.registers[10].value, STACK_START + 2 * STACK_FRAME_SIZE);
+
+        vm.pc = entry + 16;
+        vm.registers[6].value = 2;
+        assert_eq!(Exit.execute(&mut vm, &program, None).unwrap(), Next::Jump(entry + 8));
+        assert_eq!(vm.registers[6].value, 1);
+        assert_eq!(vm.registers[10].value, STACK_START + STACK_FRAME_SIZE);
+        assert!(!vm.exited);
+
+        // only instruction boundaries inside the program can be called
+        vm.registers[2].value += 4;
+        assert!(matches!(callx.execute(&mut vm, &program, None), Err(VmError::InvalidCallTarget { .. })));
+    }
+
+    #[test]
+    fn test_call_depth_is_limited() {
+        let mut vm = VMState::new();
+        for _ in 1..MAX_CALL_DEPTH {
+            vm.push_call_frame().unwrap();
+        }
+        assert!(matches!(vm.push_call_frame().unwrap_err(), VmError::CallDepthExceeded { max: MAX_CALL_DEPTH, .. }));
+
+        let mut vm = load_source(".globl e\ne:\n  call e\n  exit\n");
+        assert!(matches!(vm.run(), Err(VmError::CallDepthExceeded { max: MAX_CALL_DEPTH, .. })));
+    }
+
+    #[test]
+    fn test_decode_checks_sbpf_version() {
+        let lddw = [0x18, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(decode_instruction(&lddw, SbpfVersion::V1).is_ok());
+        assert_eq!(decode_instruction(&lddw, SbpfVersion::V2).unwrap_err().message(), "lddw is not available in SBPF v2");
+        let lmul = [0x96, 0x01, 0, 0, 2, 0, 0, 0];
+        assert!(decode_instruction(&lmul, SbpfVersion::V0).is_err());
+        assert!(decode_instruction(&lmul, SbpfVersion::V2).is_ok());
+        let grow_stack = [0x07, 0x0a, 0, 0, 0xc0, 0xff, 0xff, 0xff];
+        assert!(decode_instruction(&grow_stack, SbpfVersion::V0).is_err());
+        assert!(decode_instruction(&grow_stack, SbpfVersion::V1).is_ok());
+        assert_eq!(alu(Opcode::Hor64Imm, 0x1234, -0x8000_0000i64 as u64).unwrap(), 0x8000_0000_0000_1234);
+    }
+
+    #[test]
+    fn test_decode_v2_encodings() {
+        let decode = |bytes: &[u8], version| decode_instruction(bytes, version).map(|(instruction, _)| instruction);
+        // loads and stores moved into the opcodes of mul, div, neg and mod
+        let ldxdw = [0x79, 0xa1, 0xf8, 0xff, 0, 0, 0, 0];
+        assert_eq!(decode(&ldxdw, SbpfVersion::V2).unwrap_err().message(), "ldxdw is not available in SBPF v2");
+        let Ok(InstructionType::Load(load)) = decode(&[0x9c, 0xa1, 0xf8, 0xff, 0, 0, 0, 0], SbpfVersion::V2) else { panic!() };
+        assert_eq!((load.opcode, load.register, load.base_reg, load.offset), (Opcode::Ldxdw, 1, 10, -8));
+        let stb = [0x27, 0x0a, 0xff, 0xff, 7, 0, 0, 0];
+        assert!(matches!(decode(&stb, SbpfVersion::V2), Ok(InstructionType::StoreImm(StoreImm { opcode: Opcode::Stb, value: 7, .. }))));
+        assert!(matches!(decode(&stb, SbpfVersion::V0), Ok(InstructionType::AluImm(AluImm { opcode: Opcode::Mul64Imm, .. }))));
+        assert!(matches!(decode(&[0x8f, 0x1a, 0, 0, 0, 0, 0, 0], SbpfVersion::V3), Ok(InstructionType::Store(Store { opcode: Opcode::Stxw, .. }))));
+
+        // callx takes its register from the src field
+        let callx = [0x8d, 0x30, 0, 0, 2, 0, 0, 0];
+        assert!(matches!(decode(&callx, SbpfVersion::V0), Ok(InstructionType::Callx(Callx { register: 2 }))));
+        assert!(matches!(decode(&callx, SbpfVersion::V2), Ok(InstructionType::Callx(Callx { register: 3 }))));
+
+        // sub with an immediate subtracts the register from it
+        let program = Program::new(assemble(".globl e\ne:\n  exit\n")).unwrap();
+        let sub = [0x17, 0x01, 0, 0, 5, 0, 0, 0];
+        for (version, expected) in [(SbpfVersion::V1, 3), (SbpfVersion::V2, -3i64 as u64)] {
+            let mut vm = VMState::new();
+            vm.registers[1].value = 8;
+            decode(&sub, version).unwrap().execute(&mut vm, &program, None).unwrap();
+            assert_eq!(vm.registers[1].value, expected);
+        }
+    }
+
+    #[test]
+    fn test_assembler_rejects_changed_v2_instructions() {
+        let parse = |source: &str| {
+            let file = codespan_reporting::files::SimpleFile::new("test.s".to_string(), source.to_string());
+            let mut parser = sbpf_assembler::Parser::new(sbpf_assembler::tokenize(source).unwrap(), &file);
+            parser.set_sbpf_version(SbpfVersion::V2);
+            parser.parse().err().map(|e| e.to_string())
+        };
+        assert_eq!(parse(".globl e\ne:\n  ldxdw r1, [r10-8]\n  exit\n").unwrap(),
+            "Invalid instruction at line 3: ldxdw is not available in SBPF v2, the assembler does not emit its new encoding yet");
+        assert_eq!(parse(".globl e\ne:\n  callx r1\n  exit\n").unwrap(),
+            "Invalid instruction at line 3: callx is not available in SBPF v2, the assembler does not emit its new encoding yet");
+        assert!(parse(".globl e\ne:\n  sub64 r1, 1\n  mov64 r0, r1\n  exit\n").unwrap().contains("add the negated immediate instead"));
+        assert!(parse(".globl e\ne:\n  sub64 r1, r2\n  exit\n").is_none());
+    }
+}
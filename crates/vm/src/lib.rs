@@ -2,15 +2,38 @@ pub mod vm;
 pub mod program;
 pub mod instruction;
 pub mod log_buffer;
+pub mod memory;
+pub mod serialization;
+pub mod diff;
+pub mod compute_budget;
+pub mod syscalls;
+pub mod sysvar;
+pub mod programs;
+pub mod error;
+pub mod observer;
+pub mod breakpoint;
+pub mod watchpoint;
+pub mod history;
+pub mod disassembler;
+pub mod trace;
+pub mod snapshot;
 
-use sbpf_assembler::{Parser, Program};
-use crate::vm::VM;
+use sbpf_assembler::{Parser, Program, SbpfVersion};
+use crate::vm::{VM, PROGRAM_START};
+use crate::serialization::{Account, Pubkey};
+use crate::sysvar::Sysvars;
+use crate::error::VmError;
+use crate::breakpoint::{Breakpoint, BreakpointLocation, Condition};
+use crate::watchpoint::{Watch, WatchAccess};
+use crate::history::DEFAULT_HISTORY_LIMIT;
+use crate::trace::TraceFormat;
 use sbpf_assembler::debuginfo::RegisterType;
 use std::cell::RefCell;
 use serde::Serialize;
-use serde_wasm_bindgen::to_value;
+use serde_wasm_bindgen::{from_value, to_value};
 use wasm_bindgen::prelude::*;
 use codespan_reporting::files::SimpleFile;
+use base64::prelude::*;
 
 #[derive(Serialize)]
 struct Register {
@@ -22,7 +45,7 @@ struct Register {
 #[derive(Serialize)]
 struct Rdata {
     label: String,
-    address: usize,
+    address: u64,
     value: String,
 }
 
@@ -36,6 +59,11 @@ thread_local! {
     static VM_INSTANCE: RefCell<VM> = RefCell::new(VM::new());
 }
 
+// Direct access to the VM for native frontends such as the CLI
+pub fn with_vm<R>(f: impl FnOnce(&mut VM) -> R) -> R {
+    VM_INSTANCE.with(|vm| f(&mut vm.borrow_mut()))
+}
+
 #[wasm_bindgen]
 pub fn get_registers() -> JsValue {
     let registers: Vec<Register> = VM_INSTANCE.with(|vm| {
@@ -47,7 +75,7 @@ pub fn get_registers() -> JsValue {
                 value: match reg.register_type {
                     RegisterType::Addr => format!("0x{:016x}", reg.value),
                     RegisterType::Int => format!("{}", reg.value),
-                    RegisterType::Null => format!("null"),
+                    RegisterType::Null => "null".to_string(),
                 },
                 register_type: format!("{:?}", reg.register_type.to_string()),
             })
@@ -64,7 +92,7 @@ pub fn get_rodata() -> JsValue {
         rodata.iter()
             .map(|(label, offset, val)| Rdata {
                 label: label.to_string(),
-                address: *offset + vm.get_entry_point(),
+                address: PROGRAM_START + (*offset + vm.get_entry_point()) as u64,
                 value: val.to_string(),
             })
             .collect()
@@ -74,35 +102,70 @@ pub fn get_rodata() -> JsValue {
 
 #[wasm_bindgen]
 pub fn get_memory() -> JsValue {
-    let instruction_data = VM_INSTANCE.with(|vm| {
+    let (instruction_data, heap_used, heap_size, return_data) = VM_INSTANCE.with(|vm| {
         let vm = vm.borrow();
-        vm.get_instruction_data()
+        (vm.get_instruction_data(), vm.get_heap_used(), vm.get_heap_size(), vm.get_return_data().data.clone())
     });
 
     let memory = vec![
         Memory {
             label: "instruction_data".to_string(),
             value: format!("{:?}", instruction_data),
-        }
+        },
+        Memory {
+            label: "heap".to_string(),
+            value: format!("{} of {} bytes used", heap_used, heap_size),
+        },
+        Memory {
+            label: "return_data".to_string(),
+            value: format!("{} ({})", to_hex(&return_data), BASE64_STANDARD.encode(&return_data)),
+        },
     ];
     to_value(&memory).unwrap()
 }
 
 #[wasm_bindgen]
-pub fn assemble(assembly: &str, path: &str) -> Result<Vec<u8>, String> {
+pub fn get_return_data() -> Vec<u8> {
+    VM_INSTANCE.with(|vm| vm.borrow().get_return_data().data.clone())
+}
+
+#[wasm_bindgen]
+pub fn get_return_data_program_id() -> Vec<u8> {
+    VM_INSTANCE.with(|vm| vm.borrow().get_return_data().program_id.to_vec())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[wasm_bindgen]
+pub fn get_account_diff() -> Result<JsValue, VmError> {
+    let diff = VM_INSTANCE.with(|vm| vm.borrow().get_account_diff()).map_err(VmError::invalid_argument)?;
+    Ok(to_value(&diff).unwrap())
+}
+
+// Assembles for the configured SBPF version without touching the VM instance
+fn assemble_program(assembly: &str, path: &str) -> Result<Program, VmError> {
+    let sbpf_version = VM_INSTANCE.with(|vm| vm.borrow().get_sbpf_version());
     let file = SimpleFile::new(path.to_string(), assembly.to_string());
     let tokens = match sbpf_assembler::tokenize(assembly) {
         Ok(tokens) => tokens,
-        Err(e) => return Err(format!("Tokenizer error: {}", e)),
+        Err(e) => return Err(VmError::assembly(format!("Tokenizer error: {}", e))),
     };
 
     let mut parser = Parser::new(tokens, &file);
+    parser.set_sbpf_version(sbpf_version);
     let parse_result = match parser.parse() {
         Ok(program) => program,
-        Err(e) => return Err(format!("Parser error: {}", e)),
+        Err(e) => return Err(VmError::assembly(format!("Parser error: {}", e))),
     };
 
-    let program = Program::from_parse_result(parse_result);
+    Ok(Program::from_parse_result(parse_result))
+}
+
+#[wasm_bindgen]
+pub fn assemble(assembly: &str, path: &str) -> Result<Vec<u8>, VmError> {
+    let program = assemble_program(assembly, path)?;
     let mut ro_data = Vec::new();
     if program.has_rodata() {
         ro_data = program.parse_rodata();
@@ -116,16 +179,18 @@ pub fn assemble(assembly: &str, path: &str) -> Result<Vec<u8>, String> {
     let bytecode = program.emit_bytecode();
     let line_map = program.get_line_map();
     let debug_map = program.get_debug_map();
+    let label_map = program.get_label_map();
     VM_INSTANCE.with(|vm| {
         let mut vm = vm.borrow_mut();
         vm.load_line_map(line_map);
         vm.load_debug_map(debug_map);
+        vm.load_label_map(label_map);
     });
     Ok(bytecode)
 }
 
 #[wasm_bindgen]
-pub fn initialize(assembly: &str, path: &str) -> Result<u64, String> {
+pub fn initialize(assembly: &str, path: &str) -> Result<u64, VmError> {
     VM_INSTANCE.with(|vm| {
         let mut vm = vm.borrow_mut();
         vm.reset();
@@ -133,21 +198,81 @@ pub fn initialize(assembly: &str, path: &str) -> Result<u64, String> {
     let bytecode = assemble(assembly, path)?;
     VM_INSTANCE.with(|vm| {
         let mut vm = vm.borrow_mut();
-        vm.load_program(bytecode);
-    });
+        vm.load_program(bytecode)
+    })?;
+    Ok(0)
+}
+
+#[wasm_bindgen]
+pub fn initialize_elf(elf: &[u8]) -> Result<u64, VmError> {
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.reset();
+        vm.load_elf(elf.to_vec())
+    })?;
     Ok(0)
 }
 
 #[wasm_bindgen]
-pub fn load_input_data(account_number: u64, data: &[u8], data_type: &str) {
+pub fn load_input_data(account_number: u64, data: &[u8]) {
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.load_input_data(account_number, data);
+    })
+}
+
+fn parse_program_id(program_id: &[u8]) -> Result<Pubkey, VmError> {
+    program_id.try_into().map_err(|_| VmError::invalid_argument("Program id must be 32 bytes"))
+}
+
+#[wasm_bindgen]
+pub fn load_accounts(accounts: JsValue, instruction_data: &[u8], program_id: &[u8]) -> Result<(), VmError> {
+    let accounts: Vec<Account> = from_value(accounts)
+        .map_err(|e| VmError::invalid_argument(format!("Invalid accounts: {}", e)))?;
+    let program_id = parse_program_id(program_id)?;
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.load_accounts(accounts, instruction_data, program_id);
+    });
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn set_program_id(program_id: &[u8]) -> Result<(), VmError> {
+    let program_id = parse_program_id(program_id)?;
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.set_program_id(program_id);
+    });
+    Ok(())
+}
+
+// Makes an assembled program invokable through CPI
+#[wasm_bindgen]
+pub fn register_program(program_id: &[u8], assembly: &str, path: &str) -> Result<(), VmError> {
+    let bytecode = assemble_program(assembly, path)?.emit_bytecode();
+    register_program_elf(program_id, &bytecode)
+}
+
+#[wasm_bindgen]
+pub fn register_program_elf(program_id: &[u8], elf: &[u8]) -> Result<(), VmError> {
+    let program_id = parse_program_id(program_id)?;
     VM_INSTANCE.with(|vm| {
         let mut vm = vm.borrow_mut();
-        vm.load_input_data(account_number, data, data_type);
+        vm.register_program_elf(program_id, elf.to_vec())
     })
 }
 
 #[wasm_bindgen]
-pub fn run(assembly: &str, path: &str) -> Result<u64, String> {
+pub fn set_instruction_data(data: &[u8]) {
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.set_instruction_data(data);
+    })
+}
+
+#[wasm_bindgen]
+pub fn run(assembly: &str, path: &str) -> Result<u64, VmError> {
     let bytecode = assemble(assembly, path)?;
     VM_INSTANCE.with(|vm| {
         let mut vm = vm.borrow_mut();
@@ -157,14 +282,179 @@ pub fn run(assembly: &str, path: &str) -> Result<u64, String> {
 }
 
 #[wasm_bindgen]
-pub fn step() -> usize {
+pub fn step() -> Result<usize, VmError> {
     VM_INSTANCE.with(|vm| {
         let mut vm = vm.borrow_mut();
-        vm.step_instruction();
-        vm.get_line_number()
+        vm.step_instruction()?;
+        Ok(vm.get_line_number())
     })
 }
 
+fn add_breakpoint(location: BreakpointLocation, condition: Option<String>) -> Result<usize, VmError> {
+    let condition = condition.as_deref().map(str::parse::<Condition>).transpose()
+        .map_err(VmError::invalid_argument)?;
+    Ok(VM_INSTANCE.with(|vm| vm.borrow_mut().add_breakpoint(location, condition)))
+}
+
+// `condition` is e.g. "r3 > 10", returns the breakpoint id
+#[wasm_bindgen]
+pub fn add_line_breakpoint(line: usize, condition: Option<String>) -> Result<usize, VmError> {
+    add_breakpoint(BreakpointLocation::Line(line), condition)
+}
+
+#[wasm_bindgen]
+pub fn add_address_breakpoint(pc: usize, condition: Option<String>) -> Result<usize, VmError> {
+    add_breakpoint(BreakpointLocation::Address(pc), condition)
+}
+
+#[wasm_bindgen]
+pub fn add_label_breakpoint(label: &str, condition: Option<String>) -> Result<usize, VmError> {
+    add_breakpoint(BreakpointLocation::Label(label.to_string()), condition)
+}
+
+#[wasm_bindgen]
+pub fn remove_breakpoint(id: usize) -> bool {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().remove_breakpoint(id))
+}
+
+#[wasm_bindgen]
+pub fn set_breakpoint_enabled(id: usize, enabled: bool) -> bool {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().set_breakpoint_enabled(id, enabled))
+}
+
+#[wasm_bindgen]
+pub fn clear_breakpoints() {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().clear_breakpoints())
+}
+
+// Breakpoints with the pc they resolve to in the loaded program, null for
+// the ones that can never be hit
+#[derive(Serialize)]
+struct ResolvedBreakpoint {
+    #[serde(flatten)]
+    breakpoint: Breakpoint,
+    pc: Option<usize>,
+}
+
+#[wasm_bindgen]
+pub fn get_breakpoints() -> JsValue {
+    let breakpoints: Vec<ResolvedBreakpoint> = VM_INSTANCE.with(|vm| {
+        let vm = vm.borrow();
+        vm.get_breakpoints().into_iter()
+            .map(|breakpoint| ResolvedBreakpoint { pc: vm.resolve_breakpoint(&breakpoint.location), breakpoint })
+            .collect()
+    });
+    to_value(&breakpoints).unwrap()
+}
+
+// `access` is "read", "write" or "readwrite"
+#[wasm_bindgen]
+pub fn add_memory_watchpoint(addr: u64, len: u64, access: &str) -> Result<usize, VmError> {
+    let access = match access {
+        "read" => WatchAccess::Read,
+        "write" => WatchAccess::Write,
+        "readwrite" => WatchAccess::ReadWrite,
+        _ => return Err(VmError::invalid_argument(format!("Invalid watch access: {}", access))),
+    };
+    VM_INSTANCE.with(|vm| vm.borrow_mut().add_watchpoint(Watch::Memory { addr, len, access }))
+        .map_err(VmError::invalid_argument)
+}
+
+// Stops when the register changes, or changes to `value` if given
+#[wasm_bindgen]
+pub fn add_register_watchpoint(register: usize, value: Option<u64>) -> Result<usize, VmError> {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().add_watchpoint(Watch::Register { register, value }))
+        .map_err(VmError::invalid_argument)
+}
+
+#[wasm_bindgen]
+pub fn remove_watchpoint(id: usize) -> bool {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().remove_watchpoint(id))
+}
+
+#[wasm_bindgen]
+pub fn set_watchpoint_enabled(id: usize, enabled: bool) -> bool {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().set_watchpoint_enabled(id, enabled))
+}
+
+#[wasm_bindgen]
+pub fn clear_watchpoints() {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().clear_watchpoints())
+}
+
+#[wasm_bindgen]
+pub fn get_watchpoints() -> JsValue {
+    let watchpoints = VM_INSTANCE.with(|vm| vm.borrow().get_watchpoints());
+    to_value(&watchpoints).unwrap()
+}
+
+// Runs to the next breakpoint, watchpoint or the exit, returns the stop reason
+#[wasm_bindgen]
+pub fn continue_execution() -> Result<JsValue, VmError> {
+    let reason = VM_INSTANCE.with(|vm| vm.borrow_mut().continue_execution())?;
+    Ok(to_value(&reason).unwrap())
+}
+
+// Records an undo log of at most `limit` bytes, 64 MiB if not given
+#[wasm_bindgen]
+pub fn set_history_limit(limit: Option<usize>) {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().set_history_limit(limit.unwrap_or(DEFAULT_HISTORY_LIMIT)))
+}
+
+#[wasm_bindgen]
+pub fn get_history_len() -> usize {
+    VM_INSTANCE.with(|vm| vm.borrow().get_history_len())
+}
+
+#[wasm_bindgen]
+pub fn get_instruction_count() -> u64 {
+    VM_INSTANCE.with(|vm| vm.borrow().get_instruction_count())
+}
+
+// Returns false at the start of the history
+#[wasm_bindgen]
+pub fn step_back() -> bool {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().step_back())
+}
+
+#[wasm_bindgen]
+pub fn reverse_continue() -> JsValue {
+    let reason = VM_INSTANCE.with(|vm| vm.borrow_mut().reverse_continue());
+    to_value(&reason).unwrap()
+}
+
+// Returns the instruction count reached
+#[wasm_bindgen]
+pub fn goto_instruction(count: u64) -> Result<u64, VmError> {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().goto_instruction(count))
+}
+
+#[wasm_bindgen]
+pub fn enable_trace() {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().enable_trace())
+}
+
+// `format` is "jsonl" or "text"
+#[wasm_bindgen]
+pub fn get_trace(format: &str) -> Result<String, VmError> {
+    let format: TraceFormat = format.parse().map_err(VmError::invalid_argument)?;
+    let mut trace = Vec::new();
+    VM_INSTANCE.with(|vm| vm.borrow().write_trace(format, &mut trace))
+        .map_err(|e| VmError::invalid_argument(format!("Failed to write the trace: {}", e)))?;
+    Ok(String::from_utf8(trace).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn save_snapshot() -> Vec<u8> {
+    VM_INSTANCE.with(|vm| vm.borrow().save_snapshot())
+}
+
+// Register syscalls and CPI callees first, snapshots do not include them
+#[wasm_bindgen]
+pub fn restore_snapshot(snapshot: &[u8]) -> Result<(), VmError> {
+    VM_INSTANCE.with(|vm| vm.borrow_mut().restore_snapshot(snapshot)).map_err(VmError::invalid_argument)
+}
+
 #[wasm_bindgen]
 pub fn get_line_number() -> usize {
     VM_INSTANCE.with(|vm| {
@@ -173,6 +463,60 @@ pub fn get_line_number() -> usize {
     })
 }
 
+#[wasm_bindgen]
+pub fn set_sysvars(fixture: &str, format: &str) -> Result<(), VmError> {
+    let sysvars = match format {
+        "json" => Sysvars::from_json(fixture),
+        "toml" => Sysvars::from_toml(fixture),
+        _ => Err(format!("Unsupported sysvar fixture format: {}", format)),
+    }.map_err(VmError::invalid_argument)?;
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.set_sysvars(sysvars);
+    });
+    Ok(())
+}
+
+// `version` is "v0" to "v3", programs assembled afterwards target it
+#[wasm_bindgen]
+pub fn set_sbpf_version(version: &str) -> Result<(), VmError> {
+    let version: SbpfVersion = version.parse().map_err(VmError::invalid_argument)?;
+    VM_INSTANCE.with(|vm| vm.borrow_mut().set_sbpf_version(version));
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn set_heap_size(heap_size: usize) -> Result<(), VmError> {
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.set_heap_size(heap_size)
+    }).map_err(VmError::invalid_argument)
+}
+
+#[wasm_bindgen]
+pub fn set_compute_unit_limit(limit: u64) {
+    VM_INSTANCE.with(|vm| {
+        let mut vm = vm.borrow_mut();
+        vm.set_compute_unit_limit(limit);
+    })
+}
+
+#[wasm_bindgen]
+pub fn get_compute_unit_limit() -> u64 {
+    VM_INSTANCE.with(|vm| {
+        let vm = vm.borrow();
+        vm.get_compute_unit_limit()
+    })
+}
+
+#[wasm_bindgen]
+pub fn get_compute_units_consumed() -> u64 {
+    VM_INSTANCE.with(|vm| {
+        let vm = vm.borrow();
+        vm.get_compute_units_consumed()
+    })
+}
+
 #[wasm_bindgen]
 pub fn is_exited() -> bool {
     VM_INSTANCE.with(|vm| {
@@ -2,7 +2,7 @@ use wasm_bindgen::prelude::*;
 use std::cell::RefCell;
 
 thread_local! {
-    static LOG_BUFFER: RefCell<String> = RefCell::new(String::new());
+    static LOG_BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
 }
 
 #[wasm_bindgen]
@@ -18,4 +18,18 @@ pub fn get_log() -> String {
 pub fn log_message(msg: &str) {
     LOG_BUFFER.with(|buf| buf.borrow_mut().push_str(msg));
     LOG_BUFFER.with(|buf| buf.borrow_mut().push('\n'));
-}
\ No newline at end of file
+}
+
+pub fn get_log_len() -> usize {
+    LOG_BUFFER.with(|buf| buf.borrow().len())
+}
+
+// Drops what was logged after the buffer had `len` bytes, for stepping back
+pub fn truncate_log(len: usize) {
+    LOG_BUFFER.with(|buf| buf.borrow_mut().truncate(len));
+}
+
+// Replaces the whole buffer, for restoring a snapshot
+pub fn set_log(log: &str) {
+    LOG_BUFFER.with(|buf| *buf.borrow_mut() = log.to_string());
+}
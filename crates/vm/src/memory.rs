@@ -0,0 +1,158 @@
+use crate::vm::{PROGRAM_START, STACK_START, HEAP_START, MEMORY_INPUT_DATA_START};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionType {
+    Program,
+    Stack,
+    Heap,
+    Input,
+}
+
+impl fmt::Display for RegionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RegionType::Program => "program",
+            RegionType::Stack => "stack",
+            RegionType::Heap => "heap",
+            RegionType::Input => "input",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessType {
+    Load,
+    Store,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessViolation {
+    pub addr: u64,
+    pub len: u64,
+    // None if the address is not backed by any region
+    pub region: Option<RegionType>,
+    pub access: AccessType,
+}
+
+impl fmt::Display for AccessViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.access {
+            AccessType::Load => "load",
+            AccessType::Store => "store",
+        };
+        let region = self.region.map_or("unmapped".to_string(), |region| region.to_string());
+        write!(f, "Access violation in {} region: {} of {} bytes at 0x{:x}", region, access, self.len, self.addr)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub region_type: RegionType,
+    pub vm_addr: u64,
+    pub data: Vec<u8>,
+    pub writable: bool,
+}
+
+impl MemoryRegion {
+    pub fn new(region_type: RegionType, vm_addr: u64, data: Vec<u8>, writable: bool) -> Self {
+        Self { region_type, vm_addr, data, writable }
+    }
+
+    // Offset of [addr, addr + len) inside this region, if fully contained
+    fn offset_of(&self, addr: u64, len: u64) -> Option<usize> {
+        let offset = addr.checked_sub(self.vm_addr)?;
+        let end = offset.checked_add(len)?;
+        if end > self.data.len() as u64 {
+            return None;
+        }
+        Some(offset as usize)
+    }
+}
+
+// Solana's virtual address space: every region starts on a 4 GiB boundary,
+// so the upper 32 bits of an address select the region.
+#[derive(Debug, Clone)]
+pub struct MemoryMapping {
+    regions: Vec<MemoryRegion>,
+}
+
+impl MemoryMapping {
+    pub fn new(program: Vec<u8>, stack_size: usize, heap_size: usize, input: Vec<u8>) -> Self {
+        Self {
+            regions: vec![
+                MemoryRegion::new(RegionType::Program, PROGRAM_START, program, false),
+                MemoryRegion::new(RegionType::Stack, STACK_START, vec![0u8; stack_size], true),
+                MemoryRegion::new(RegionType::Heap, HEAP_START, vec![0u8; heap_size], true),
+                MemoryRegion::new(RegionType::Input, MEMORY_INPUT_DATA_START, input, true),
+            ],
+        }
+    }
+
+    pub fn region(&self, region_type: RegionType) -> &MemoryRegion {
+        self.regions.iter().find(|region| region.region_type == region_type).unwrap()
+    }
+
+    pub fn region_mut(&mut self, region_type: RegionType) -> &mut MemoryRegion {
+        self.regions.iter_mut().find(|region| region.region_type == region_type).unwrap()
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+
+    fn find_region(&self, addr: u64) -> Option<usize> {
+        self.regions.iter().position(|region| region.vm_addr >> 32 == addr >> 32)
+    }
+
+    pub fn load(&self, addr: u64, len: u64) -> Result<&[u8], AccessViolation> {
+        let violation = |region| AccessViolation { addr, len, region, access: AccessType::Load };
+        let index = self.find_region(addr).ok_or_else(|| violation(None))?;
+        let region = &self.regions[index];
+        let offset = region.offset_of(addr, len).ok_or_else(|| violation(Some(region.region_type)))?;
+        Ok(&region.data[offset..offset + len as usize])
+    }
+
+    pub fn load_mut(&mut self, addr: u64, len: u64) -> Result<&mut [u8], AccessViolation> {
+        let violation = |region| AccessViolation { addr, len, region, access: AccessType::Store };
+        let index = self.find_region(addr).ok_or_else(|| violation(None))?;
+        let region = &mut self.regions[index];
+        if !region.writable {
+            return Err(violation(Some(region.region_type)));
+        }
+        let offset = region.offset_of(addr, len).ok_or_else(|| violation(Some(region.region_type)))?;
+        Ok(&mut region.data[offset..offset + len as usize])
+    }
+
+    pub fn store(&mut self, addr: u64, data: &[u8]) -> Result<(), AccessViolation> {
+        self.load_mut(addr, data.len() as u64)?.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_checks_bounds_and_permissions() {
+        let mut memory = MemoryMapping::new(vec![1, 2, 3, 4], 16, 16, vec![0u8; 8]);
+        assert_eq!(memory.load(PROGRAM_START + 1, 2).unwrap(), &[2, 3]);
+        assert!(memory.load(PROGRAM_START + 3, 2).is_err());
+
+        let err = memory.store(PROGRAM_START, &[0]).unwrap_err();
+        assert_eq!(err.region, Some(RegionType::Program));
+        assert_eq!(err.access, AccessType::Store);
+        assert_eq!(err.to_string(), "Access violation in program region: store of 1 bytes at 0x100000000");
+
+        memory.store(MEMORY_INPUT_DATA_START + 4, &[5, 6, 7, 8]).unwrap();
+        assert_eq!(memory.load(MEMORY_INPUT_DATA_START + 4, 4).unwrap(), &[5, 6, 7, 8]);
+
+        let err = memory.load(0x500000000, 1).unwrap_err();
+        assert_eq!(err, AccessViolation { addr: 0x500000000, len: 1, region: None, access: AccessType::Load });
+        assert_eq!(err.to_string(), "Access violation in unmapped region: load of 1 bytes at 0x500000000");
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::VmError;
+use crate::instruction::InstructionType;
+use crate::memory::AccessType;
+use crate::vm::VMState;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// A load or store by an instruction or a syscall
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u64,
+    pub access: AccessType,
+    // bytes read, or written by a store
+    pub data: Vec<u8>,
+    // what a store overwrote
+    pub old_data: Option<Vec<u8>>,
+}
+
+// Hooks called by the execution engine, for tracing, coverage, breakpoints
+// and the like. `pc` is always the one of the instruction being executed.
+#[allow(unused_variables)]
+pub trait ExecutionObserver {
+    fn before_instruction(&mut self, vm: &VMState, pc: usize, instruction: &InstructionType) {}
+
+    fn after_instruction(&mut self, vm: &VMState, pc: usize, instruction: &InstructionType) {}
+
+    // before the syscall runs, its result is in r0 by after_instruction
+    fn on_syscall(&mut self, vm: &VMState, name: &str, args: [u64; 5]) {}
+
+    fn on_memory_access(&mut self, vm: &VMState, access: &MemoryAccess) {}
+
+    // once the program exits or fails
+    fn on_exit(&mut self, vm: &VMState, result: &Result<u64, VmError>) {}
+}
+
+// Observers attached to a VM, shared so that callers can read their results
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Rc<RefCell<dyn ExecutionObserver>>>);
+
+impl Observers {
+    pub fn add(&mut self, observer: Rc<RefCell<dyn ExecutionObserver>>) {
+        self.0.push(observer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Observers already running a hook are skipped, so memory they read
+    // through the VM is not reported back to them
+    pub fn notify(&self, mut hook: impl FnMut(&mut dyn ExecutionObserver)) {
+        for observer in &self.0 {
+            if let Ok(mut observer) = observer.try_borrow_mut() {
+                hook(&mut *observer);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} observers", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+    use codespan_reporting::files::SimpleFile;
+
+    // Every hook as one line, tagged with the observer's name
+    struct Recorder {
+        name: &'static str,
+        events: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl ExecutionObserver for Recorder {
+        fn before_instruction(&mut self, _vm: &VMState, pc: usize, _instruction: &InstructionType) {
+            self.events.borrow_mut().push(format!("{} before 0x{:x}", self.name, pc));
+        }
+
+        fn after_instruction(&mut self, vm: &VMState, pc: usize, _instruction: &InstructionType) {
+            self.events.borrow_mut().push(format!("{} after 0x{:x} -> 0x{:x}", self.name, pc, vm.pc));
+        }
+
+        fn on_syscall(&mut self, _vm: &VMState, name: &str, args: [u64; 5]) {
+            self.events.borrow_mut().push(format!("{} syscall {} {:?}", self.name, name, args));
+        }
+
+        fn on_memory_access(&mut self, _vm: &VMState, access: &MemoryAccess) {
+            self.events.borrow_mut().push(format!("{} {:?} {:?} over {:?}", self.name, access.access, access.data, access.old_data));
+        }
+
+        fn on_exit(&mut self, _vm: &VMState, result: &Result<u64, VmError>) {
+            self.events.borrow_mut().push(format!("{} exit {:?}", self.name, result));
+        }
+    }
+
+    fn load(source: &str) -> VM {
+        let file = SimpleFile::new("test.s".to_string(), source.to_string());
+        let tokens = sbpf_assembler::tokenize(source).unwrap();
+        let program = sbpf_assembler::Program::from_parse_result(sbpf_assembler::Parser::new(tokens, &file).parse().unwrap());
+        let mut vm = VM::new();
+        vm.load_program(program.emit_bytecode()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_hooks_run_in_execution_order() {
+        let source = ".globl e\ne:\n  mov64 r1, 7\n  stxb [r10-8], r1\n  call sol_log_64_\n  ldxb r0, [r10-8]\n  exit\n";
+        let mut vm = load(source);
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        vm.add_observer(Rc::new(RefCell::new(Recorder { name: "a", events: events.clone() })));
+        vm.add_observer(Rc::new(RefCell::new(Recorder { name: "b", events: events.clone() })));
+        assert_eq!(vm.run().unwrap(), 7);
+
+        // observers are called in the order they were added, memory and
+        // syscall hooks between the before and after of their instruction
+        let e = vm.get_entry_point();
+        let expected = [
+            format!("a before 0x{:x}", e), format!("b before 0x{:x}", e),
+            format!("a after 0x{:x} -> 0x{:x}", e, e + 8), format!("b after 0x{:x} -> 0x{:x}", e, e + 8),
+            format!("a before 0x{:x}", e + 8), format!("b before 0x{:x}", e + 8),
+            "a Store [7] over Some([0])".to_string(), "b Store [7] over Some([0])".to_string(),
+            format!("a after 0x{:x} -> 0x{:x}", e + 8, e + 16), format!("b after 0x{:x} -> 0x{:x}", e + 8, e + 16),
+            format!("a before 0x{:x}", e + 16), format!("b before 0x{:x}", e + 16),
+            "a syscall sol_log_64_ [7, 0, 0, 0, 0]".to_string(), "b syscall sol_log_64_ [7, 0, 0, 0, 0]".to_string(),
+            format!("a after 0x{:x} -> 0x{:x}", e + 16, e + 24), format!("b after 0x{:x} -> 0x{:x}", e + 16, e + 24),
+            format!("a before 0x{:x}", e + 24), format!("b before 0x{:x}", e + 24),
+            "a Load [7] over None".to_string(), "b Load [7] over None".to_string(),
+            format!("a after 0x{:x} -> 0x{:x}", e + 24, e + 32), format!("b after 0x{:x} -> 0x{:x}", e + 24, e + 32),
+            format!("a before 0x{:x}", e + 32), format!("b before 0x{:x}", e + 32),
+            format!("a after 0x{:x} -> 0x{:x}", e + 32, e + 32), format!("b after 0x{:x} -> 0x{:x}", e + 32, e + 32),
+            "a exit Ok(7)".to_string(), "b exit Ok(7)".to_string(),
+        ];
+        assert_eq!(*events.borrow(), expected);
+    }
+
+    #[test]
+    fn test_failure_is_reported_once_after_the_instruction_fails() {
+        let source = ".globl e\ne:\n  mov64 r1, 0\n  div64 r0, r1\n  exit\n";
+        let mut vm = load(source);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        vm.add_observer(Rc::new(RefCell::new(Recorder { name: "a", events: events.clone() })));
+
+        let err = vm.run().unwrap_err();
+        let e = vm.get_entry_point();
+        // no after_instruction for the instruction that failed
+        assert_eq!(events.borrow()[2..], [format!("a before 0x{:x}", e + 8), format!("a exit Err({:?})", err)]);
+    }
+}
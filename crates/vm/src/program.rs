@@ -1,36 +1,93 @@
+use crate::vm::{PROGRAM_START, STACK_START};
+use crate::syscalls::hash_symbol_name;
+use crate::error::VmError;
+use sbpf_assembler::SbpfVersion;
+use std::ops::Range;
+
+// ELF identification and header values
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_BPF: u16 = 247;
+const EM_SBPF: u16 = 263;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const REL_SIZE: usize = 16;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+const SHT_DYNSYM: u32 = 11;
+const STT_FUNC: u8 = 2;
+
+const R_BPF_NONE: u32 = 0;
+const R_BPF_64_64: u32 = 1;
+const R_BPF_64_RELATIVE: u32 = 8;
+const R_BPF_64_32: u32 = 10;
+
+const LDDW_OPCODE: u8 = 0x18;
+const CALL_OPCODE: u8 = 0x85;
+
+// Sections mapped into the program region, like the Solana loader does
+const LOADED_SECTIONS: [&str; 4] = [".text", ".rodata", ".data.rel.ro", ".eh_frame"];
+
+// A loaded program. `bytecode` holds the loaded sections at their virtual
+// addresses and is mapped at PROGRAM_START, so a pc is a virtual address.
 pub struct Program {
     pub bytecode: Vec<u8>,
     pub entry_point: u64,
+    // virtual addresses of .text
+    pub text: Range<u64>,
+    // from e_flags, decides which instructions are valid
+    pub sbpf_version: SbpfVersion,
 }
 
-impl Program {
-    pub fn new(bytecode: Vec<u8>) -> Result<Self, String> {
-        if bytecode.len() < 64 { // Minimum size for ELF header
-            return Err("Invalid bytecode: too short to be an ELF file".to_string());
-        }
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_vaddr: u64,
+    p_memsz: u64,
+}
 
-        // Verify ELF magic number
-        if bytecode[0] != 0x7f || bytecode[1] != 0x45 || bytecode[2] != 0x4c || bytecode[3] != 0x46 {
-            return Err("Invalid bytecode: not an ELF file".to_string());
+struct SectionHeader {
+    name: String,
+    sh_type: u32,
+    sh_addr: u64,
+    sh_offset: u64,
+    sh_size: u64,
+    sh_link: u32,
+}
+
+impl SectionHeader {
+    fn file_range(&self) -> Range<usize> {
+        if self.sh_type == SHT_NOBITS {
+            return 0..0;
         }
+        self.sh_offset as usize..(self.sh_offset + self.sh_size) as usize
+    }
 
-        let mut program = Program { 
-            bytecode, 
-            entry_point: 0 
-        };
-        
-        program.parse_bytecode()?;
-        Ok(program)
+    fn vm_range(&self) -> Range<u64> {
+        self.sh_addr..self.sh_addr + self.sh_size
     }
+}
 
-    fn parse_bytecode(&mut self) -> Result<(), String> {
-        // Parse entry point from ELF header (offset 24-31)
-        self.entry_point = u64::from_le_bytes([
-            self.bytecode[24], self.bytecode[25], self.bytecode[26], self.bytecode[27],
-            self.bytecode[28], self.bytecode[29], self.bytecode[30], self.bytecode[31],
-        ]);
+struct Symbol {
+    name: String,
+    st_info: u8,
+    st_value: u64,
+}
 
-        Ok(())
+impl Program {
+    pub fn new(bytecode: Vec<u8>) -> Result<Self, VmError> {
+        Elf::parse(&bytecode).and_then(|elf| elf.load()).map_err(VmError::elf)
+    }
+
+    // Whether `pc` is the start of an instruction slot in .text
+    pub fn is_instruction(&self, pc: u64) -> bool {
+        self.text.contains(&pc) && (pc - self.text.start) % 8 == 0
     }
 
     pub fn read(&self, address: u64, length: u64) -> Result<Vec<u8>, String> {
@@ -41,3 +98,344 @@ impl Program {
         Ok(data)
     }
 }
+
+struct Elf<'a> {
+    bytes: &'a [u8],
+    entry_point: u64,
+    sbpf_version: SbpfVersion,
+    program_headers: Vec<ProgramHeader>,
+    sections: Vec<SectionHeader>,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, String> {
+        if bytes.len() < ELF_HEADER_SIZE {
+            return Err("Invalid bytecode: too short to be an ELF file".to_string());
+        }
+        if bytes[..4] != *b"\x7fELF" {
+            return Err("Invalid bytecode: not an ELF file".to_string());
+        }
+        if bytes[4] != ELFCLASS64 || bytes[5] != ELFDATA2LSB {
+            return Err("Invalid ELF: expected a 64-bit little endian file".to_string());
+        }
+        let machine = read_u16(bytes, 18)?;
+        if machine != EM_BPF && machine != EM_SBPF {
+            return Err(format!("Invalid ELF: machine {} is not BPF or SBPF", machine));
+        }
+
+        let e_flags = read_u32(bytes, 48)?;
+        let sbpf_version = SbpfVersion::from_e_flags(e_flags)
+            .ok_or_else(|| format!("Invalid ELF: unsupported SBPF version in e_flags 0x{:x}", e_flags))?;
+
+        let mut elf = Elf { bytes, entry_point: read_u64(bytes, 24)?, sbpf_version, program_headers: Vec::new(), sections: Vec::new() };
+        let phoff = read_u64(bytes, 32)?;
+        let phnum = read_u16(bytes, 56)? as usize;
+        let phoff = header_table(bytes, phoff, phnum, PROGRAM_HEADER_SIZE).ok_or("Invalid ELF: program headers are outside the file")?;
+        for i in 0..phnum {
+            let header = phoff + i * PROGRAM_HEADER_SIZE;
+            let (p_offset, p_filesz) = (read_u64(bytes, header + 8)?, read_u64(bytes, header + 32)?);
+            if file_slice(bytes, p_offset, p_filesz).is_none() {
+                return Err(format!("Invalid ELF: program header {} is outside the file", i));
+            }
+            elf.program_headers.push(ProgramHeader {
+                p_type: read_u32(bytes, header)?,
+                p_flags: read_u32(bytes, header + 4)?,
+                p_vaddr: read_u64(bytes, header + 16)?,
+                p_memsz: read_u64(bytes, header + 40)?,
+            });
+        }
+
+        let shoff = read_u64(bytes, 40)?;
+        let shnum = read_u16(bytes, 60)? as usize;
+        let shstrndx = read_u16(bytes, 62)? as usize;
+        let shoff = header_table(bytes, shoff, shnum, SECTION_HEADER_SIZE).ok_or("Invalid ELF: section headers are outside the file")?;
+        let mut name_offsets = Vec::new();
+        for i in 0..shnum {
+            let header = shoff + i * SECTION_HEADER_SIZE;
+            let section = SectionHeader {
+                name: String::new(),
+                sh_type: read_u32(bytes, header + 4)?,
+                sh_addr: read_u64(bytes, header + 16)?,
+                sh_offset: read_u64(bytes, header + 24)?,
+                sh_size: read_u64(bytes, header + 32)?,
+                sh_link: read_u32(bytes, header + 40)?,
+            };
+            if section.sh_type != SHT_NOBITS && file_slice(bytes, section.sh_offset, section.sh_size).is_none() {
+                return Err(format!("Invalid ELF: section {} is outside the file", i));
+            }
+            if section.sh_addr.checked_add(section.sh_size).is_none() {
+                return Err(format!("Invalid ELF: section {} has an invalid address", i));
+            }
+            name_offsets.push(read_u32(bytes, header)?);
+            elf.sections.push(section);
+        }
+        if shnum > 0 {
+            let shstrtab = elf.sections.get(shstrndx)
+                .ok_or_else(|| format!("Invalid ELF: section name table {} does not exist", shstrndx))?
+                .file_range();
+            for (section, name_offset) in elf.sections.iter_mut().zip(name_offsets) {
+                section.name = read_string(&bytes[shstrtab.clone()], name_offset as usize)
+                    .ok_or("Invalid ELF: section name outside the section name table")?;
+            }
+        }
+        Ok(elf)
+    }
+
+    fn section(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    fn load(&self) -> Result<Program, String> {
+        let text = self.section(".text").ok_or("Invalid ELF: no .text section")?;
+        let text_range = text.vm_range();
+        if text_range.is_empty() || text.sh_size % 8 != 0 {
+            return Err("Invalid ELF: .text is empty or not a whole number of instructions".to_string());
+        }
+        if !(text_range.contains(&self.entry_point) && (self.entry_point - text.sh_addr) % 8 == 0) {
+            return Err(format!("Invalid ELF: entry point 0x{:x} is not an instruction in .text", self.entry_point));
+        }
+        // when the file describes its segments, .text has to be executable
+        let loads: Vec<&ProgramHeader> = self.program_headers.iter().filter(|header| header.p_type == PT_LOAD).collect();
+        if !loads.is_empty() && !loads.iter().any(|header| {
+            header.p_flags & PF_X != 0 && header.p_vaddr <= text.sh_addr && text_range.end <= header.p_vaddr.saturating_add(header.p_memsz)
+        }) {
+            return Err("Invalid ELF: .text is not in an executable segment".to_string());
+        }
+        // writable data has nowhere to live in the read-only program region
+        let is_writable = |name: &str| name.starts_with(".bss") || (name.starts_with(".data") && !name.starts_with(".data.rel"));
+        if let Some(section) = self.sections.iter().find(|section| is_writable(&section.name) && section.sh_size > 0) {
+            return Err(format!("Invalid ELF: writable section {} is not supported", section.name));
+        }
+
+        let loaded: Vec<&SectionHeader> = self.sections.iter()
+            .filter(|section| LOADED_SECTIONS.iter().any(|name| section.name.starts_with(name)))
+            .collect();
+        let size = loaded.iter().map(|section| section.vm_range().end).max().unwrap_or(0);
+        if size > STACK_START - PROGRAM_START {
+            return Err(format!("Invalid ELF: sections end at 0x{:x}, past the program region", size));
+        }
+        // sections are laid out as in the file, so a crafted address cannot
+        // make the image much larger than the file itself
+        if size > self.bytes.len() as u64 {
+            return Err(format!("Invalid ELF: sections end at 0x{:x}, past the end of the file", size));
+        }
+        let mut image = vec![0u8; size as usize];
+        for (i, section) in loaded.iter().enumerate() {
+            if loaded[..i].iter().any(|other| other.sh_addr < section.vm_range().end && section.sh_addr < other.vm_range().end) {
+                return Err(format!("Invalid ELF: section {} overlaps another loaded section", section.name));
+            }
+            let data = &self.bytes[section.file_range()];
+            image[section.sh_addr as usize..section.sh_addr as usize + data.len()].copy_from_slice(data);
+        }
+
+        let mut program = Program { bytecode: image, entry_point: self.entry_point, text: text_range, sbpf_version: self.sbpf_version };
+        let relocations = self.sections.iter().filter(|section| section.sh_type == SHT_REL);
+        if let Some(section) = relocations.clone().find(|section| self.sbpf_version.strict_elf() && section.sh_size > 0) {
+            return Err(format!("Invalid ELF: SBPF {} does not allow relocations, found {}", self.sbpf_version, section.name));
+        }
+        for section in relocations {
+            self.relocate(&mut program, section)?;
+        }
+        Ok(program)
+    }
+
+    // Applies the relocations of `rel` to the loaded sections, r_offset is a
+    // virtual address
+    fn relocate(&self, program: &mut Program, rel: &SectionHeader) -> Result<(), String> {
+        let entries = &self.bytes[rel.file_range()];
+        if entries.len() % REL_SIZE != 0 {
+            return Err(format!("Invalid ELF: relocation section {} is not a whole number of entries", rel.name));
+        }
+        for entry in entries.chunks(REL_SIZE) {
+            let r_offset = read_u64(entry, 0)?;
+            let r_type = read_u32(entry, 8)?;
+            let r_sym = read_u32(entry, 12)? as usize;
+            let at = |len: u64| {
+                r_offset.checked_add(len)
+                    .filter(|&end| end <= program.bytecode.len() as u64)
+                    .map(|_| r_offset as usize)
+                    .ok_or_else(|| format!("Invalid ELF: relocation at 0x{:x} is outside the loaded sections", r_offset))
+            };
+            match r_type {
+                R_BPF_NONE => {}
+                R_BPF_64_64 => {
+                    // lddw of a symbol's address, the addend is in the low immediate
+                    let offset = at(16)?;
+                    expect_opcode(&program.bytecode, offset, LDDW_OPCODE, "R_BPF_64_64")?;
+                    let addend = read_u32(&program.bytecode, offset + 4)? as u64;
+                    let symbol = self.symbol(rel, r_sym)?;
+                    write_lddw(&mut program.bytecode, offset, program_address(symbol.st_value.wrapping_add(addend)));
+                }
+                R_BPF_64_RELATIVE if program.text.contains(&r_offset) => {
+                    // lddw splits its 64-bit immediate across the two instruction slots
+                    let offset = at(16)?;
+                    expect_opcode(&program.bytecode, offset, LDDW_OPCODE, "R_BPF_64_RELATIVE")?;
+                    let low = read_u32(&program.bytecode, offset + 4)? as u64;
+                    let high = read_u32(&program.bytecode, offset + 12)? as u64;
+                    write_lddw(&mut program.bytecode, offset, program_address((high << 32) | low));
+                }
+                R_BPF_64_RELATIVE => {
+                    // a pointer in data, older toolchains only wrote the low
+                    // 32 bits of the address, at offset 4
+                    let offset = at(8)?;
+                    let address = read_u32(&program.bytecode, offset + 4)? as u64;
+                    program.bytecode[offset..offset + 8].copy_from_slice(&program_address(address).to_le_bytes());
+                }
+                R_BPF_64_32 => {
+                    let offset = at(8)?;
+                    expect_opcode(&program.bytecode, offset, CALL_OPCODE, "R_BPF_64_32")?;
+                    let symbol = self.symbol(rel, r_sym)?;
+                    if symbol.st_info & 0x0F == STT_FUNC && symbol.st_value != 0 {
+                        // call to a function in this program: make it pc-relative
+                        if !program.is_instruction(symbol.st_value) {
+                            return Err(format!("Invalid ELF: call at 0x{:x} to {} is outside .text", r_offset, symbol.name));
+                        }
+                        let slots = (symbol.st_value as i64 - r_offset as i64) / 8 - 1;
+                        program.bytecode[offset + 1] = (program.bytecode[offset + 1] & 0x0F) | 0x10;
+                        program.bytecode[offset + 4..offset + 8].copy_from_slice(&(slots as i32).to_le_bytes());
+                    } else {
+                        // call to an external symbol: clear src so it dispatches as a
+                        // syscall and point the immediate at the hash of its name
+                        if symbol.name.is_empty() {
+                            return Err(format!("Invalid ELF: call at 0x{:x} to a symbol without a name", r_offset));
+                        }
+                        program.bytecode[offset + 1] &= 0x0F;
+                        program.bytecode[offset + 4..offset + 8].copy_from_slice(&hash_symbol_name(&symbol.name).to_le_bytes());
+                    }
+                }
+                _ => return Err(format!("Invalid ELF: unsupported relocation type {} at 0x{:x}", r_type, r_offset)),
+            }
+        }
+        Ok(())
+    }
+
+    // Symbol `index` of the symbol table a relocation section links to
+    fn symbol(&self, rel: &SectionHeader, index: usize) -> Result<Symbol, String> {
+        let symtab = self.sections.get(rel.sh_link as usize)
+            .filter(|section| section.sh_type == SHT_DYNSYM || section.sh_type == SHT_SYMTAB)
+            .or_else(|| self.sections.iter().find(|section| section.sh_type == SHT_DYNSYM))
+            .ok_or("Invalid ELF: relocation to a symbol without a .dynsym section")?;
+        // sh_link points at the string table holding the symbol names
+        let strtab = self.sections.get(symtab.sh_link as usize)
+            .ok_or_else(|| format!("Invalid ELF: {} links to a missing string table", symtab.name))?;
+        let symbols = &self.bytes[symtab.file_range()];
+        let symbol = index.checked_mul(SYMBOL_SIZE)
+            .and_then(|start| symbols.get(start..start + SYMBOL_SIZE))
+            .ok_or_else(|| format!("Invalid ELF: symbol {} does not exist", index))?;
+        let name = read_string(&self.bytes[strtab.file_range()], read_u32(symbol, 0)? as usize)
+            .ok_or_else(|| format!("Invalid ELF: name of symbol {} is outside the string table", index))?;
+        Ok(Symbol { name, st_info: symbol[4], st_value: read_u64(symbol, 8)? })
+    }
+}
+
+// Addresses below the program region are offsets into it
+fn program_address(address: u64) -> u64 {
+    if address < PROGRAM_START { address + PROGRAM_START } else { address }
+}
+
+fn write_lddw(bytecode: &mut [u8], offset: usize, value: u64) {
+    bytecode[offset + 4..offset + 8].copy_from_slice(&(value as u32).to_le_bytes());
+    bytecode[offset + 12..offset + 16].copy_from_slice(&((value >> 32) as u32).to_le_bytes());
+}
+
+fn expect_opcode(bytecode: &[u8], offset: usize, opcode: u8, relocation: &str) -> Result<(), String> {
+    if bytecode[offset] != opcode {
+        return Err(format!("Invalid ELF: {} relocation at 0x{:x} applies to opcode 0x{:02x}", relocation, offset, bytecode[offset]));
+    }
+    Ok(())
+}
+
+// Offset of a table of `count` headers, if it is inside the file
+fn header_table(bytes: &[u8], offset: u64, count: usize, entry_size: usize) -> Option<usize> {
+    file_slice(bytes, offset, (count * entry_size) as u64).map(|_| offset as usize)
+}
+
+fn file_slice(bytes: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+    let end = offset.checked_add(size)?;
+    bytes.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?)
+}
+
+fn read_string(table: &[u8], offset: usize) -> Option<String> {
+    let name = table.get(offset..)?.split(|&b| b == 0).next()?;
+    Some(String::from_utf8_lossy(name).into_owned())
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], String> {
+    offset.checked_add(N)
+        .and_then(|end| bytes.get(offset..end))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Invalid bytecode: unexpected end of file at offset {}", offset))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    Ok(u16::from_le_bytes(read_bytes(bytes, offset)?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(read_bytes(bytes, offset)?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    Ok(u64::from_le_bytes(read_bytes(bytes, offset)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan_reporting::files::SimpleFile;
+
+    fn assemble(source: &str, version: SbpfVersion) -> Vec<u8> {
+        let file = SimpleFile::new("test.s".to_string(), source.to_string());
+        let tokens = sbpf_assembler::tokenize(source).unwrap();
+        let mut parser = sbpf_assembler::Parser::new(tokens, &file);
+        parser.set_sbpf_version(version);
+        sbpf_assembler::Program::from_parse_result(parser.parse().unwrap()).emit_bytecode()
+    }
+
+    #[test]
+    fn test_load_relocates_text() {
+        let elf = assemble(".globl e\ne:\n  lddw r1, message\n  call sol_log_\n  exit\n.extern sol_log_\n.rodata\nmessage: .ascii \"hi\"\n", SbpfVersion::V0);
+        let program = Program::new(elf.clone()).unwrap();
+        let text = program.text.start as usize;
+        assert_eq!(program.entry_point, program.text.start);
+        assert_eq!(program.text.end - program.text.start, 32);
+        // the lddw points into the program region, the call is a syscall
+        let address = u32::from_le_bytes(program.bytecode[text + 4..text + 8].try_into().unwrap()) as u64
+            | (u32::from_le_bytes(program.bytecode[text + 12..text + 16].try_into().unwrap()) as u64) << 32;
+        assert_eq!(&program.bytecode[(address - PROGRAM_START) as usize..][..2], b"hi");
+        assert_eq!(program.bytecode[text + 17] >> 4, 0);
+        assert_eq!(program.bytecode[text + 20..text + 24], hash_symbol_name("sol_log_").to_le_bytes());
+
+        let mut wrong_machine = elf.clone();
+        wrong_machine[18] = 62;
+        assert!(Program::new(wrong_machine).is_err());
+        assert!(Program::new(elf[..elf.len() - 1].to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_load_bounds_the_image_by_the_file() {
+        let mut elf = assemble(".globl e\ne:\n  lddw r1, message\n  exit\n.rodata\nmessage: .ascii \"hi\"\n", SbpfVersion::V0);
+        let parsed = Elf::parse(&elf).unwrap();
+        let rodata = parsed.sections.iter().position(|section| section.name == ".rodata").unwrap();
+        let shoff = read_u64(&elf, 40).unwrap() as usize;
+        // a .rodata address far past the file would need a 256 MiB image
+        let header = shoff + rodata * SECTION_HEADER_SIZE;
+        elf[header + 16..header + 24].copy_from_slice(&0x1000_0000u64.to_le_bytes());
+        assert_eq!(Program::new(elf).err().unwrap().message(), "Invalid ELF: sections end at 0x10000002, past the end of the file");
+    }
+
+    #[test]
+    fn test_load_reads_sbpf_version() {
+        let source = ".globl e\ne:\n  mov32 r1, 1\n  hor64 r1, 2\n  call sol_log_\n  exit\n";
+        let elf = assemble(source, SbpfVersion::V3);
+        assert_eq!(Program::new(elf.clone()).unwrap().sbpf_version, SbpfVersion::V3);
+
+        let mut unknown = elf.clone();
+        unknown[48] = 9;
+        assert_eq!(Program::new(unknown).err().unwrap().message(), "Invalid ELF: unsupported SBPF version in e_flags 0x9");
+        // v2 resolves the syscall with a relocation, which v3 does not allow
+        let mut relocated = assemble(source, SbpfVersion::V2);
+        relocated[48] = 3;
+        assert!(Program::new(relocated).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use crate::serialization::{Account, Pubkey};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub const SYSTEM_PROGRAM_ID: Pubkey = [0u8; 32];
+
+// Native mocks get the instruction accounts with the privileges of the CPI and
+// change them in place
+pub type NativeProgram = dyn Fn(&Pubkey, &mut [Account], &[u8]) -> Result<(), String>;
+
+#[derive(Clone)]
+pub enum Callee {
+    // assembled from a .s file or loaded from disk
+    Elf(Rc<Vec<u8>>),
+    Native(Rc<NativeProgram>),
+}
+
+// Programs that can be invoked through CPI
+#[derive(Clone)]
+pub struct ProgramRegistry {
+    programs: HashMap<Pubkey, Callee>,
+}
+
+impl ProgramRegistry {
+    pub fn new() -> Self {
+        Self { programs: HashMap::new() }
+    }
+
+    pub fn register_elf(&mut self, program_id: Pubkey, elf: Vec<u8>) {
+        self.programs.insert(program_id, Callee::Elf(Rc::new(elf)));
+    }
+
+    pub fn register_native(&mut self, program_id: Pubkey, program: impl Fn(&Pubkey, &mut [Account], &[u8]) -> Result<(), String> + 'static) {
+        self.programs.insert(program_id, Callee::Native(Rc::new(program)));
+    }
+
+    pub fn get(&self, program_id: &Pubkey) -> Option<Callee> {
+        self.programs.get(program_id).cloned()
+    }
+}
+
+impl Default for ProgramRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register_native(SYSTEM_PROGRAM_ID, system_program);
+        registry
+    }
+}
+
+impl std::fmt::Debug for ProgramRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.programs.keys().map(|key| bs58::encode(key).into_string())).finish()
+    }
+}
+
+// System Program stand-in, only Transfer is supported:
+// u32 instruction index 2 followed by the u64 amount of lamports
+pub fn system_program(_program_id: &Pubkey, accounts: &mut [Account], data: &[u8]) -> Result<(), String> {
+    let lamports = match data {
+        [2, 0, 0, 0, amount @ ..] if amount.len() == 8 => u64::from_le_bytes(amount.try_into().unwrap()),
+        _ => return Err("Unsupported system instruction".to_string()),
+    };
+    let [from, to, ..] = accounts else {
+        return Err("Transfer needs a source and a destination account".to_string());
+    };
+    if !from.is_signer {
+        return Err("Transfer source must sign".to_string());
+    }
+    if !from.data.is_empty() || from.owner != SYSTEM_PROGRAM_ID {
+        return Err("Transfer source must be a system account without data".to_string());
+    }
+    let from_lamports = from.lamports.checked_sub(lamports)
+        .ok_or_else(|| format!("Insufficient lamports: need {}, have {}", lamports, from.lamports))?;
+    let to_lamports = to.lamports.checked_add(lamports).ok_or_else(|| "Arithmetic overflow".to_string())?;
+    from.lamports = from_lamports;
+    to.lamports = to_lamports;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_program_transfer() {
+        let from = Account { key: [1; 32], lamports: 1000, is_signer: true, is_writable: true, ..Account::default() };
+        let to = Account { key: [2; 32], is_writable: true, ..Account::default() };
+        let mut accounts = vec![from, to];
+        let mut data = vec![2, 0, 0, 0];
+        data.extend_from_slice(&300u64.to_le_bytes());
+        system_program(&SYSTEM_PROGRAM_ID, &mut accounts, &data).unwrap();
+        assert_eq!((accounts[0].lamports, accounts[1].lamports), (700, 300));
+
+        data[4..].copy_from_slice(&701u64.to_le_bytes());
+        assert!(system_program(&SYSTEM_PROGRAM_ID, &mut accounts, &data).is_err());
+        accounts[1].lamports = u64::MAX;
+        data[4..].copy_from_slice(&1u64.to_le_bytes());
+        assert_eq!(system_program(&SYSTEM_PROGRAM_ID, &mut accounts, &data).unwrap_err(), "Arithmetic overflow");
+        assert_eq!(accounts[0].lamports, 700);
+        accounts[0].is_signer = false;
+        data[4..].copy_from_slice(&1u64.to_le_bytes());
+        assert!(system_program(&SYSTEM_PROGRAM_ID, &mut accounts, &data).is_err());
+    }
+}
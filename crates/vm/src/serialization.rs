@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+// Room left after each account's data for the program to grow it
+pub const MAX_PERMITTED_DATA_INCREASE: usize = 10 * 1024;
+pub const NON_DUP_MARKER: u8 = u8::MAX;
+const BPF_ALIGN_OF_U128: usize = 8;
+
+pub type Pubkey = [u8; 32];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Account {
+    pub key: Pubkey,
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub executable: bool,
+    pub rent_epoch: u64,
+}
+
+// Serialize accounts, instruction data and program id into the input region
+// layout the BPF loader hands to the entrypoint in r1:
+//
+//   u64 number of accounts
+//   per account, either
+//     u8 index of the first occurrence + 7 bytes padding (duplicate)
+//   or
+//     u8 NON_DUP_MARKER, u8 is_signer, u8 is_writable, u8 executable, 4 bytes padding
+//     32 bytes key, 32 bytes owner, u64 lamports, u64 data length, data
+//     MAX_PERMITTED_DATA_INCREASE bytes padding, aligned to 8
+//     u64 rent epoch
+//   u64 instruction data length, instruction data
+//   32 bytes program id
+pub fn serialize_parameters(accounts: &[Account], instruction_data: &[u8], program_id: &Pubkey) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(&(accounts.len() as u64).to_le_bytes());
+
+    for (i, account) in accounts.iter().enumerate() {
+        if let Some(position) = accounts[..i].iter().position(|other| other.key == account.key) {
+            buffer.push(position as u8);
+            buffer.extend_from_slice(&[0u8; 7]);
+            continue;
+        }
+        buffer.push(NON_DUP_MARKER);
+        buffer.push(account.is_signer as u8);
+        buffer.push(account.is_writable as u8);
+        buffer.push(account.executable as u8);
+        buffer.extend_from_slice(&[0u8; 4]);
+        buffer.extend_from_slice(&account.key);
+        buffer.extend_from_slice(&account.owner);
+        buffer.extend_from_slice(&account.lamports.to_le_bytes());
+        buffer.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(&account.data);
+        buffer.resize(buffer.len() + realloc_padding(account.data.len()), 0);
+        buffer.extend_from_slice(&account.rent_epoch.to_le_bytes());
+    }
+
+    buffer.extend_from_slice(&(instruction_data.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(instruction_data);
+    buffer.extend_from_slice(program_id);
+    buffer
+}
+
+// Read the accounts back out of a serialized input region. `accounts` is the
+// state that was serialized, it determines where each account lives in the
+// buffer. Only lamports, owner and data can be changed by the program.
+pub fn deserialize_parameters(input: &[u8], accounts: &[Account]) -> Result<Vec<Account>, String> {
+    let read = |offset: usize, len: usize| {
+        // `len` can come from the program, e.g. a data_len it overwrote
+        offset.checked_add(len).and_then(|end| input.get(offset..end)).ok_or_else(|| format!("Input region too short to read account at offset {}", offset))
+    };
+    let read_u64 = |offset: usize| read(offset, 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+
+    let mut offset = 8;
+    let mut result: Vec<Account> = Vec::with_capacity(accounts.len());
+    for (i, account) in accounts.iter().enumerate() {
+        if let Some(position) = accounts[..i].iter().position(|other| other.key == account.key) {
+            result.push(result[position].clone());
+            offset += 8;
+            continue;
+        }
+        // dup marker, flags, padding and key
+        offset += 8 + 32;
+        let owner = read(offset, 32)?.try_into().unwrap();
+        offset += 32;
+        let lamports = read_u64(offset)?;
+        offset += 8;
+        let data_len = read_u64(offset)? as usize;
+        offset += 8;
+        let data = read(offset, data_len)?.to_vec();
+        // the layout is fixed by the original length, not the new one
+        offset += account.data.len() + realloc_padding(account.data.len()) + 8;
+        result.push(Account { owner, lamports, data, ..account.clone() });
+    }
+    Ok(result)
+}
+
+// Padding after the account data, keeps the rent epoch 8-byte aligned
+pub fn realloc_padding(data_len: usize) -> usize {
+    MAX_PERMITTED_DATA_INCREASE + (BPF_ALIGN_OF_U128 - data_len % BPF_ALIGN_OF_U128) % BPF_ALIGN_OF_U128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_single_empty_account() {
+        let account = Account { is_writable: true, ..Account::default() };
+        let buffer = serialize_parameters(&[account], &[42, 7], &[9u8; 32]);
+
+        // the offset test_file/fib.s reads its instruction data from
+        let ix_data_offset = 8 + 8 + 80 + 10240 + 8 + 8;
+        assert_eq!(buffer[ix_data_offset - 8], 2);
+        assert_eq!(&buffer[ix_data_offset..ix_data_offset + 2], &[42, 7]);
+        assert_eq!(&buffer[ix_data_offset + 2..], &[9u8; 32]);
+    }
+
+    #[test]
+    fn test_serialize_duplicate_account() {
+        let first = Account { key: [1u8; 32], data: vec![1, 2, 3], ..Account::default() };
+        let second = Account { key: [2u8; 32], ..Account::default() };
+        let buffer = serialize_parameters(&[first.clone(), second, first], &[], &[0u8; 32]);
+
+        let first_len = 8 + 80 + 3 + realloc_padding(3) + 8;
+        let second_len = 8 + 80 + realloc_padding(0) + 8;
+        let dup_offset = 8 + first_len + second_len;
+        assert_eq!(buffer[8], NON_DUP_MARKER);
+        assert_eq!(&buffer[dup_offset..dup_offset + 8], &[0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(buffer.len(), dup_offset + 8 + 8 + 32);
+    }
+
+    #[test]
+    fn test_deserialize_round_trip() {
+        let accounts = vec![
+            Account { key: [1u8; 32], lamports: 10, data: vec![1, 2, 3], is_writable: true, ..Account::default() },
+            Account { key: [2u8; 32], lamports: 5, ..Account::default() },
+        ];
+        let mut buffer = serialize_parameters(&accounts, &[1], &[0u8; 32]);
+        assert_eq!(deserialize_parameters(&buffer, &accounts).unwrap(), accounts);
+
+        // grow the first account by one byte
+        buffer[8 + 8 + 32 + 32 + 8] = 4;
+        buffer[8 + 88 + 3] = 9;
+        let post = deserialize_parameters(&buffer, &accounts).unwrap();
+        assert_eq!(post[0].data, vec![1, 2, 3, 9]);
+        assert_eq!(post[1], accounts[1]);
+
+        buffer[8 + 8 + 32 + 32 + 8..][..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(deserialize_parameters(&buffer, &accounts).is_err());
+    }
+}
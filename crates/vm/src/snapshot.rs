@@ -0,0 +1,508 @@
+use crate::compute_budget::ComputeBudget;
+use crate::memory::{MemoryMapping, RegionType};
+use crate::program::Program;
+use crate::serialization::{Account, Pubkey};
+use crate::sysvar::Sysvars;
+use crate::vm::{CallFrame, Register, ReturnData};
+use sbpf_assembler::debuginfo::{DebugInfo, RegisterHint, RegisterType};
+use sbpf_assembler::SbpfVersion;
+use std::collections::HashMap;
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"HELIOSSN";
+// Bumped whenever the layout below changes, 2 added the .text range and 3
+// the SBPF version
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+// Everything needed to continue a paused program in another VM. Registered
+// syscalls and CPI callees are code, they have to be registered again.
+pub struct Snapshot {
+    pub program: Option<Program>,
+    pub rodata: Option<Vec<(String, usize, String)>>,
+    pub line_map: Option<HashMap<u64, usize>>,
+    pub debug_map: Option<HashMap<u64, DebugInfo>>,
+    pub label_map: Option<HashMap<String, u64>>,
+    pub accounts: Vec<Account>,
+    pub instruction_data: Vec<u8>,
+    pub registers: [Register; 11],
+    pub memory: MemoryMapping,
+    pub call_frames: Vec<CallFrame>,
+    pub compute_budget: ComputeBudget,
+    pub compute_units_consumed: u64,
+    pub sysvars: Sysvars,
+    pub invoke_depth: usize,
+    pub program_id: Pubkey,
+    pub return_data: ReturnData,
+    pub heap_size: usize,
+    pub heap_allocated: u64,
+    pub instruction_count: u64,
+    pub pc: usize,
+    pub exited: bool,
+    pub log: String,
+}
+
+const REGIONS: [RegionType; 4] = [RegionType::Program, RegionType::Stack, RegionType::Heap, RegionType::Input];
+
+// Layout, all integers little endian:
+//
+//   8 bytes SNAPSHOT_MAGIC, u32 SNAPSHOT_VERSION
+//   program (entry point, .text range, SBPF version and image), rodata,
+//   line map, debug map and label map, each a u8 presence flag followed by
+//   the value
+//   accounts, instruction data and program id
+//   registers, memory regions in REGIONS order, call frames
+//   compute budget, consumed units, sysvars as a JSON fixture
+//   invoke depth, return data, heap size and allocation
+//   instruction count, pc, exited flag, program log
+//
+// Byte strings, strings and lists are prefixed with their u64 length.
+impl Snapshot {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.buffer.extend_from_slice(SNAPSHOT_MAGIC);
+        writer.buffer.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+
+        writer.option(&self.program, |writer, program| {
+            writer.u64(program.entry_point);
+            writer.u64(program.text.start);
+            writer.u64(program.text.end);
+            writer.u64(program.sbpf_version.e_flags() as u64);
+            writer.bytes(&program.bytecode);
+        });
+        writer.option(&self.rodata, |writer, rodata| {
+            writer.list(rodata, |writer, (label, offset, value)| {
+                writer.string(label);
+                writer.u64(*offset as u64);
+                writer.string(value);
+            });
+        });
+        writer.option(&self.line_map, |writer, line_map| {
+            writer.list(line_map, |writer, (offset, line)| {
+                writer.u64(*offset);
+                writer.u64(*line as u64);
+            });
+        });
+        writer.option(&self.debug_map, |writer, debug_map| {
+            writer.list(debug_map, |writer, (offset, debug_info)| {
+                writer.u64(*offset);
+                writer.u64(debug_info.line_number as u64);
+                writer.u64(debug_info.register_hint.register as u64);
+                writer.register_type(debug_info.register_hint.register_type);
+            });
+        });
+        writer.option(&self.label_map, |writer, label_map| {
+            writer.list(label_map, |writer, (label, offset)| {
+                writer.string(label);
+                writer.u64(*offset);
+            });
+        });
+
+        writer.list(&self.accounts, |writer, account| {
+            writer.buffer.extend_from_slice(&account.key);
+            writer.buffer.extend_from_slice(&account.owner);
+            writer.u64(account.lamports);
+            writer.bytes(&account.data);
+            writer.bool(account.is_signer);
+            writer.bool(account.is_writable);
+            writer.bool(account.executable);
+            writer.u64(account.rent_epoch);
+        });
+        writer.bytes(&self.instruction_data);
+        writer.buffer.extend_from_slice(&self.program_id);
+
+        for register in &self.registers {
+            writer.register(register);
+        }
+        for region_type in REGIONS {
+            writer.bytes(&self.memory.region(region_type).data);
+        }
+        writer.list(&self.call_frames, |writer, frame| {
+            for register in &frame.saved_registers {
+                writer.register(register);
+            }
+            writer.register(&frame.frame_pointer);
+            writer.u64(frame.return_pc as u64);
+        });
+
+        let budget = &self.compute_budget;
+        for units in [
+            budget.compute_unit_limit, budget.syscall_base_cost, budget.log_64_units, budget.log_pubkey_units,
+            budget.mem_op_base_cost, budget.cpi_bytes_per_unit, budget.sha256_base_cost, budget.sha256_byte_cost,
+            budget.sha256_max_slices, budget.create_program_address_units, budget.sysvar_base_cost,
+            budget.invoke_units, budget.max_invoke_stack_height as u64,
+        ] {
+            writer.u64(units);
+        }
+        writer.u64(self.compute_units_consumed);
+        writer.string(&serde_json::to_string(&self.sysvars).expect("sysvars serialize to JSON"));
+
+        writer.u64(self.invoke_depth as u64);
+        writer.buffer.extend_from_slice(&self.return_data.program_id);
+        writer.bytes(&self.return_data.data);
+        writer.u64(self.heap_size as u64);
+        writer.u64(self.heap_allocated);
+        writer.u64(self.instruction_count);
+        writer.u64(self.pc as u64);
+        writer.bool(self.exited);
+        writer.string(&self.log);
+        writer.buffer
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err("Invalid snapshot: not a helios snapshot".to_string());
+        }
+        let version = u32::from_le_bytes(reader.array()?);
+        if version != SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}, expected {}", version, SNAPSHOT_VERSION));
+        }
+
+        let program = reader.option(|reader| {
+            let entry_point = reader.u64()?;
+            let text = reader.u64()?..reader.u64()?;
+            let sbpf_version = SbpfVersion::from_e_flags(reader.u64()? as u32)
+                .ok_or("Invalid snapshot: unknown SBPF version")?;
+            Ok(Program { bytecode: reader.bytes()?, entry_point, text, sbpf_version })
+        })?;
+        let rodata = reader.option(|reader| {
+            reader.list(|reader| Ok((reader.string()?, reader.u64()? as usize, reader.string()?)))
+        })?;
+        let line_map = reader.option(|reader| reader.list(|reader| Ok((reader.u64()?, reader.u64()? as usize))))?;
+        let debug_map = reader.option(|reader| {
+            reader.list(|reader| {
+                let offset = reader.u64()?;
+                let line_number = reader.u64()? as usize;
+                let register_hint = RegisterHint { register: reader.u64()? as usize, register_type: reader.register_type()? };
+                Ok((offset, DebugInfo { line_number, register_hint }))
+            })
+        })?;
+        let label_map = reader.option(|reader| reader.list(|reader| Ok((reader.string()?, reader.u64()?))))?;
+
+        let accounts = reader.list(|reader| {
+            Ok(Account {
+                key: reader.array()?,
+                owner: reader.array()?,
+                lamports: reader.u64()?,
+                data: reader.bytes()?,
+                is_signer: reader.bool()?,
+                is_writable: reader.bool()?,
+                executable: reader.bool()?,
+                rent_epoch: reader.u64()?,
+            })
+        })?;
+        let instruction_data = reader.bytes()?;
+        let program_id = reader.array()?;
+
+        let registers = reader.registers(0)?;
+        let mut memory = MemoryMapping::new(Vec::new(), 0, 0, Vec::new());
+        for region_type in REGIONS {
+            memory.region_mut(region_type).data = reader.bytes()?;
+        }
+        let call_frames = reader.list(|reader| {
+            Ok(CallFrame {
+                saved_registers: reader.registers(6)?,
+                frame_pointer: reader.register(10)?,
+                return_pc: reader.u64()? as usize,
+            })
+        })?;
+
+        let compute_budget = ComputeBudget {
+            compute_unit_limit: reader.u64()?,
+            syscall_base_cost: reader.u64()?,
+            log_64_units: reader.u64()?,
+            log_pubkey_units: reader.u64()?,
+            mem_op_base_cost: reader.u64()?,
+            cpi_bytes_per_unit: reader.u64()?,
+            sha256_base_cost: reader.u64()?,
+            sha256_byte_cost: reader.u64()?,
+            sha256_max_slices: reader.u64()?,
+            create_program_address_units: reader.u64()?,
+            sysvar_base_cost: reader.u64()?,
+            invoke_units: reader.u64()?,
+            max_invoke_stack_height: reader.u64()? as usize,
+        };
+        let compute_units_consumed = reader.u64()?;
+        let sysvars = Sysvars::from_json(&reader.string()?)?;
+
+        let snapshot = Snapshot {
+            program,
+            rodata,
+            line_map: line_map.map(HashMap::from_iter),
+            debug_map: debug_map.map(HashMap::from_iter),
+            label_map: label_map.map(HashMap::from_iter),
+            accounts,
+            instruction_data,
+            registers,
+            memory,
+            call_frames,
+            compute_budget,
+            compute_units_consumed,
+            sysvars,
+            invoke_depth: reader.u64()? as usize,
+            program_id,
+            return_data: ReturnData { program_id: reader.array()?, data: reader.bytes()? },
+            heap_size: reader.u64()? as usize,
+            heap_allocated: reader.u64()?,
+            instruction_count: reader.u64()?,
+            pc: reader.u64()? as usize,
+            exited: reader.bool()?,
+            log: reader.string()?,
+        };
+        if reader.position != bytes.len() {
+            return Err("Invalid snapshot: trailing bytes".to_string());
+        }
+        Ok(snapshot)
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.buffer.push(value as u8);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+
+    fn register_type(&mut self, register_type: RegisterType) {
+        self.buffer.push(match register_type {
+            RegisterType::Int => 0,
+            RegisterType::Addr => 1,
+            RegisterType::Null => 2,
+        });
+    }
+
+    fn register(&mut self, register: &Register) {
+        self.u64(register.value);
+        self.register_type(register.register_type);
+    }
+
+    fn option<T>(&mut self, value: &Option<T>, write: impl FnOnce(&mut Self, &T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    fn list<I>(&mut self, items: I, mut write: impl FnMut(&mut Self, I::Item))
+    where
+        I: IntoIterator,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let items = items.into_iter();
+        self.u64(items.len() as u64);
+        for item in items {
+            write(self, item);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.position.checked_add(len)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| "Invalid snapshot: unexpected end of file".to_string())?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        match self.array::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [value] => Err(format!("Invalid snapshot: {} is not a boolean", value)),
+        }
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        let len = self.u64()?;
+        // every item takes at least a byte, this keeps corrupt lengths from
+        // allocating
+        usize::try_from(len).ok()
+            .filter(|&len| len <= self.bytes.len() - self.position)
+            .ok_or_else(|| format!("Invalid snapshot: length {} exceeds the file", len))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.len()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?).map_err(|e| format!("Invalid snapshot: {}", e))
+    }
+
+    fn register_type(&mut self) -> Result<RegisterType, String> {
+        match self.array::<1>()? {
+            [0] => Ok(RegisterType::Int),
+            [1] => Ok(RegisterType::Addr),
+            [2] => Ok(RegisterType::Null),
+            [value] => Err(format!("Invalid snapshot: unknown register type {}", value)),
+        }
+    }
+
+    fn register(&mut self, index: usize) -> Result<Register, String> {
+        Ok(Register { name: format!("r{}", index), value: self.u64()?, register_type: self.register_type()? })
+    }
+
+    // N registers named from r<first>
+    fn registers<const N: usize>(&mut self, first: usize) -> Result<[Register; N], String> {
+        let registers = (first..first + N).map(|index| self.register(index)).collect::<Result<Vec<_>, _>>()?;
+        Ok(registers.try_into().unwrap())
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<Option<T>, String> {
+        if self.bool()? { read(self).map(Some) } else { Ok(None) }
+    }
+
+    fn list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let len = self.len()?;
+        (0..len).map(|_| read(self)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_buffer::{clear_log, get_log};
+    use crate::vm::VM;
+    use codespan_reporting::files::SimpleFile;
+
+    fn configured_vm() -> VM {
+        let mut vm = VM::new();
+        vm.set_compute_unit_limit(1234);
+        vm.load_input_data(2, b"seed");
+        vm
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let snapshot = configured_vm().save_snapshot();
+        let mut restored = VM::new();
+        restored.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.get_compute_unit_limit(), 1234);
+        assert_eq!(restored.get_accounts().len(), 2);
+        assert_eq!(restored.get_instruction_data(), b"seed");
+        assert_eq!(restored.save_snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_wrong_version_is_rejected() {
+        let snapshot = configured_vm().save_snapshot();
+        let with_version = |version: u32| {
+            let mut bytes = snapshot.clone();
+            bytes[8..12].copy_from_slice(&version.to_le_bytes());
+            bytes
+        };
+
+        let mut vm = VM::new();
+        assert_eq!(
+            vm.restore_snapshot(&with_version(SNAPSHOT_VERSION + 1)).unwrap_err(),
+            format!("Unsupported snapshot version {}, expected {}", SNAPSHOT_VERSION + 1, SNAPSHOT_VERSION),
+        );
+        assert_eq!(
+            vm.restore_snapshot(&with_version(0)).unwrap_err(),
+            format!("Unsupported snapshot version 0, expected {}", SNAPSHOT_VERSION),
+        );
+        // a rejected snapshot leaves the VM as it was
+        assert_eq!(vm.save_snapshot(), VM::new().save_snapshot());
+    }
+
+    #[test]
+    fn test_corrupted_snapshot_is_rejected() {
+        let snapshot = configured_vm().save_snapshot();
+        let mut vm = VM::new();
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut corrupted = snapshot.clone();
+            corrupted[offset..offset + bytes.len()].copy_from_slice(bytes);
+            corrupted
+        };
+
+        assert_eq!(vm.restore_snapshot(b"").unwrap_err(), "Invalid snapshot: unexpected end of file");
+        assert_eq!(vm.restore_snapshot(&corrupt(0, b"ELF")).unwrap_err(), "Invalid snapshot: not a helios snapshot");
+        for len in SNAPSHOT_MAGIC.len()..snapshot.len() {
+            assert!(vm.restore_snapshot(&snapshot[..len]).is_err(), "truncated to {} bytes", len);
+        }
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert_eq!(vm.restore_snapshot(&trailing).unwrap_err(), "Invalid snapshot: trailing bytes");
+
+        // no program, rodata or maps, so the presence flags come right
+        // after the header, followed by the account count
+        assert_eq!(snapshot[12..17], [0; 5]);
+        assert_eq!(vm.restore_snapshot(&corrupt(12, &[2])).unwrap_err(), "Invalid snapshot: 2 is not a boolean");
+        assert_eq!(
+            vm.restore_snapshot(&corrupt(17, &u64::MAX.to_le_bytes())).unwrap_err(),
+            format!("Invalid snapshot: length {} exceeds the file", u64::MAX),
+        );
+        assert_eq!(vm.save_snapshot(), VM::new().save_snapshot());
+    }
+
+    #[test]
+    fn test_restored_vm_resumes() {
+        let source = "
+.globl e
+e:
+  mov64 r6, 0
+loop:
+  add64 r6, 1
+  stxdw [r10-8], r6
+  mov64 r1, r6
+  call sol_log_64_
+  jlt r6, 3, loop
+  ldxdw r0, [r10-8]
+  exit
+.extern sol_log_64_
+";
+        let file = SimpleFile::new("test.s".to_string(), source.to_string());
+        let tokens = sbpf_assembler::tokenize(source).unwrap();
+        let program = sbpf_assembler::Program::from_parse_result(sbpf_assembler::Parser::new(tokens, &file).parse().unwrap());
+        clear_log();
+        let mut vm = VM::new();
+        vm.load_program(program.emit_bytecode()).unwrap();
+        for _ in 0..7 {
+            vm.step_instruction().unwrap();
+        }
+        let snapshot = vm.save_snapshot();
+        assert_eq!(vm.run().unwrap(), 3);
+        let log = get_log();
+        assert_eq!(log.matches("Program log:").count(), 3);
+        let consumed = vm.get_compute_units_consumed();
+
+        // the log up to the snapshot comes back with it
+        clear_log();
+        let mut restored = VM::new();
+        restored.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.get_instruction_count(), 7);
+        assert_eq!(restored.run().unwrap(), 3);
+        assert_eq!(get_log(), log);
+        assert_eq!(restored.get_compute_units_consumed(), consumed);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::syscalls::Syscall;
+use crate::error::VmError;
+use crate::vm::{VMState, HEAP_START};
+
+const ALIGN: u64 = 8;
+
+// Deprecated bump allocator, frees are ignored and 0 means out of memory
+pub struct SolAllocFree;
+
+impl Syscall for SolAllocFree {
+    fn execute(&self, vm: &mut VMState, [size, free_addr, ..]: [u64; 5]) -> Result<u64, VmError> {
+        if free_addr != 0 {
+            return Ok(0);
+        }
+        let start = vm.heap_allocated.next_multiple_of(ALIGN);
+        match start.checked_add(size) {
+            Some(end) if end <= vm.heap_size as u64 => {
+                vm.heap_allocated = end;
+                Ok(HEAP_START + start)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::MIN_HEAP_FRAME_BYTES;
+
+    #[test]
+    fn test_alloc_aligns_and_runs_out() {
+        let mut vm = VMState::new();
+        assert_eq!(SolAllocFree.execute(&mut vm, [3, 0, 0, 0, 0]).unwrap(), HEAP_START);
+        assert_eq!(SolAllocFree.execute(&mut vm, [8, 0, 0, 0, 0]).unwrap(), HEAP_START + 8);
+        // frees are ignored
+        assert_eq!(SolAllocFree.execute(&mut vm, [8, HEAP_START, 0, 0, 0]).unwrap(), 0);
+        assert_eq!(vm.heap_allocated, 16);
+
+        let rest = MIN_HEAP_FRAME_BYTES as u64 - 16;
+        assert_eq!(SolAllocFree.execute(&mut vm, [rest + 1, 0, 0, 0, 0]).unwrap(), 0);
+        assert_eq!(SolAllocFree.execute(&mut vm, [u64::MAX, 0, 0, 0, 0]).unwrap(), 0);
+        assert_eq!(SolAllocFree.execute(&mut vm, [rest, 0, 0, 0, 0]).unwrap(), HEAP_START + 16);
+        assert_eq!(SolAllocFree.execute(&mut vm, [1, 0, 0, 0, 0]).unwrap(), 0);
+    }
+}
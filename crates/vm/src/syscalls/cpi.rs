@@ -0,0 +1,348 @@
+use crate::diff::diff_accounts;
+use crate::log_buffer::log_message;
+use crate::programs::Callee;
+use crate::serialization::{Account, Pubkey};
+use crate::syscalls::Syscall;
+use crate::syscalls::hash::read_slices;
+use crate::syscalls::pda::{MAX_SEEDS, create_program_address};
+use crate::error::VmError;
+use crate::memory::{AccessType, AccessViolation};
+use crate::vm::{VM, VMState, MEMORY_INPUT_DATA_START};
+
+pub const MAX_SIGNERS: usize = 16;
+pub const MAX_CPI_INSTRUCTION_ACCOUNTS: usize = u8::MAX as usize;
+pub const MAX_CPI_ACCOUNT_INFOS: usize = 128;
+pub const MAX_CPI_INSTRUCTION_DATA_LEN: usize = 10 * 1024;
+
+struct AccountMeta {
+    pubkey: Pubkey,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+struct CpiInstruction {
+    program_id: Pubkey,
+    accounts: Vec<AccountMeta>,
+    data: Vec<u8>,
+}
+
+// An AccountInfo passed by the caller: the account as the caller sees it and
+// where its fields live in the caller's memory
+struct CallerAccount {
+    account: Account,
+    lamports_addr: u64,
+    owner_addr: u64,
+    data_addr: u64,
+    // the length stored next to the data pointer in the AccountInfo
+    data_len_addr: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Abi {
+    C,
+    Rust,
+}
+
+// sol_invoke_signed_c and sol_invoke_signed_rust only differ in the layout of
+// the instruction and account infos
+pub struct SolInvokeSigned {
+    abi: Abi,
+}
+
+impl SolInvokeSigned {
+    pub fn c() -> Self {
+        Self { abi: Abi::C }
+    }
+
+    pub fn rust() -> Self {
+        Self { abi: Abi::Rust }
+    }
+}
+
+// Address of the `len` byte field at `offset` in a struct the program
+// points to, a pointer that wraps around is an access violation
+fn field_addr(addr: u64, offset: u64, len: u64) -> Result<u64, VmError> {
+    addr.checked_add(offset).ok_or_else(|| {
+        AccessViolation { addr, len: offset.saturating_add(len), region: None, access: AccessType::Load }.into()
+    })
+}
+
+fn read_u64(vm: &VMState, addr: u64, offset: u64) -> Result<u64, VmError> {
+    Ok(u64::from_le_bytes(vm.read_memory(field_addr(addr, offset, 8)?, 8)?.try_into().unwrap()))
+}
+
+fn read_bool(vm: &VMState, addr: u64, offset: u64) -> Result<bool, VmError> {
+    Ok(vm.read_memory(field_addr(addr, offset, 1)?, 1)?[0] != 0)
+}
+
+fn read_pubkey(vm: &VMState, addr: u64, offset: u64) -> Result<Pubkey, VmError> {
+    Ok(vm.read_memory(field_addr(addr, offset, 32)?, 32)?.try_into().unwrap())
+}
+
+// Counts are checked before anything is allocated for them
+fn check_count(what: &str, count: u64, max: usize) -> Result<(), VmError> {
+    if count > max as u64 {
+        return Err(VmError::syscall(format!("Too many {}: {} > {}", what, count, max)));
+    }
+    Ok(())
+}
+
+fn encode(pubkey: &Pubkey) -> String {
+    bs58::encode(pubkey).into_string()
+}
+
+impl Abi {
+    // C:    SolInstruction { program_id: *const Pubkey, accounts: *const SolAccountMeta,
+    //                        accounts_len: u64, data: *const u8, data_len: u64 }
+    //       SolAccountMeta { pubkey: *const Pubkey, is_writable: bool, is_signer: bool }, 16 bytes
+    // Rust: StableInstruction { accounts: StableVec<AccountMeta>, data: StableVec<u8>, program_id: Pubkey }
+    //       StableVec is (ptr, cap, len)
+    //       AccountMeta { pubkey: Pubkey, is_signer: bool, is_writable: bool }, 34 bytes
+    fn read_instruction(self, vm: &VMState, addr: u64) -> Result<CpiInstruction, VmError> {
+        let (program_id, accounts_addr, accounts_len, data_addr, data_len) = match self {
+            Abi::C => (
+                read_pubkey(vm, read_u64(vm, addr, 0)?, 0)?,
+                read_u64(vm, addr, 8)?,
+                read_u64(vm, addr, 16)?,
+                read_u64(vm, addr, 24)?,
+                read_u64(vm, addr, 32)?,
+            ),
+            Abi::Rust => (
+                read_pubkey(vm, addr, 48)?,
+                read_u64(vm, addr, 0)?,
+                read_u64(vm, addr, 16)?,
+                read_u64(vm, addr, 24)?,
+                read_u64(vm, addr, 40)?,
+            ),
+        };
+        if data_len > MAX_CPI_INSTRUCTION_DATA_LEN as u64 {
+            return Err(VmError::syscall(format!("Instruction data too large: {} > {}", data_len, MAX_CPI_INSTRUCTION_DATA_LEN)));
+        }
+        check_count("instruction accounts", accounts_len, MAX_CPI_INSTRUCTION_ACCOUNTS)?;
+
+        let mut accounts = Vec::with_capacity(accounts_len as usize);
+        for i in 0..accounts_len {
+            accounts.push(match self {
+                Abi::C => {
+                    let meta = i * 16;
+                    AccountMeta {
+                        pubkey: read_pubkey(vm, read_u64(vm, accounts_addr, meta)?, 0)?,
+                        is_writable: read_bool(vm, accounts_addr, meta + 8)?,
+                        is_signer: read_bool(vm, accounts_addr, meta + 9)?,
+                    }
+                }
+                Abi::Rust => {
+                    let meta = i * 34;
+                    AccountMeta {
+                        pubkey: read_pubkey(vm, accounts_addr, meta)?,
+                        is_signer: read_bool(vm, accounts_addr, meta + 32)?,
+                        is_writable: read_bool(vm, accounts_addr, meta + 33)?,
+                    }
+                }
+            });
+        }
+        let data = vm.read_memory(data_addr, data_len as usize)?.to_vec();
+        Ok(CpiInstruction { program_id, accounts, data })
+    }
+
+    // C:    SolAccountInfo { key: *const Pubkey, lamports: *mut u64, data_len: u64, data: *mut u8,
+    //                        owner: *const Pubkey, rent_epoch: u64, is_signer, is_writable, executable }, 56 bytes
+    // Rust: AccountInfo { key: &Pubkey, lamports: Rc<RefCell<&mut u64>>, data: Rc<RefCell<&mut [u8]>>,
+    //                     owner: &Pubkey, rent_epoch: u64, is_signer, is_writable, executable }, 48 bytes
+    //       the RefCell's value sits after the Rc's strong and weak counts and the borrow flag
+    fn read_account_info(self, vm: &VMState, addr: u64) -> Result<CallerAccount, VmError> {
+        // `rest` is the offset of rent_epoch and the flags after it
+        let (key_addr, lamports_addr, data_addr, data_len_addr, owner_addr, rest) = match self {
+            Abi::C => (
+                read_u64(vm, addr, 0)?,
+                read_u64(vm, addr, 8)?,
+                read_u64(vm, addr, 24)?,
+                field_addr(addr, 16, 8)?,
+                read_u64(vm, addr, 32)?,
+                40,
+            ),
+            Abi::Rust => {
+                let lamports_rc = read_u64(vm, addr, 8)?;
+                let data_rc = read_u64(vm, addr, 16)?;
+                (
+                    read_u64(vm, addr, 0)?,
+                    read_u64(vm, lamports_rc, 24)?,
+                    read_u64(vm, data_rc, 24)?,
+                    field_addr(data_rc, 32, 8)?,
+                    read_u64(vm, addr, 24)?,
+                    32,
+                )
+            }
+        };
+        let data_len = read_u64(vm, data_len_addr, 0)?;
+        let account = Account {
+            key: read_pubkey(vm, key_addr, 0)?,
+            owner: read_pubkey(vm, owner_addr, 0)?,
+            lamports: read_u64(vm, lamports_addr, 0)?,
+            data: vm.read_memory(data_addr, data_len as usize)?.to_vec(),
+            rent_epoch: read_u64(vm, addr, rest)?,
+            is_signer: read_bool(vm, addr, rest + 8)?,
+            is_writable: read_bool(vm, addr, rest + 9)?,
+            executable: read_bool(vm, addr, rest + 10)?,
+        };
+        Ok(CallerAccount { account, lamports_addr, owner_addr, data_addr, data_len_addr })
+    }
+
+    fn account_info_size(self) -> u64 {
+        match self {
+            Abi::C => 56,
+            Abi::Rust => 48,
+        }
+    }
+}
+
+// Signer seeds are &[&[&[u8]]] in both ABIs, each signer must derive a PDA of
+// the caller
+fn read_signers(vm: &VMState, addr: u64, len: u64) -> Result<Vec<Pubkey>, VmError> {
+    check_count("signers", len, MAX_SIGNERS)?;
+    let mut signers = Vec::with_capacity(len as usize);
+    for (seeds_addr, seeds_len) in read_slices(vm, addr, len)? {
+        check_count("seeds for a signer", seeds_len, MAX_SEEDS)?;
+        let mut seeds = Vec::with_capacity(seeds_len as usize);
+        for (ptr, len) in read_slices(vm, seeds_addr, seeds_len)? {
+            seeds.push(vm.read_memory(ptr, len as usize)?);
+        }
+        let signer = create_program_address(&seeds, &vm.program_id)
+            .ok_or_else(|| VmError::syscall("Could not create program address with signer seeds"))?;
+        signers.push(signer);
+    }
+    Ok(signers)
+}
+
+// Runs the callee and checks it only made changes it was allowed to
+fn execute_callee(vm: &mut VMState, instruction: &CpiInstruction, pre: &[Account]) -> Result<Vec<Account>, VmError> {
+    let program_id = instruction.program_id;
+    let callee = vm.programs.get(&program_id)
+        .ok_or_else(|| VmError::syscall(format!("Program {} is not registered", encode(&program_id))))?;
+    let post = match callee {
+        Callee::Native(program) => {
+            let mut post = pre.to_vec();
+            program(&program_id, &mut post, &instruction.data).map_err(VmError::syscall)?;
+            post
+        }
+        Callee::Elf(elf) => VM::invoke(vm, program_id, elf.to_vec(), pre.to_vec(), &instruction.data)?,
+    };
+
+    if let Some(violation) = diff_accounts(pre, &post).violations.first() {
+        return Err(VmError::syscall(violation.to_string()));
+    }
+    for (before, after) in pre.iter().zip(&post) {
+        if before.owner == program_id {
+            continue;
+        }
+        if before.data != after.data {
+            return Err(VmError::syscall(format!("Instruction modified data of account {} it does not own", encode(&before.key))));
+        }
+        if after.lamports < before.lamports {
+            return Err(VmError::syscall(format!("Instruction spent from the balance of account {} it does not own", encode(&before.key))));
+        }
+    }
+    Ok(post)
+}
+
+impl Syscall for SolInvokeSigned {
+    fn execute(&self, vm: &mut VMState, [instruction_addr, account_infos_addr, account_infos_len, signers_seeds_addr, signers_seeds_len]: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(vm.compute_budget.invoke_units)?;
+        if vm.invoke_depth >= vm.compute_budget.max_invoke_stack_height {
+            return Err(VmError::syscall(format!("Max invoke stack height of {} exceeded", vm.compute_budget.max_invoke_stack_height)));
+        }
+
+        let instruction = self.abi.read_instruction(vm, instruction_addr)?;
+        vm.consume_compute_units(instruction.data.len() as u64 / vm.compute_budget.cpi_bytes_per_unit)?;
+        let signers = read_signers(vm, signers_seeds_addr, signers_seeds_len)?;
+
+        check_count("account infos", account_infos_len, MAX_CPI_ACCOUNT_INFOS)?;
+        let mut caller_accounts = Vec::with_capacity(account_infos_len as usize);
+        for i in 0..account_infos_len {
+            let size = self.abi.account_info_size();
+            caller_accounts.push(self.abi.read_account_info(vm, field_addr(account_infos_addr, i * size, size)?)?);
+        }
+
+        // Duplicate metas share the union of their privileges, which must not
+        // exceed what the caller has
+        let mut pre = Vec::with_capacity(instruction.accounts.len());
+        let mut callers = Vec::with_capacity(instruction.accounts.len());
+        for meta in &instruction.accounts {
+            let key = encode(&meta.pubkey);
+            let caller = caller_accounts.iter()
+                .find(|caller| caller.account.key == meta.pubkey)
+                .ok_or_else(|| VmError::syscall(format!("Instruction references account {} without an account info", key)))?;
+            let same_key = instruction.accounts.iter().filter(|other| other.pubkey == meta.pubkey);
+            let (is_signer, is_writable) = same_key.fold((false, false), |(signer, writable), other| {
+                (signer || other.is_signer, writable || other.is_writable)
+            });
+            if is_writable && !caller.account.is_writable {
+                return Err(VmError::syscall(format!("{}'s writable privilege escalated", key)));
+            }
+            if is_signer && !caller.account.is_signer && !signers.contains(&meta.pubkey) {
+                return Err(VmError::syscall(format!("{}'s signer privilege escalated", key)));
+            }
+            pre.push(Account { is_signer, is_writable, ..caller.account.clone() });
+            callers.push(caller);
+        }
+
+        let program_id = encode(&instruction.program_id);
+        log_message(&format!("Program {} invoke [{}]", program_id, vm.invoke_depth + 1));
+        let post = match execute_callee(vm, &instruction, &pre) {
+            Ok(post) => post,
+            Err(e) => {
+                log_message(&format!("Program {} failed: {}", program_id, e));
+                return Err(e);
+            }
+        };
+        log_message(&format!("Program {} success", program_id));
+
+        // Write the writable accounts back through the caller's account infos
+        for (i, (account, caller)) in post.iter().zip(&callers).enumerate() {
+            if !account.is_writable || post[..i].iter().any(|other| other.key == account.key) {
+                continue;
+            }
+            vm.write_memory(caller.lamports_addr, &account.lamports.to_le_bytes())?;
+            vm.write_memory(caller.owner_addr, &account.owner)?;
+            let old_len = caller.account.data.len();
+            let new_len = account.data.len();
+            if new_len != old_len {
+                vm.write_memory(caller.data_len_addr, &(new_len as u64).to_le_bytes())?;
+                // the serialized length right before the data in the input region
+                if caller.data_addr >= MEMORY_INPUT_DATA_START + 8 {
+                    vm.write_memory(caller.data_addr - 8, &(new_len as u64).to_le_bytes())?;
+                }
+            }
+            let mut data = account.data.clone();
+            // zero what the callee truncated
+            data.resize(new_len.max(old_len), 0);
+            vm.write_memory(caller.data_addr, &data)?;
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::STACK_START;
+
+    #[test]
+    fn test_guest_counts_and_pointers_are_checked() {
+        let mut vm = VMState::new();
+        // SolInstruction { program_id, accounts, accounts_len, data, data_len }
+        let instruction = [STACK_START + 64, STACK_START, u64::MAX, STACK_START, 0].map(u64::to_le_bytes).concat();
+        vm.write_memory(STACK_START, &instruction).unwrap();
+        let e = SolInvokeSigned::c().execute(&mut vm, [STACK_START, 0, 0, 0, 0]).unwrap_err();
+        assert_eq!(e.message(), "Too many instruction accounts: 18446744073709551615 > 255");
+
+        let e = SolInvokeSigned::rust().execute(&mut vm, [u64::MAX - 8, 0, 0, 0, 0]).unwrap_err();
+        assert!(matches!(e, VmError::AccessViolation { .. }));
+
+        vm.write_memory(STACK_START + 16, &0u64.to_le_bytes()).unwrap();
+        let e = SolInvokeSigned::c().execute(&mut vm, [STACK_START, 0, 0, 0, u64::MAX]).unwrap_err();
+        assert_eq!(e.message(), "Too many signers: 18446744073709551615 > 16");
+        let e = SolInvokeSigned::c().execute(&mut vm, [STACK_START, 0, u64::MAX, 0, 0]).unwrap_err();
+        assert_eq!(e.message(), "Too many account infos: 18446744073709551615 > 128");
+    }
+}
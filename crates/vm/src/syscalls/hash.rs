@@ -0,0 +1,124 @@
+use crate::syscalls::Syscall;
+use crate::error::VmError;
+use crate::vm::VMState;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+pub const HASH_BYTES: usize = 32;
+
+// Hashes every input slice in order, like a single hash over their concatenation
+pub struct SolHash {
+    name: &'static str,
+    hash: fn(&[Vec<u8>]) -> [u8; HASH_BYTES],
+}
+
+impl SolHash {
+    pub fn sha256() -> Self {
+        Self { name: "sol_sha256", hash: |vals| digest::<Sha256>(vals) }
+    }
+
+    pub fn keccak256() -> Self {
+        Self { name: "sol_keccak256", hash: |vals| digest::<Keccak256>(vals) }
+    }
+
+    pub fn blake3() -> Self {
+        Self {
+            name: "sol_blake3",
+            hash: |vals| {
+                let mut hasher = blake3::Hasher::new();
+                for val in vals {
+                    hasher.update(val);
+                }
+                *hasher.finalize().as_bytes()
+            },
+        }
+    }
+}
+
+fn digest<D: Digest>(vals: &[Vec<u8>]) -> [u8; HASH_BYTES] {
+    let mut hasher = D::new();
+    for val in vals {
+        hasher.update(val);
+    }
+    hasher.finalize().as_slice().try_into().unwrap()
+}
+
+// Reads an array of (ptr: u64, len: u64) pairs, as passed by &[&[u8]]
+pub fn read_slices(vm: &VMState, addr: u64, len: u64) -> Result<Vec<(u64, u64)>, VmError> {
+    let size = len.checked_mul(16).ok_or_else(|| VmError::syscall(format!("Too many slices: {}", len)))?;
+    let bytes = vm.read_memory(addr, size as usize)?;
+    Ok(bytes.chunks_exact(16)
+        .map(|pair| {
+            let ptr = u64::from_le_bytes(pair[..8].try_into().unwrap());
+            let len = u64::from_le_bytes(pair[8..].try_into().unwrap());
+            (ptr, len)
+        })
+        .collect())
+}
+
+impl Syscall for SolHash {
+    fn execute(&self, vm: &mut VMState, [vals_addr, vals_len, result_addr, ..]: [u64; 5]) -> Result<u64, VmError> {
+        let budget = vm.compute_budget;
+        if vals_len > budget.sha256_max_slices {
+            return Err(VmError::syscall(format!("{}: too many slices ({} > {})", self.name, vals_len, budget.sha256_max_slices)));
+        }
+        vm.consume_compute_units(budget.sha256_base_cost)?;
+
+        let mut vals = Vec::with_capacity(vals_len as usize);
+        for (ptr, len) in read_slices(vm, vals_addr, vals_len)? {
+            vm.consume_compute_units(budget.mem_op_base_cost.max(budget.sha256_byte_cost * (len / 2)))?;
+            vals.push(vm.read_memory(ptr, len as usize)?.to_vec());
+        }
+        vm.write_memory(result_addr, &(self.hash)(&vals))?;
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::STACK_START;
+
+    fn hex(bytes: [u8; HASH_BYTES]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_hash_known_vectors() {
+        let vals = vec![b"ab".to_vec(), b"c".to_vec()];
+        assert_eq!(hex((SolHash::sha256().hash)(&vals)), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex((SolHash::keccak256().hash)(&vals)), "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45");
+        assert_eq!(hex((SolHash::blake3().hash)(&vals)), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    }
+
+    #[test]
+    fn test_hash_reads_vm_memory_and_charges_per_slice() {
+        let mut vm = VMState::new();
+        vm.compute_budget.compute_unit_limit = 1_000;
+        let data = STACK_START + 64;
+        vm.write_memory(data, &[7u8; 44]).unwrap();
+        // two slices of 40 and 4 bytes
+        let mut slices = Vec::new();
+        for (ptr, len) in [(data, 40u64), (data + 40, 4)] {
+            slices.extend_from_slice(&ptr.to_le_bytes());
+            slices.extend_from_slice(&len.to_le_bytes());
+        }
+        vm.write_memory(STACK_START, &slices).unwrap();
+
+        let result = STACK_START + 128;
+        SolHash::sha256().execute(&mut vm, [STACK_START, 2, result, 0, 0]).unwrap();
+        assert_eq!(vm.read_memory(result, HASH_BYTES).unwrap(), Sha256::digest([7u8; 44]).as_slice());
+        // base 85, then max(10, len / 2) per slice
+        assert_eq!(vm.compute_units_consumed, 85 + 20 + 10);
+    }
+
+    #[test]
+    fn test_hash_rejects_too_many_slices() {
+        let mut vm = VMState::new();
+        let e = SolHash::keccak256().execute(&mut vm, [STACK_START, 20_001, STACK_START, 0, 0]).unwrap_err();
+        assert_eq!(e.message(), "sol_keccak256: too many slices (20001 > 20000)");
+        assert_eq!(vm.compute_units_consumed, 0);
+        // the byte size of the slice array itself must not overflow
+        assert_eq!(read_slices(&vm, STACK_START, u64::MAX).unwrap_err().message(), format!("Too many slices: {}", u64::MAX));
+    }
+}
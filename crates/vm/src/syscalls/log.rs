@@ -0,0 +1,137 @@
+use crate::syscalls::Syscall;
+use crate::syscalls::hash::read_slices;
+use crate::error::VmError;
+use crate::vm::VMState;
+use crate::log_buffer::log_message;
+use base64::prelude::*;
+
+// Log lines match the ones the Solana runtime writes to the transaction logs
+
+// same bound as the hash syscalls, the runtime only has the memory limit
+pub const MAX_LOG_DATA_SLICES: u64 = 20_000;
+
+fn read_string(vm: &VMState, addr: u64, len: u64) -> Result<String, VmError> {
+    let bytes = vm.read_memory(addr, len as usize)?;
+    std::str::from_utf8(bytes).map(str::to_string).map_err(|_| VmError::syscall("Invalid UTF-8 string"))
+}
+
+pub struct SolLog;
+
+impl Syscall for SolLog {
+    fn execute(&self, vm: &mut VMState, [addr, len, ..]: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(vm.compute_budget.syscall_base_cost.max(len))?;
+        let message = read_string(vm, addr, len)?;
+        log_message(&format!("Program log: {}", message));
+        Ok(0)
+    }
+}
+
+pub struct SolLog64;
+
+impl Syscall for SolLog64 {
+    fn execute(&self, vm: &mut VMState, [arg1, arg2, arg3, arg4, arg5]: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(vm.compute_budget.log_64_units)?;
+        log_message(&format!("Program log: {:#x}, {:#x}, {:#x}, {:#x}, {:#x}", arg1, arg2, arg3, arg4, arg5));
+        Ok(0)
+    }
+}
+
+pub struct SolLogPubkey;
+
+impl Syscall for SolLogPubkey {
+    fn execute(&self, vm: &mut VMState, [pubkey_addr, ..]: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(vm.compute_budget.log_pubkey_units)?;
+        let pubkey = vm.read_memory(pubkey_addr, 32)?;
+        log_message(&format!("Program log: {}", bs58::encode(pubkey).into_string()));
+        Ok(0)
+    }
+}
+
+pub struct SolLogComputeUnits;
+
+impl Syscall for SolLogComputeUnits {
+    fn execute(&self, vm: &mut VMState, _args: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(vm.compute_budget.syscall_base_cost)?;
+        let remaining = vm.compute_budget.compute_unit_limit.saturating_sub(vm.compute_units_consumed);
+        log_message(&format!("Program consumption: {} units remaining", remaining));
+        Ok(0)
+    }
+}
+
+pub struct SolLogData;
+
+impl Syscall for SolLogData {
+    fn execute(&self, vm: &mut VMState, [addr, len, ..]: [u64; 5]) -> Result<u64, VmError> {
+        let cost = vm.compute_budget.syscall_base_cost;
+        vm.consume_compute_units(cost)?;
+        if len > MAX_LOG_DATA_SLICES {
+            return Err(VmError::syscall(format!("sol_log_data: too many slices ({} > {})", len, MAX_LOG_DATA_SLICES)));
+        }
+        vm.consume_compute_units(cost.saturating_mul(len))?;
+        let slices = read_slices(vm, addr, len)?;
+        vm.consume_compute_units(slices.iter().fold(0u64, |total, (_, len)| total.saturating_add(*len)))?;
+
+        let mut fields = Vec::with_capacity(slices.len());
+        for (ptr, len) in slices {
+            fields.push(BASE64_STANDARD.encode(vm.read_memory(ptr, len as usize)?));
+        }
+        log_message(&format!("Program data: {}", fields.join(" ")));
+        Ok(0)
+    }
+}
+
+// Aborts the program with the location passed by the panic handler
+pub struct SolPanic;
+
+impl Syscall for SolPanic {
+    fn execute(&self, vm: &mut VMState, [file_addr, len, line, column, _]: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(len)?;
+        let file = read_string(vm, file_addr, len)?;
+        Err(VmError::syscall(format!("SBF program Panicked in {} at {}:{}", file, line, column)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_buffer::{clear_log, get_log};
+    use crate::vm::STACK_START;
+
+    #[test]
+    fn test_log_formats() {
+        clear_log();
+        let mut vm = VMState::new();
+        vm.write_memory(STACK_START, b"hello").unwrap();
+        SolLog.execute(&mut vm, [STACK_START, 5, 0, 0, 0]).unwrap();
+        SolLog64.execute(&mut vm, [1, 2, 3, 0xff, 5]).unwrap();
+        SolLogPubkey.execute(&mut vm, [STACK_START + 64, 0, 0, 0, 0]).unwrap();
+        SolLogComputeUnits.execute(&mut vm, [0; 5]).unwrap();
+        // two slices, "he" and "llo"
+        let slices = [STACK_START, 2, STACK_START + 2, 3].map(u64::to_le_bytes).concat();
+        vm.write_memory(STACK_START + 128, &slices).unwrap();
+        SolLogData.execute(&mut vm, [STACK_START + 128, 2, 0, 0, 0]).unwrap();
+        assert_eq!(get_log(), "\
+Program log: hello
+Program log: 0x1, 0x2, 0x3, 0xff, 0x5
+Program log: 11111111111111111111111111111111
+Program consumption: 199600 units remaining
+Program data: aGU= bGxv
+");
+
+        let e = SolPanic.execute(&mut vm, [STACK_START, 5, 12, 3, 0]).unwrap_err();
+        assert_eq!(e.message(), "SBF program Panicked in hello at 12:3");
+    }
+
+    #[test]
+    fn test_log_data_bounds_slices() {
+        let mut vm = VMState::new();
+        vm.compute_budget.compute_unit_limit = u64::MAX;
+        let e = SolLogData.execute(&mut vm, [STACK_START, u64::MAX, 0, 0, 0]).unwrap_err();
+        assert_eq!(e.message(), "sol_log_data: too many slices (18446744073709551615 > 20000)");
+        // lengths that overflow when summed are charged, not added up
+        let slices = [STACK_START, u64::MAX, STACK_START, u64::MAX].map(u64::to_le_bytes).concat();
+        vm.write_memory(STACK_START, &slices).unwrap();
+        let e = SolLogData.execute(&mut vm, [STACK_START, 2, 0, 0, 0]).unwrap_err();
+        assert!(matches!(e, VmError::BudgetExceeded { .. }));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::syscalls::Syscall;
+use crate::error::VmError;
+use crate::vm::VMState;
+
+// Memory syscalls cost a base fee or one unit per `cpi_bytes_per_unit` bytes
+fn consume_mem_op(vm: &mut VMState, n: u64) -> Result<(), VmError> {
+    let cost = vm.compute_budget.mem_op_base_cost.max(n / vm.compute_budget.cpi_bytes_per_unit);
+    vm.consume_compute_units(cost)
+}
+
+fn is_nonoverlapping(src: u64, dst: u64, n: u64) -> bool {
+    src.abs_diff(dst) >= n
+}
+
+pub struct SolMemcpy;
+
+impl Syscall for SolMemcpy {
+    fn execute(&self, vm: &mut VMState, [dst, src, n, ..]: [u64; 5]) -> Result<u64, VmError> {
+        consume_mem_op(vm, n)?;
+        if !is_nonoverlapping(src, dst, n) {
+            return Err(VmError::syscall("sol_memcpy_: overlapping copy"));
+        }
+        let data = vm.read_memory(src, n as usize)?.to_vec();
+        vm.write_memory(dst, &data)?;
+        Ok(0)
+    }
+}
+
+pub struct SolMemmove;
+
+impl Syscall for SolMemmove {
+    fn execute(&self, vm: &mut VMState, [dst, src, n, ..]: [u64; 5]) -> Result<u64, VmError> {
+        consume_mem_op(vm, n)?;
+        // the source is read in full first, so overlapping ranges are fine
+        let data = vm.read_memory(src, n as usize)?.to_vec();
+        vm.write_memory(dst, &data)?;
+        Ok(0)
+    }
+}
+
+pub struct SolMemset;
+
+impl Syscall for SolMemset {
+    fn execute(&self, vm: &mut VMState, [dst, c, n, ..]: [u64; 5]) -> Result<u64, VmError> {
+        consume_mem_op(vm, n)?;
+        // check the destination before allocating the fill, `n` comes from the program
+        vm.memory.load_mut(dst, n)?;
+        vm.write_memory(dst, &vec![c as u8; n as usize])?;
+        Ok(0)
+    }
+}
+
+pub struct SolMemcmp;
+
+impl Syscall for SolMemcmp {
+    fn execute(&self, vm: &mut VMState, [s1, s2, n, result_addr, _]: [u64; 5]) -> Result<u64, VmError> {
+        consume_mem_op(vm, n)?;
+        let a = vm.read_memory(s1, n as usize)?;
+        let b = vm.read_memory(s2, n as usize)?;
+        // difference of the first mismatching bytes, written as an i32
+        let result = a.iter().zip(b)
+            .find(|(x, y)| x != y)
+            .map(|(&x, &y)| x as i32 - y as i32)
+            .unwrap_or(0);
+        vm.write_memory(result_addr, &result.to_le_bytes())?;
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{AccessViolation, RegionType};
+    use crate::vm::STACK_START;
+
+    #[test]
+    fn test_memcpy_rejects_overlap() {
+        let mut vm = VMState::new();
+        let e = SolMemcpy.execute(&mut vm, [STACK_START + 4, STACK_START, 8, 0, 0]).unwrap_err();
+        assert_eq!(e.message(), "sol_memcpy_: overlapping copy");
+        assert!(SolMemcpy.execute(&mut vm, [STACK_START + 8, STACK_START, 8, 0, 0]).is_ok());
+    }
+
+    #[test]
+    fn test_memmove_overlap() {
+        let mut vm = VMState::new();
+        vm.write_memory(STACK_START, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        SolMemmove.execute(&mut vm, [STACK_START + 2, STACK_START, 6, 0, 0]).unwrap();
+        assert_eq!(vm.read_memory(STACK_START, 8).unwrap(), &[1, 2, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_memcmp_sign() {
+        let mut vm = VMState::new();
+        vm.write_memory(STACK_START, &[1, 2, 3, 1, 5, 3]).unwrap();
+        let result = |vm: &VMState| i32::from_le_bytes(vm.read_memory(STACK_START + 8, 4).unwrap().try_into().unwrap());
+        SolMemcmp.execute(&mut vm, [STACK_START, STACK_START + 3, 3, STACK_START + 8, 0]).unwrap();
+        assert_eq!(result(&vm), -3);
+        SolMemcmp.execute(&mut vm, [STACK_START + 3, STACK_START, 3, STACK_START + 8, 0]).unwrap();
+        assert_eq!(result(&vm), 3);
+        SolMemcmp.execute(&mut vm, [STACK_START, STACK_START, 3, STACK_START + 8, 0]).unwrap();
+        assert_eq!(result(&vm), 0);
+    }
+
+    #[test]
+    fn test_memset() {
+        let mut vm = VMState::new();
+        SolMemset.execute(&mut vm, [STACK_START + 1, 0x1ab, 3, 0, 0]).unwrap();
+        assert_eq!(vm.read_memory(STACK_START, 5).unwrap(), &[0, 0xab, 0xab, 0xab, 0]);
+
+        // an out of bounds length fails before anything is allocated
+        vm.compute_budget.compute_unit_limit = u64::MAX;
+        let e = SolMemset.execute(&mut vm, [STACK_START, 0, 1 << 40, 0, 0]).unwrap_err();
+        assert!(matches!(e, VmError::AccessViolation { violation: AccessViolation { region: Some(RegionType::Stack), .. }, .. }));
+    }
+}
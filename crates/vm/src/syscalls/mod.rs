@@ -0,0 +1,95 @@
+pub mod log;
+pub mod alloc;
+pub mod memory;
+pub mod hash;
+pub mod pda;
+pub mod sysvar;
+pub mod return_data;
+pub mod cpi;
+
+use crate::error::VmError;
+use crate::vm::VMState;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub trait Syscall {
+    // Arguments are r1-r5, the returned value is written to r0
+    fn execute(&self, vm: &mut VMState, args: [u64; 5]) -> Result<u64, VmError>;
+}
+
+// Syscalls are identified by the murmur3-32 hash of their name, shared with
+// the assembler so relocations and the registry agree
+pub use sbpf_assembler::utils::hash_symbol_name;
+
+#[derive(Clone)]
+pub struct SyscallRegistry {
+    syscalls: HashMap<u32, (String, Rc<dyn Syscall>)>,
+}
+
+impl SyscallRegistry {
+    pub fn new() -> Self {
+        Self { syscalls: HashMap::new() }
+    }
+
+    // Replaces any syscall already registered under the same name
+    pub fn register(&mut self, name: &str, syscall: impl Syscall + 'static) {
+        self.syscalls.insert(hash_symbol_name(name), (name.to_string(), Rc::new(syscall)));
+    }
+
+    pub fn get(&self, hash: u32) -> Option<Rc<dyn Syscall>> {
+        self.syscalls.get(&hash).map(|(_, syscall)| syscall.clone())
+    }
+
+    pub fn name(&self, hash: u32) -> Option<&str> {
+        self.syscalls.get(&hash).map(|(name, _)| name.as_str())
+    }
+}
+
+impl Default for SyscallRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register("sol_log_", log::SolLog);
+        registry.register("sol_log_64_", log::SolLog64);
+        registry.register("sol_log_pubkey", log::SolLogPubkey);
+        registry.register("sol_log_compute_units_", log::SolLogComputeUnits);
+        registry.register("sol_log_data", log::SolLogData);
+        registry.register("sol_panic_", log::SolPanic);
+        registry.register("sol_alloc_free_", alloc::SolAllocFree);
+        registry.register("sol_memcpy_", memory::SolMemcpy);
+        registry.register("sol_memmove_", memory::SolMemmove);
+        registry.register("sol_memset_", memory::SolMemset);
+        registry.register("sol_memcmp_", memory::SolMemcmp);
+        registry.register("sol_sha256", hash::SolHash::sha256());
+        registry.register("sol_keccak256", hash::SolHash::keccak256());
+        registry.register("sol_blake3", hash::SolHash::blake3());
+        registry.register("sol_create_program_address", pda::SolCreateProgramAddress);
+        registry.register("sol_try_find_program_address", pda::SolTryFindProgramAddress);
+        registry.register("sol_get_clock_sysvar", sysvar::SolGetSysvar::clock());
+        registry.register("sol_get_rent_sysvar", sysvar::SolGetSysvar::rent());
+        registry.register("sol_get_epoch_schedule_sysvar", sysvar::SolGetSysvar::epoch_schedule());
+        registry.register("sol_get_fees_sysvar", sysvar::SolGetSysvar::fees());
+        registry.register("sol_set_return_data", return_data::SolSetReturnData);
+        registry.register("sol_get_return_data", return_data::SolGetReturnData);
+        registry.register("sol_invoke_signed_c", cpi::SolInvokeSigned::c());
+        registry.register("sol_invoke_signed_rust", cpi::SolInvokeSigned::rust());
+        registry
+    }
+}
+
+impl std::fmt::Debug for SyscallRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.syscalls.values().map(|(name, _)| name)).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_symbol_name() {
+        // values used by the Solana runtime
+        assert_eq!(hash_symbol_name("sol_log_"), 0x207559bd);
+        assert_eq!(hash_symbol_name("sol_log_64_"), 0x5c2a3178);
+    }
+}
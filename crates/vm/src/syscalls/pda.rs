@@ -0,0 +1,125 @@
+use crate::serialization::Pubkey;
+use crate::syscalls::Syscall;
+use crate::syscalls::hash::read_slices;
+use crate::error::VmError;
+use crate::vm::VMState;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+
+pub const MAX_SEEDS: usize = 16;
+pub const MAX_SEED_LEN: usize = 32;
+const PDA_MARKER: &[u8] = b"ProgramDerivedAddress";
+
+// A program derived address must not be a valid ed25519 public key
+fn is_on_curve(bytes: &[u8; 32]) -> bool {
+    CompressedEdwardsY(*bytes).decompress().is_some()
+}
+
+pub fn create_program_address(seeds: &[&[u8]], program_id: &Pubkey) -> Option<Pubkey> {
+    if seeds.len() > MAX_SEEDS || seeds.iter().any(|seed| seed.len() > MAX_SEED_LEN) {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    for seed in seeds {
+        hasher.update(seed);
+    }
+    hasher.update(program_id);
+    hasher.update(PDA_MARKER);
+    let address: Pubkey = hasher.finalize().into();
+    (!is_on_curve(&address)).then_some(address)
+}
+
+// Tries bump seeds from 255 down to 1 like the runtime, `on_miss` runs for
+// every bump that lands on the curve and can abort the search
+fn try_find_program_address<E>(
+    seeds: &[&[u8]],
+    program_id: &Pubkey,
+    mut on_miss: impl FnMut() -> Result<(), E>,
+) -> Result<Option<(Pubkey, u8)>, E> {
+    for bump in (1..=u8::MAX).rev() {
+        let bump_seed = [bump];
+        let mut seeds_with_bump = seeds.to_vec();
+        seeds_with_bump.push(&bump_seed);
+        if let Some(address) = create_program_address(&seeds_with_bump, program_id) {
+            return Ok(Some((address, bump)));
+        }
+        on_miss()?;
+    }
+    Ok(None)
+}
+
+pub fn find_program_address(seeds: &[&[u8]], program_id: &Pubkey) -> Option<(Pubkey, u8)> {
+    try_find_program_address(seeds, program_id, || Ok::<_, Infallible>(())).unwrap_or_else(|never| match never {})
+}
+
+// Reads the seeds and program id shared by both PDA syscalls
+fn read_inputs(vm: &VMState, seeds_addr: u64, seeds_len: u64, program_id_addr: u64) -> Result<(Vec<Vec<u8>>, Pubkey), VmError> {
+    if seeds_len as usize > MAX_SEEDS {
+        return Err(VmError::syscall(format!("Too many seeds for address generation: {} > {}", seeds_len, MAX_SEEDS)));
+    }
+    let mut seeds = Vec::with_capacity(seeds_len as usize);
+    for (ptr, len) in read_slices(vm, seeds_addr, seeds_len)? {
+        if len as usize > MAX_SEED_LEN {
+            return Err(VmError::syscall(format!("Seed is too long for address generation: {} > {}", len, MAX_SEED_LEN)));
+        }
+        seeds.push(vm.read_memory(ptr, len as usize)?.to_vec());
+    }
+    let program_id = vm.read_memory(program_id_addr, 32)?.try_into().unwrap();
+    Ok((seeds, program_id))
+}
+
+pub struct SolCreateProgramAddress;
+
+impl Syscall for SolCreateProgramAddress {
+    fn execute(&self, vm: &mut VMState, [seeds_addr, seeds_len, program_id_addr, address_addr, _]: [u64; 5]) -> Result<u64, VmError> {
+        vm.consume_compute_units(vm.compute_budget.create_program_address_units)?;
+        let (seeds, program_id) = read_inputs(vm, seeds_addr, seeds_len, program_id_addr)?;
+        let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+        match create_program_address(&seeds, &program_id) {
+            Some(address) => {
+                vm.write_memory(address_addr, &address)?;
+                Ok(0)
+            }
+            None => Ok(1),
+        }
+    }
+}
+
+pub struct SolTryFindProgramAddress;
+
+impl Syscall for SolTryFindProgramAddress {
+    fn execute(&self, vm: &mut VMState, [seeds_addr, seeds_len, program_id_addr, address_addr, bump_seed_addr]: [u64; 5]) -> Result<u64, VmError> {
+        let cost = vm.compute_budget.create_program_address_units;
+        vm.consume_compute_units(cost)?;
+        let (seeds, program_id) = read_inputs(vm, seeds_addr, seeds_len, program_id_addr)?;
+
+        let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+        // every failed attempt is charged like the runtime does
+        match try_find_program_address(&seeds, &program_id, || vm.consume_compute_units(cost))? {
+            Some((address, bump)) => {
+                vm.write_memory(address_addr, &address)?;
+                vm.write_memory(bump_seed_addr, &[bump])?;
+                Ok(0)
+            }
+            None => Ok(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_program_address_is_off_curve() {
+        let program_id = [7u8; 32];
+        let (address, bump) = find_program_address(&[b"vault"], &program_id).unwrap();
+        assert!(!is_on_curve(&address));
+        assert_eq!(create_program_address(&[b"vault", &[bump]], &program_id), Some(address));
+        // bumps above the one found all land on the curve
+        for higher in (bump..=u8::MAX).skip(1) {
+            assert_eq!(create_program_address(&[b"vault", &[higher]], &program_id), None);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::syscalls::Syscall;
+use crate::error::VmError;
+use crate::vm::{VMState, ReturnData};
+
+pub const MAX_RETURN_DATA: usize = 1024;
+
+pub struct SolSetReturnData;
+
+impl Syscall for SolSetReturnData {
+    fn execute(&self, vm: &mut VMState, [addr, len, ..]: [u64; 5]) -> Result<u64, VmError> {
+        let budget = vm.compute_budget;
+        vm.consume_compute_units(len / budget.cpi_bytes_per_unit + budget.syscall_base_cost)?;
+        if len > MAX_RETURN_DATA as u64 {
+            return Err(VmError::syscall(format!("Return data too large ({} > {})", len, MAX_RETURN_DATA)));
+        }
+        let data = if len == 0 { Vec::new() } else { vm.read_memory(addr, len as usize)?.to_vec() };
+        vm.return_data = ReturnData { program_id: vm.program_id, data };
+        Ok(0)
+    }
+}
+
+pub struct SolGetReturnData;
+
+impl Syscall for SolGetReturnData {
+    // copies at most `len` bytes, returns the full length of the return data
+    fn execute(&self, vm: &mut VMState, [addr, len, program_id_addr, ..]: [u64; 5]) -> Result<u64, VmError> {
+        let budget = vm.compute_budget;
+        vm.consume_compute_units(budget.syscall_base_cost)?;
+        let ReturnData { program_id, data } = vm.return_data.clone();
+        let length = (len as usize).min(data.len());
+        if length != 0 {
+            vm.consume_compute_units((length as u64 + 32) / budget.cpi_bytes_per_unit)?;
+            vm.write_memory(addr, &data[..length])?;
+            vm.write_memory(program_id_addr, &program_id)?;
+        }
+        Ok(data.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::STACK_START;
+
+    #[test]
+    fn test_return_data_round_trip() {
+        let mut vm = VMState::new();
+        vm.program_id = [3u8; 32];
+        vm.write_memory(STACK_START, &[1, 2, 3, 4]).unwrap();
+        SolSetReturnData.execute(&mut vm, [STACK_START, 4, 0, 0, 0]).unwrap();
+        assert_eq!(vm.return_data, ReturnData { program_id: [3u8; 32], data: vec![1, 2, 3, 4] });
+
+        // a short buffer gets a prefix, the full length is returned
+        let (data_addr, program_id_addr) = (STACK_START + 64, STACK_START + 128);
+        assert_eq!(SolGetReturnData.execute(&mut vm, [data_addr, 2, program_id_addr, 0, 0]).unwrap(), 4);
+        assert_eq!(vm.read_memory(data_addr, 4).unwrap(), &[1, 2, 0, 0]);
+        assert_eq!(vm.read_memory(program_id_addr, 32).unwrap(), &[3u8; 32]);
+        assert_eq!(SolGetReturnData.execute(&mut vm, [data_addr, 16, program_id_addr, 0, 0]).unwrap(), 4);
+        assert_eq!(vm.read_memory(data_addr, 4).unwrap(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_return_data_is_limited() {
+        let mut vm = VMState::new();
+        assert!(SolSetReturnData.execute(&mut vm, [STACK_START, MAX_RETURN_DATA as u64, 0, 0, 0]).is_ok());
+        let e = SolSetReturnData.execute(&mut vm, [STACK_START, MAX_RETURN_DATA as u64 + 1, 0, 0, 0]).unwrap_err();
+        assert_eq!(e.message(), "Return data too large (1025 > 1024)");
+        assert_eq!(vm.return_data.data.len(), MAX_RETURN_DATA);
+    }
+}
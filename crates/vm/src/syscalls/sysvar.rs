@@ -0,0 +1,36 @@
+use crate::syscalls::Syscall;
+use crate::sysvar::Sysvars;
+use crate::error::VmError;
+use crate::vm::VMState;
+
+// Copies one sysvar from the VM's mock cluster state to `var_addr`
+pub struct SolGetSysvar {
+    bytes: fn(&Sysvars) -> Vec<u8>,
+}
+
+impl SolGetSysvar {
+    pub fn clock() -> Self {
+        Self { bytes: |sysvars| sysvars.clock.to_bytes() }
+    }
+
+    pub fn rent() -> Self {
+        Self { bytes: |sysvars| sysvars.rent.to_bytes() }
+    }
+
+    pub fn epoch_schedule() -> Self {
+        Self { bytes: |sysvars| sysvars.epoch_schedule.to_bytes() }
+    }
+
+    pub fn fees() -> Self {
+        Self { bytes: |sysvars| sysvars.fees.to_bytes() }
+    }
+}
+
+impl Syscall for SolGetSysvar {
+    fn execute(&self, vm: &mut VMState, [var_addr, ..]: [u64; 5]) -> Result<u64, VmError> {
+        let bytes = (self.bytes)(&vm.sysvars);
+        vm.consume_compute_units(vm.compute_budget.sysvar_base_cost + bytes.len() as u64)?;
+        vm.write_memory(var_addr, &bytes)?;
+        Ok(0)
+    }
+}
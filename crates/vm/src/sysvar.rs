@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+// Mock cluster state returned by the sysvar syscalls. Every field is optional
+// in a fixture, missing ones keep their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sysvars {
+    pub clock: Clock,
+    pub rent: Rent,
+    pub epoch_schedule: EpochSchedule,
+    pub fees: Fees,
+}
+
+impl Sysvars {
+    pub fn from_json(fixture: &str) -> Result<Self, String> {
+        serde_json::from_str(fixture).map_err(|e| format!("Invalid sysvar fixture: {}", e))
+    }
+
+    pub fn from_toml(fixture: &str) -> Result<Self, String> {
+        toml::from_str(fixture).map_err(|e| format!("Invalid sysvar fixture: {}", e))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Clock {
+    pub slot: u64,
+    pub epoch_start_timestamp: i64,
+    pub epoch: u64,
+    pub leader_schedule_epoch: u64,
+    pub unix_timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rent {
+    pub lamports_per_byte_year: u64,
+    pub exemption_threshold: f64,
+    pub burn_percent: u8,
+}
+
+impl Default for Rent {
+    fn default() -> Self {
+        Self { lamports_per_byte_year: 3480, exemption_threshold: 2.0, burn_percent: 50 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EpochSchedule {
+    pub slots_per_epoch: u64,
+    pub leader_schedule_slot_offset: u64,
+    pub warmup: bool,
+    pub first_normal_epoch: u64,
+    pub first_normal_slot: u64,
+}
+
+impl Default for EpochSchedule {
+    fn default() -> Self {
+        Self {
+            slots_per_epoch: 432_000,
+            leader_schedule_slot_offset: 432_000,
+            warmup: true,
+            first_normal_epoch: 14,
+            first_normal_slot: 524_256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fees {
+    pub lamports_per_signature: u64,
+}
+
+impl Default for Fees {
+    fn default() -> Self {
+        Self { lamports_per_signature: 5000 }
+    }
+}
+
+// The syscalls copy the #[repr(C)] structs, so the byte layouts below
+// include the same padding
+impl Clock {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(40);
+        bytes.extend_from_slice(&self.slot.to_le_bytes());
+        bytes.extend_from_slice(&self.epoch_start_timestamp.to_le_bytes());
+        bytes.extend_from_slice(&self.epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.leader_schedule_epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.unix_timestamp.to_le_bytes());
+        bytes
+    }
+}
+
+impl Rent {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(24);
+        bytes.extend_from_slice(&self.lamports_per_byte_year.to_le_bytes());
+        bytes.extend_from_slice(&self.exemption_threshold.to_le_bytes());
+        bytes.push(self.burn_percent);
+        bytes.extend_from_slice(&[0u8; 7]);
+        bytes
+    }
+}
+
+impl EpochSchedule {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(40);
+        bytes.extend_from_slice(&self.slots_per_epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.leader_schedule_slot_offset.to_le_bytes());
+        bytes.push(self.warmup as u8);
+        bytes.extend_from_slice(&[0u8; 7]);
+        bytes.extend_from_slice(&self.first_normal_epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.first_normal_slot.to_le_bytes());
+        bytes
+    }
+}
+
+impl Fees {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.lamports_per_signature.to_le_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysvar_layouts() {
+        let sysvars = Sysvars::default();
+        assert_eq!(sysvars.clock.to_bytes().len(), 40);
+        assert_eq!(sysvars.rent.to_bytes().len(), 24);
+        assert_eq!(sysvars.epoch_schedule.to_bytes().len(), 40);
+        assert_eq!(sysvars.fees.to_bytes().len(), 8);
+        assert_eq!(sysvars.epoch_schedule.to_bytes()[16], 1);
+    }
+
+    #[test]
+    fn test_partial_fixture_keeps_defaults() {
+        let sysvars = Sysvars::from_toml("[clock]\nslot = 42\nunix_timestamp = 1700000000\n").unwrap();
+        assert_eq!(sysvars.clock.slot, 42);
+        assert_eq!(sysvars.rent, Rent::default());
+        assert_eq!(Sysvars::from_json(r#"{"clock": {"slot": 42, "unix_timestamp": 1700000000}}"#).unwrap(), sysvars);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::instruction::InstructionType;
+use crate::memory::AccessType;
+use crate::observer::{ExecutionObserver, MemoryAccess};
+use crate::vm::VMState;
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // one JSON object per instruction
+    JsonLines,
+    // the solana-sbpf interpreter trace
+    Text,
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "jsonl" => Ok(TraceFormat::JsonLines),
+            "text" => Ok(TraceFormat::Text),
+            _ => Err(format!("Unsupported trace format: {}, expected jsonl or text", format)),
+        }
+    }
+}
+
+// Registers are the ones the instruction started with
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub count: u64,
+    pub pc: usize,
+    pub registers: [u64; 11],
+    pub memory: Vec<MemoryAccess>,
+}
+
+// Records every executed instruction as an observer of the VM
+#[derive(Debug, Default)]
+pub struct Tracer {
+    entries: Vec<TraceEntry>,
+}
+
+impl Tracer {
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl ExecutionObserver for Tracer {
+    fn before_instruction(&mut self, vm: &VMState, pc: usize, _instruction: &InstructionType) {
+        self.entries.push(TraceEntry {
+            count: vm.instruction_count,
+            pc,
+            registers: std::array::from_fn(|i| vm.registers[i].value),
+            memory: Vec::new(),
+        });
+    }
+
+    fn on_memory_access(&mut self, _vm: &VMState, access: &MemoryAccess) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.memory.push(access.clone());
+        }
+    }
+}
+
+// A trace entry with what the VM knows about its instruction
+pub struct TraceRecord<'a> {
+    pub entry: &'a TraceEntry,
+    // index of the instruction in the text section
+    pub slot: usize,
+    pub line: Option<usize>,
+    pub disassembly: String,
+}
+
+#[derive(Serialize)]
+struct JsonAccess {
+    addr: String,
+    access: AccessType,
+    data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_data: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    count: u64,
+    pc: usize,
+    line: Option<usize>,
+    instruction: &'a str,
+    registers: Vec<String>,
+    memory: Vec<JsonAccess>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn write_trace(records: &[TraceRecord], format: TraceFormat, out: &mut impl Write) -> std::io::Result<()> {
+    for record in records {
+        let entry = record.entry;
+        match format {
+            // same layout as solana-sbpf's disassemble_trace_log, whose
+            // entries are the registers followed by the pc in slots
+            TraceFormat::Text => {
+                let mut registers = [0u64; 12];
+                registers[..11].copy_from_slice(&entry.registers);
+                registers[11] = record.slot as u64;
+                writeln!(out, "{:5?} {:016X?} {:5?}: {}", entry.count, registers, record.slot, record.disassembly)?;
+            }
+            TraceFormat::JsonLines => {
+                let json = JsonRecord {
+                    count: entry.count,
+                    pc: entry.pc,
+                    line: record.line,
+                    instruction: &record.disassembly,
+                    registers: entry.registers.iter().map(|value| format!("0x{:016x}", value)).collect(),
+                    memory: entry.memory.iter()
+                        .map(|access| JsonAccess {
+                            addr: format!("0x{:x}", access.addr),
+                            access: access.access,
+                            data: to_hex(&access.data),
+                            old_data: access.old_data.as_deref().map(to_hex),
+                        })
+                        .collect(),
+                };
+                serde_json::to_writer(&mut *out, &json)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_entry() -> TraceEntry {
+        let mut registers = [0u64; 11];
+        registers[0] = 2;
+        registers[10] = 0x2_0000_1000;
+        let store = MemoryAccess { addr: 0x2_0000_0ff8, access: AccessType::Store, data: vec![2, 0, 0, 0, 0, 0, 0, 0], old_data: Some(vec![0; 8]) };
+        let load = MemoryAccess { addr: 0x4_0000_0000, access: AccessType::Load, data: vec![0xab], old_data: None };
+        TraceEntry { count: 1, pc: 0x1e8, registers, memory: vec![store, load] }
+    }
+
+    fn write(entry: &TraceEntry, format: &str) -> String {
+        let record = TraceRecord { entry, slot: 1, line: Some(5), disassembly: "stxdw [r10-0x8], r0".to_string() };
+        let mut out = Vec::new();
+        write_trace(&[record], format.parse().unwrap(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_text_format_matches_solana_sbpf() {
+        // r0 to r10 and the pc in slots, then the slot and the instruction
+        assert_eq!(write(&store_entry(), "text"), format!(
+            "    1 [0000000000000002, {}0000000200001000, 0000000000000001]     1: stxdw [r10-0x8], r0\n",
+            "0000000000000000, ".repeat(9),
+        ));
+    }
+
+    #[test]
+    fn test_jsonl_format() {
+        let registers = format!("\"0x0000000000000002\",{}\"0x0000000200001000\"", "\"0x0000000000000000\",".repeat(9));
+        assert_eq!(write(&store_entry(), "jsonl"), format!(
+            concat!(
+                r#"{{"count":1,"pc":488,"line":5,"instruction":"stxdw [r10-0x8], r0","registers":[{}],"#,
+                r#""memory":[{{"addr":"0x200000ff8","access":"store","data":"0200000000000000","old_data":"0000000000000000"}},"#,
+                r#"{{"addr":"0x400000000","access":"load","data":"ab"}}]}}"#,
+                "\n",
+            ),
+            registers,
+        ));
+    }
+
+    #[test]
+    fn test_unknown_format_is_rejected() {
+        assert_eq!("json".parse::<TraceFormat>().unwrap_err(), "Unsupported trace format: json, expected jsonl or text");
+    }
+}
@@ -1,17 +1,45 @@
 use crate::program::Program;
-use crate::instruction::{Instruction, decode_instruction};
-use crate::log_buffer::{get_log, log_message};
+use crate::instruction::{Instruction, Next, decode_instruction};
+use crate::log_buffer::{get_log, log_message, set_log};
+use crate::memory::{AccessType, MemoryMapping, RegionType};
+use crate::serialization::{Account, Pubkey, serialize_parameters, deserialize_parameters};
+use crate::diff::{AccountsDiff, diff_accounts};
+use crate::compute_budget::ComputeBudget;
+use crate::error::{Location, VmError};
+use crate::observer::{ExecutionObserver, MemoryAccess, Observers};
+use crate::syscalls::{Syscall, SyscallRegistry};
+use crate::sysvar::Sysvars;
+use crate::programs::ProgramRegistry;
+use crate::breakpoint::{Breakpoint, BreakpointLocation, Breakpoints, Condition, StopReason};
+use crate::watchpoint::{Watch, Watchpoint, Watchpoints};
+use crate::history::History;
+use crate::trace::{TraceFormat, TraceRecord, Tracer, write_trace};
+use crate::disassembler::disassemble;
+use crate::snapshot::Snapshot;
 use sbpf_assembler::debuginfo::DebugInfo;
 use sbpf_assembler::debuginfo::RegisterType;
+use sbpf_assembler::SbpfVersion;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 // Memory layout constants for map-based memory design
-// pub const STACK_START: u64 = 0x200000000;
-// pub const HEAP_START: usize = 0x300000000;
+// read-only program region
+pub const PROGRAM_START: u64 = 0x100000000;
+pub const STACK_START: u64 = 0x200000000;
+pub const HEAP_START: u64 = 0x300000000;
 
 // writable memory region
 pub const MEMORY_INPUT_DATA_START: u64 = 0x400000000;
 
+pub const STACK_FRAME_SIZE: u64 = 4096;
+pub const MAX_CALL_DEPTH: usize = 64;
+pub const STACK_SIZE: usize = STACK_FRAME_SIZE as usize * MAX_CALL_DEPTH;
+// heap size can be raised in 1 KiB steps, like request_heap_frame
+pub const MIN_HEAP_FRAME_BYTES: usize = 32 * 1024;
+pub const MAX_HEAP_FRAME_BYTES: usize = 256 * 1024;
+pub const HEAP_FRAME_BYTES_MULTIPLE: usize = 1024;
+
 #[derive(Debug, Clone)]
 pub struct Register {
     pub name: String,
@@ -25,25 +53,120 @@ pub struct VM {
     rodata: Option<Vec<(String, usize, String)>>,
     line_map: Option<HashMap<u64, usize>>,
     debug_map: Option<HashMap<u64, DebugInfo>>,
+    label_map: Option<HashMap<String, u64>>,
+    breakpoints: Breakpoints,
+    // attached as an observer once the first watchpoint is added
+    watchpoints: Option<Rc<RefCell<Watchpoints>>>,
+    // undo log for stepping back, attached once it is enabled
+    history: Option<Rc<RefCell<History>>>,
+    tracer: Option<Rc<RefCell<Tracer>>>,
+    // serialized into the input region on every reset
+    accounts: Vec<Account>,
+    instruction_data: Vec<u8>,
+    // version .s sources are assembled for
+    sbpf_version: SbpfVersion,
     state: VMState,
 }
 
+// Saved caller state, restored by `exit`
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    // callee-saved registers r6-r9
+    pub saved_registers: [Register; 4],
+    pub frame_pointer: Register,
+    // pc of the instruction after the call
+    pub return_pc: usize,
+}
+
+// Set by sol_set_return_data, tagged with the program that set it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReturnData {
+    pub program_id: Pubkey,
+    pub data: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct VMState {
     pub registers: [Register; 11],
-    pub memory: Vec<u8>,
+    pub memory: MemoryMapping,
+    pub call_frames: Vec<CallFrame>,
+    pub compute_budget: ComputeBudget,
+    pub compute_units_consumed: u64,
+    pub syscalls: SyscallRegistry,
+    pub sysvars: Sysvars,
+    // callees for sol_invoke_signed_*
+    pub programs: ProgramRegistry,
+    // 1 for the top level program, incremented by every CPI
+    pub invoke_depth: usize,
+    // id of the executing program
+    pub program_id: Pubkey,
+    pub return_data: ReturnData,
+    pub heap_size: usize,
+    // bump pointer of sol_alloc_free_, relative to HEAP_START
+    pub heap_allocated: u64,
+    pub observers: Observers,
+    // instructions executed since the start, exit included
+    pub instruction_count: u64,
+    // of the loaded program, decides how r10 moves on calls
+    pub sbpf_version: SbpfVersion,
     // program pointer
     pub pc: usize,
     pub exited: bool,
 }
 
+impl Default for VMState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VMState {
+    pub fn new() -> Self {
+        let mut state = VMState {
+            registers: std::array::from_fn(|i| Register {
+                name: format!("r{}", i),
+                value: 0,
+                register_type: RegisterType::Null,
+            }),
+            memory: MemoryMapping::new(Vec::new(), STACK_SIZE, MIN_HEAP_FRAME_BYTES, Vec::new()),
+            call_frames: Vec::new(),
+            compute_budget: ComputeBudget::default(),
+            compute_units_consumed: 0,
+            syscalls: SyscallRegistry::default(),
+            sysvars: Sysvars::default(),
+            programs: ProgramRegistry::default(),
+            invoke_depth: 1,
+            program_id: [0u8; 32],
+            return_data: ReturnData::default(),
+            heap_size: MIN_HEAP_FRAME_BYTES,
+            heap_allocated: 0,
+            observers: Observers::default(),
+            instruction_count: 0,
+            sbpf_version: SbpfVersion::default(),
+            pc: 0,
+            exited: false,
+        };
+        state.reset();
+        state
+    }
+
     pub fn exit(&mut self) {
         log_message(&format!("{}", self.registers[0].value));
         self.exited = true;
     }
 
+    // Top of the first stack frame, or of the whole stack when the program
+    // moves r10 itself
+    fn initial_stack_pointer(&self) -> u64 {
+        if self.sbpf_version.dynamic_stack_frames() {
+            STACK_START + STACK_SIZE as u64
+        } else {
+            STACK_START + STACK_FRAME_SIZE
+        }
+    }
+
     pub fn reset(&mut self) {
+        let stack_pointer = self.initial_stack_pointer();
         self.registers = [
             // initialze r0 to 0 (true)
             Register { name: "r0".to_string(), value: 0, register_type: RegisterType::Int },
@@ -58,10 +181,17 @@ impl VMState {
             Register { name: "r7".to_string(), value: 0, register_type: RegisterType::Null },
             Register { name: "r8".to_string(), value: 0, register_type: RegisterType::Null },
             Register { name: "r9".to_string(), value: 0, register_type: RegisterType::Null },
-            Register { name: "r10".to_string(), value: 0, register_type: RegisterType::Null },
+            // initialize r10 to the top of the stack
+            Register { name: "r10".to_string(), value: stack_pointer, register_type: RegisterType::Addr },
         ];
-        // figure out memory size
-        self.memory = vec![0u8; 20000];
+        // keep the loaded program, start from clean stack, heap and input
+        let program = std::mem::take(&mut self.memory.region_mut(RegionType::Program).data);
+        self.memory = MemoryMapping::new(program, STACK_SIZE, self.heap_size, Vec::new());
+        self.heap_allocated = 0;
+        self.return_data = ReturnData::default();
+        self.call_frames.clear();
+        self.compute_units_consumed = 0;
+        self.instruction_count = 0;
         self.pc = 0;
         self.exited = false;
     }
@@ -74,41 +204,118 @@ impl VMState {
             self.registers[register].register_type = register_type;
         }
     }
+
+    pub fn consume_compute_units(&mut self, units: u64) -> Result<(), VmError> {
+        let limit = self.compute_budget.compute_unit_limit;
+        if units > limit.saturating_sub(self.compute_units_consumed) {
+            self.compute_units_consumed = limit;
+            return Err(VmError::BudgetExceeded { limit, location: None });
+        }
+        self.compute_units_consumed += units;
+        Ok(())
+    }
+
+    pub fn push_call_frame(&mut self) -> Result<(), VmError> {
+        // the outermost frame counts towards the limit
+        if self.call_frames.len() + 1 >= MAX_CALL_DEPTH {
+            return Err(VmError::CallDepthExceeded { max: MAX_CALL_DEPTH, location: None });
+        }
+        self.call_frames.push(CallFrame {
+            saved_registers: [
+                self.registers[6].clone(),
+                self.registers[7].clone(),
+                self.registers[8].clone(),
+                self.registers[9].clone(),
+            ],
+            frame_pointer: self.registers[10].clone(),
+            return_pc: self.pc + 8,
+        });
+        // each call gets a fresh stack frame, unless the program moves r10 itself
+        if !self.sbpf_version.dynamic_stack_frames() {
+            self.registers[10].value += STACK_FRAME_SIZE;
+        }
+        Ok(())
+    }
+
+    // Returns the pc to return to, None if there is no caller
+    pub fn pop_call_frame(&mut self) -> Option<usize> {
+        let frame = self.call_frames.pop()?;
+        let [r6, r7, r8, r9] = frame.saved_registers;
+        self.registers[6] = r6;
+        self.registers[7] = r7;
+        self.registers[8] = r8;
+        self.registers[9] = r9;
+        self.registers[10] = frame.frame_pointer;
+        Some(frame.return_pc)
+    }
+
+    pub fn read_memory(&self, addr: u64, len: usize) -> Result<&[u8], VmError> {
+        // empty slices are valid at any address, like null pointers with no length
+        if len == 0 {
+            return Ok(&[]);
+        }
+        let data = self.memory.load(addr, len as u64)?;
+        if !self.observers.is_empty() {
+            let access = MemoryAccess { addr, access: AccessType::Load, data: data.to_vec(), old_data: None };
+            self.observers.notify(|observer| observer.on_memory_access(self, &access));
+        }
+        Ok(data)
+    }
+
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<(), VmError> {
+        if self.observers.is_empty() {
+            return Ok(self.memory.store(addr, data)?);
+        }
+        let old_data = self.memory.load_mut(addr, data.len() as u64)?.to_vec();
+        self.memory.store(addr, data)?;
+        let access = MemoryAccess { addr, access: AccessType::Store, data: data.to_vec(), old_data: Some(old_data) };
+        self.observers.notify(|observer| observer.on_memory_access(self, &access));
+        Ok(())
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     pub fn new() -> Self {
-        VM {
-            // MISC TODO : self.reset()
-            state: VMState {
-                registers: [
-                    Register { name: "r0".to_string(), value: 0, register_type: RegisterType::Int },
-                    // r1 points to the start of the memory
-                    Register { name: "r1".to_string(), value: MEMORY_INPUT_DATA_START, register_type: RegisterType::Addr },
-                    Register { name: "r2".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r3".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r4".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r5".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r6".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r7".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r8".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r9".to_string(), value: 0, register_type: RegisterType::Null },
-                    Register { name: "r10".to_string(), value: 0, register_type: RegisterType::Null },
-                ],
-                memory: vec![0u8; 20000],
-                pc: 0,
-                exited: false,
-            },
+        let mut vm = VM {
+            state: VMState::new(),
             program: None,
             entry_point: None,
             rodata: None,
             line_map: None,
             debug_map: None,
-        }
+            label_map: None,
+            breakpoints: Breakpoints::default(),
+            watchpoints: None,
+            history: None,
+            tracer: None,
+            accounts: Vec::new(),
+            instruction_data: Vec::new(),
+            sbpf_version: SbpfVersion::default(),
+        };
+        vm.serialize_input();
+        vm
     }
 
     pub fn reset(&mut self) {
         self.state.reset();
+        self.serialize_input();
+        if let Some(history) = &self.history {
+            history.borrow_mut().clear();
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.borrow_mut().clear();
+        }
+    }
+
+    fn serialize_input(&mut self) {
+        let input = serialize_parameters(&self.accounts, &self.instruction_data, &self.state.program_id);
+        self.state.memory.region_mut(RegionType::Input).data = input;
     }
 
     pub fn load_rodata(&mut self, rodata: Vec<(String, usize, String)>) {
@@ -123,91 +330,513 @@ impl VM {
         self.debug_map = Some(debug_map);
     }
 
-    pub fn load_program(&mut self, bytecode: Vec<u8>) -> Result<(), String> {
+    pub fn load_label_map(&mut self, label_map: HashMap<String, u64>) {
+        self.label_map = Some(label_map);
+    }
+
+    pub fn load_program(&mut self, bytecode: Vec<u8>) -> Result<(), VmError> {
         let program = Program::new(bytecode)?;
+        self.state.memory.region_mut(RegionType::Program).data = program.bytecode.clone();
+        self.state.sbpf_version = program.sbpf_version;
+        self.state.registers[10].value = self.state.initial_stack_pointer();
         self.program = Some(program);
         self.entry_point = Some(self.program.as_ref().unwrap().entry_point as usize);
         self.state.pc = self.entry_point.unwrap();
         Ok(())
     }
 
-    pub fn load_input_data(&mut self, account_number: u64, data: &[u8], data_type: &str) {
-        // hard code account number to 0
-        let start_addr = 8;
-        self.state.memory[8] = data.len() as u8;
-        self.state.memory[start_addr + 8 .. start_addr + 8 + data.len()].copy_from_slice(data);
+    // Loads a compiled program, e.g. from cargo build-sbf, which has no
+    // source to map its instructions back to
+    pub fn load_elf(&mut self, elf: Vec<u8>) -> Result<(), VmError> {
+        self.rodata = Some(Vec::new());
+        self.line_map = None;
+        self.debug_map = None;
+        self.label_map = None;
+        self.load_program(elf)
+    }
+
+    pub fn set_sbpf_version(&mut self, version: SbpfVersion) {
+        self.sbpf_version = version;
+    }
+
+    pub fn get_sbpf_version(&self) -> SbpfVersion {
+        self.sbpf_version
+    }
+
+    pub fn load_accounts(&mut self, accounts: Vec<Account>, instruction_data: &[u8], program_id: Pubkey) {
+        self.accounts = accounts;
+        self.instruction_data = instruction_data.to_vec();
+        self.state.program_id = program_id;
+        self.serialize_input();
+    }
+
+    pub fn set_program_id(&mut self, program_id: Pubkey) {
+        self.state.program_id = program_id;
+        self.serialize_input();
+    }
+
+    // Replaces the instruction data, keeping the loaded accounts
+    pub fn set_instruction_data(&mut self, data: &[u8]) {
+        self.instruction_data = data.to_vec();
+        self.serialize_input();
+    }
+
+    // Loads `account_number` empty writable accounts with distinct keys and
+    // `data` as the instruction data
+    pub fn load_input_data(&mut self, account_number: u64, data: &[u8]) {
+        let accounts = (0..account_number)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[..8].copy_from_slice(&(i + 1).to_le_bytes());
+                Account { key, is_writable: true, ..Account::default() }
+            })
+            .collect();
+        self.load_accounts(accounts, data, self.state.program_id);
+    }
+
+    pub fn get_accounts(&self) -> &[Account] {
+        &self.accounts
     }
 
     pub fn get_instruction_data(&self) -> Vec<u8> {
-        let start_addr = 8;
-        let len = self.state.memory[start_addr] as usize;
-        self.state.memory[start_addr + 8 .. start_addr + 8 + len].to_vec()
+        self.instruction_data.clone()
+    }
+
+    // Parse the input region back into accounts and compare with what was loaded
+    pub fn get_account_diff(&self) -> Result<AccountsDiff, String> {
+        let input = &self.state.memory.region(RegionType::Input).data;
+        let post = deserialize_parameters(input, &self.accounts)?;
+        Ok(diff_accounts(&self.accounts, &post))
+    }
+
+    // Replaces the heap with a zeroed one of the new size
+    pub fn set_heap_size(&mut self, heap_size: usize) -> Result<(), String> {
+        if !(MIN_HEAP_FRAME_BYTES..=MAX_HEAP_FRAME_BYTES).contains(&heap_size) || heap_size % HEAP_FRAME_BYTES_MULTIPLE != 0 {
+            return Err(format!(
+                "Invalid heap size {}, must be a multiple of {} between {} and {}",
+                heap_size, HEAP_FRAME_BYTES_MULTIPLE, MIN_HEAP_FRAME_BYTES, MAX_HEAP_FRAME_BYTES
+            ));
+        }
+        self.state.heap_size = heap_size;
+        self.state.heap_allocated = 0;
+        self.state.memory.region_mut(RegionType::Heap).data = vec![0u8; heap_size];
+        Ok(())
+    }
+
+    pub fn get_heap_size(&self) -> usize {
+        self.state.heap_size
+    }
+
+    pub fn get_return_data(&self) -> &ReturnData {
+        &self.state.return_data
+    }
+
+    pub fn get_heap_used(&self) -> u64 {
+        self.state.heap_allocated
+    }
+
+    pub fn set_sysvars(&mut self, sysvars: Sysvars) {
+        self.state.sysvars = sysvars;
+    }
+
+    pub fn register_syscall(&mut self, name: &str, syscall: impl Syscall + 'static) {
+        self.state.syscalls.register(name, syscall);
+    }
+
+    // Checks the ELF can be loaded before it gets invoked
+    pub fn register_program_elf(&mut self, program_id: Pubkey, elf: Vec<u8>) -> Result<(), VmError> {
+        Program::new(elf.clone())?;
+        self.state.programs.register_elf(program_id, elf);
+        Ok(())
+    }
+
+    pub fn register_native_program(&mut self, program_id: Pubkey, program: impl Fn(&Pubkey, &mut [Account], &[u8]) -> Result<(), String> + 'static) {
+        self.state.programs.register_native(program_id, program);
+    }
+
+    // Runs a CPI callee in a fresh VM that shares the caller's syscalls,
+    // sysvars, programs and compute meter. Returns the accounts as the
+    // callee left them. Errors lose the callee's location so that they
+    // point at the caller's invoke.
+    pub fn invoke(caller: &mut VMState, program_id: Pubkey, elf: Vec<u8>, accounts: Vec<Account>, instruction_data: &[u8]) -> Result<Vec<Account>, VmError> {
+        let mut callee = VM::new();
+        callee.state.syscalls = caller.syscalls.clone();
+        callee.state.sysvars = caller.sysvars.clone();
+        callee.state.programs = caller.programs.clone();
+        callee.state.compute_budget = caller.compute_budget;
+        callee.state.compute_units_consumed = caller.compute_units_consumed;
+        callee.state.invoke_depth = caller.invoke_depth + 1;
+        callee.state.return_data = caller.return_data.clone();
+        callee.load_program(elf)?;
+        callee.load_accounts(accounts, instruction_data, program_id);
+
+        let result = callee.run();
+        caller.compute_units_consumed = callee.state.compute_units_consumed;
+        caller.return_data = callee.state.return_data.clone();
+        match result.map_err(VmError::without_location)? {
+            0 => {}
+            code => return Err(VmError::ProgramError { code, location: None }),
+        }
+        let input = &callee.state.memory.region(RegionType::Input).data;
+        deserialize_parameters(input, &callee.accounts).map_err(VmError::syscall)
+    }
+
+    pub fn set_compute_unit_limit(&mut self, limit: u64) {
+        self.state.compute_budget.compute_unit_limit = limit;
+    }
+
+    pub fn get_compute_unit_limit(&self) -> u64 {
+        self.state.compute_budget.compute_unit_limit
+    }
+
+    pub fn get_compute_units_consumed(&self) -> u64 {
+        self.state.compute_units_consumed
     }
 
     pub fn is_exited(&self) -> bool {
         self.state.exited
     }
 
-    pub fn run(&mut self) -> Result<u64, String> {
-        let program = self.program.as_ref().ok_or("No program loaded")?;
-        
-        // BUG TODO : update it to call step_instruction()
+    pub fn add_observer(&mut self, observer: Rc<RefCell<dyn ExecutionObserver>>) {
+        self.state.observers.add(observer);
+    }
+
+    pub fn run(&mut self) -> Result<u64, VmError> {
         while !self.state.exited {
-            // Get the current instruction bytes
-            let current_bytes = &program.bytecode[self.state.pc..];
-            
-            // Get debug info for current instruction if available
-            let debug_info = if let Some(debug_map) = &self.debug_map {
-                let offset = self.state.pc as u64 - self.entry_point.unwrap() as u64;
-                debug_map.get(&offset)
-            } else {
-                None
-            };
-            
-            // Decode the instruction first
-            let (instruction, size) = decode_instruction(current_bytes)?;
-            
-            // Execute the instruction with debug info
-            instruction.execute(&mut self.state, program, debug_info)?;
-
-            // Only increment PC if the instruction didn't modify it (e.g., jump)
-            if self.state.pc == self.state.pc {
-                self.state.pc += size;
-            }
+            self.step_instruction()?;
         }
-        
-        // Return the result from r0
         Ok(self.state.registers[0].value)
     }
-    
-    pub fn step_instruction(&mut self) -> Result<(), String> {
-        let program = self.program.as_ref().ok_or("No program loaded")?;
-        let current_bytes = &program.bytecode[self.state.pc..];
-        
-        // Get debug info for current instruction if available
-        let debug_info = if let Some(debug_map) = &self.debug_map {
-            let offset = self.state.pc as u64 - self.entry_point.unwrap() as u64;
-            debug_map.get(&offset)
-        } else {
-            None
+
+    pub fn add_breakpoint(&mut self, location: BreakpointLocation, condition: Option<Condition>) -> usize {
+        self.breakpoints.add(location, condition)
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        self.breakpoints.remove(id)
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        self.breakpoints.set_enabled(id, enabled)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn get_breakpoints(&self) -> Vec<Breakpoint> {
+        self.breakpoints.iter().cloned().collect()
+    }
+
+    pub fn add_watchpoint(&mut self, watch: Watch) -> Result<usize, String> {
+        watch.validate()?;
+        let watchpoints = self.watchpoints.get_or_insert_with(|| {
+            let watchpoints = Rc::new(RefCell::new(Watchpoints::default()));
+            self.state.observers.add(watchpoints.clone());
+            watchpoints
+        });
+        Ok(watchpoints.borrow_mut().add(watch))
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.watchpoints.as_ref().is_some_and(|watchpoints| watchpoints.borrow_mut().remove(id))
+    }
+
+    pub fn set_watchpoint_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        self.watchpoints.as_ref().is_some_and(|watchpoints| watchpoints.borrow_mut().set_enabled(id, enabled))
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.borrow_mut().clear();
+        }
+    }
+
+    pub fn get_watchpoints(&self) -> Vec<Watchpoint> {
+        self.watchpoints.as_ref()
+            .map(|watchpoints| watchpoints.borrow().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // First watchpoint triggered by the last executed instruction
+    fn watchpoint_hit(&self) -> Option<StopReason> {
+        let watchpoints = self.watchpoints.as_ref()?.borrow();
+        let hit = watchpoints.hits().first()?;
+        Some(StopReason::Watchpoint { id: hit.id, location: self.location_at(hit.pc), change: hit.change.clone() })
+    }
+
+    // Starts recording an undo log of at most `limit` bytes, or changes the
+    // limit of the one being recorded
+    pub fn set_history_limit(&mut self, limit: usize) {
+        match &self.history {
+            Some(history) => history.borrow_mut().set_limit(limit),
+            None => {
+                let history = Rc::new(RefCell::new(History::new(limit)));
+                self.state.observers.add(history.clone());
+                self.history = Some(history);
+            }
+        }
+    }
+
+    // Number of instructions that can be stepped back over
+    pub fn get_history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.borrow().len())
+    }
+
+    pub fn get_instruction_count(&self) -> u64 {
+        self.state.instruction_count
+    }
+
+    // Undoes the last executed instruction, false if it was not recorded
+    pub fn step_back(&mut self) -> bool {
+        self.history.as_ref().is_some_and(|history| history.borrow_mut().undo(&mut self.state))
+    }
+
+    // Steps back until execution reaches an enabled breakpoint whose
+    // condition holds or the start of the history
+    pub fn reverse_continue(&mut self) -> StopReason {
+        loop {
+            if !self.step_back() {
+                return StopReason::HistoryStart { location: self.location() };
+            }
+            if let Some(stop) = self.check_breakpoints() {
+                return stop;
+            }
+        }
+    }
+
+    // Steps back or runs forward until `count` instructions have been
+    // executed. Returns the count reached, which falls short when the
+    // history does not go back far enough or the program exits first.
+    pub fn goto_instruction(&mut self, count: u64) -> Result<u64, VmError> {
+        while self.state.instruction_count > count && self.step_back() {}
+        while self.state.instruction_count < count && !self.state.exited {
+            self.step_instruction()?;
+        }
+        Ok(self.state.instruction_count)
+    }
+
+    // Records every instruction executed from now on
+    pub fn enable_trace(&mut self) {
+        if self.tracer.is_none() {
+            let tracer = Rc::new(RefCell::new(Tracer::default()));
+            self.state.observers.add(tracer.clone());
+            self.tracer = Some(tracer);
+        }
+    }
+
+    pub fn write_trace(&self, format: TraceFormat, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        let Some(tracer) = &self.tracer else {
+            return Ok(());
+        };
+        let tracer = tracer.borrow();
+        let bytecode = self.program.as_ref().map_or(&[][..], |program| &program.bytecode);
+        let version = self.program.as_ref().map_or(self.sbpf_version, |program| program.sbpf_version);
+        let records: Vec<TraceRecord> = tracer.entries().iter()
+            .map(|entry| {
+                let location = self.location_at(entry.pc);
+                let slot = (location.offset / 8) as usize;
+                TraceRecord {
+                    entry,
+                    slot,
+                    line: location.line,
+                    disassembly: disassemble(bytecode.get(entry.pc..).unwrap_or_default(), slot, &self.state.syscalls, version),
+                }
+            })
+            .collect();
+        write_trace(&records, format, out)
+    }
+
+    // Encodes the program, the execution state and the log so far
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let state = &self.state;
+        let snapshot = Snapshot {
+            program: self.program.as_ref().map(|program| Program {
+                bytecode: program.bytecode.clone(),
+                entry_point: program.entry_point,
+                text: program.text.clone(),
+                sbpf_version: program.sbpf_version,
+            }),
+            rodata: self.rodata.clone(),
+            line_map: self.line_map.clone(),
+            debug_map: self.debug_map.clone(),
+            label_map: self.label_map.clone(),
+            accounts: self.accounts.clone(),
+            instruction_data: self.instruction_data.clone(),
+            registers: state.registers.clone(),
+            memory: state.memory.clone(),
+            call_frames: state.call_frames.clone(),
+            compute_budget: state.compute_budget,
+            compute_units_consumed: state.compute_units_consumed,
+            sysvars: state.sysvars.clone(),
+            invoke_depth: state.invoke_depth,
+            program_id: state.program_id,
+            return_data: state.return_data.clone(),
+            heap_size: state.heap_size,
+            heap_allocated: state.heap_allocated,
+            instruction_count: state.instruction_count,
+            pc: state.pc,
+            exited: state.exited,
+            log: get_log(),
         };
-        
-        let (instruction, size) = decode_instruction(current_bytes)?;
-        instruction.execute(&mut self.state, program, debug_info)?;
-        self.state.pc += size;
+        snapshot.encode()
+    }
+
+    // Continues from a saved snapshot. Syscalls, CPI callees, breakpoints
+    // and watchpoints stay as they are, the history and trace start over.
+    pub fn restore_snapshot(&mut self, snapshot: &[u8]) -> Result<(), String> {
+        let snapshot = Snapshot::decode(snapshot)?;
+        self.entry_point = snapshot.program.as_ref().map(|program| program.entry_point as usize);
+        self.state.sbpf_version = snapshot.program.as_ref().map_or(SbpfVersion::default(), |program| program.sbpf_version);
+        self.program = snapshot.program;
+        self.rodata = snapshot.rodata;
+        self.line_map = snapshot.line_map;
+        self.debug_map = snapshot.debug_map;
+        self.label_map = snapshot.label_map;
+        self.accounts = snapshot.accounts;
+        self.instruction_data = snapshot.instruction_data;
+
+        let state = &mut self.state;
+        state.registers = snapshot.registers;
+        state.memory = snapshot.memory;
+        state.call_frames = snapshot.call_frames;
+        state.compute_budget = snapshot.compute_budget;
+        state.compute_units_consumed = snapshot.compute_units_consumed;
+        state.sysvars = snapshot.sysvars;
+        state.invoke_depth = snapshot.invoke_depth;
+        state.program_id = snapshot.program_id;
+        state.return_data = snapshot.return_data;
+        state.heap_size = snapshot.heap_size;
+        state.heap_allocated = snapshot.heap_allocated;
+        state.instruction_count = snapshot.instruction_count;
+        state.pc = snapshot.pc;
+        state.exited = snapshot.exited;
+        set_log(&snapshot.log);
+
+        if let Some(history) = &self.history {
+            history.borrow_mut().clear();
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.borrow_mut().clear();
+        }
         Ok(())
     }
 
+    // pc a breakpoint stops at in the loaded program, the first instruction
+    // of a line. None if there is no such instruction.
+    pub fn resolve_breakpoint(&self, location: &BreakpointLocation) -> Option<usize> {
+        let text_start = self.program.as_ref()?.text.start as usize;
+        let offset = match location {
+            BreakpointLocation::Line(line) => self.debug_map.as_ref()?.iter()
+                .filter(|(_, debug_info)| debug_info.line_number == *line)
+                .map(|(offset, _)| *offset)
+                .min()?,
+            BreakpointLocation::Address(pc) => return Some(*pc).filter(|&pc| self.is_instruction(pc)),
+            BreakpointLocation::Label(label) => *self.label_map.as_ref()?.get(label)?,
+        };
+        Some(text_start + offset as usize).filter(|&pc| self.is_instruction(pc))
+    }
+
+    fn is_instruction(&self, pc: usize) -> bool {
+        self.program.as_ref().is_some_and(|program| program.is_instruction(pc as u64))
+    }
+
+    // Runs until the program exits, an instruction triggers a watchpoint or
+    // execution reaches an enabled breakpoint whose condition holds. Always
+    // executes at least one instruction, so that continuing from a
+    // breakpoint moves on.
+    pub fn continue_execution(&mut self) -> Result<StopReason, VmError> {
+        loop {
+            if self.state.exited {
+                return Ok(StopReason::Exit { code: self.state.registers[0].value });
+            }
+            self.step_instruction()?;
+            if let Some(stop) = self.watchpoint_hit() {
+                return Ok(stop);
+            }
+            if self.state.exited {
+                return Ok(StopReason::Exit { code: self.state.registers[0].value });
+            }
+            if let Some(stop) = self.check_breakpoints() {
+                return Ok(stop);
+            }
+        }
+    }
+
+    // Whether a breakpoint stops at the current pc, counting the hit. For
+    // the first instruction, which `continue_execution` never stops at.
+    pub fn check_breakpoints(&mut self) -> Option<StopReason> {
+        if self.breakpoints.is_empty() || self.state.exited {
+            return None;
+        }
+        let location = self.location();
+        let mut breakpoints = std::mem::take(&mut self.breakpoints);
+        let hit = breakpoints.hit(&self.state, &location, |location| self.resolve_breakpoint(location));
+        self.breakpoints = breakpoints;
+        hit.map(|id| StopReason::Breakpoint { id, location })
+    }
+
+    // Executes the instruction at pc, observers see the exit or the error
+    // that ends the program
+    pub fn step_instruction(&mut self) -> Result<(), VmError> {
+        let result = self.execute_instruction();
+        let exit = match &result {
+            Err(e) => Some(Err(e.clone())),
+            Ok(()) if self.state.exited => Some(Ok(self.state.registers[0].value)),
+            Ok(()) => None,
+        };
+        if let Some(exit) = exit {
+            self.state.observers.notify(|observer| observer.on_exit(&self.state, &exit));
+        }
+        result
+    }
+
+    fn execute_instruction(&mut self) -> Result<(), VmError> {
+        let program = self.program.as_ref().ok_or_else(|| VmError::elf("No program loaded"))?;
+        let pc = self.state.pc;
+        let location = self.location();
+        let bytes = program.bytecode.get(pc..program.text.end as usize).filter(|_| program.is_instruction(pc as u64))
+            .ok_or_else(|| VmError::invalid_instruction(format!("pc 0x{:x} is outside .text", pc)).at(location))?;
+        let debug_info = self.debug_map.as_ref().and_then(|debug_map| debug_map.get(&location.offset));
+
+        let (instruction, size) = decode_instruction(bytes, program.sbpf_version).map_err(|e| e.at(location))?;
+        self.state.observers.notify(|observer| observer.before_instruction(&self.state, pc, &instruction));
+        self.state.consume_compute_units(1).map_err(|e| e.at(location))?;
+        let next = instruction.execute(&mut self.state, program, debug_info).map_err(|e| e.at(location))?;
+        self.state.instruction_count += 1;
+
+        // stay on the exit instruction once the program is done
+        if !self.state.exited {
+            self.state.pc = match next {
+                Next::FallThrough => pc + size,
+                Next::Jump(target) => target,
+            };
+        }
+        self.state.observers.notify(|observer| observer.after_instruction(&self.state, pc, &instruction));
+        Ok(())
+    }
+
+    // Location of the instruction at the current pc, for errors
+    fn location(&self) -> Location {
+        self.location_at(self.state.pc)
+    }
+
+    // debug info is keyed by the offset into .text
+    fn location_at(&self, pc: usize) -> Location {
+        let text_start = self.program.as_ref().map_or(0, |program| program.text.start);
+        let offset = (pc as u64).wrapping_sub(text_start);
+        let line = self.debug_map.as_ref()
+            .and_then(|debug_map| debug_map.get(&offset))
+            .map(|debug_info| debug_info.line_number);
+        Location { pc, offset, line }
+    }
+
     pub fn get_entry_point(&self) -> usize {
         self.entry_point.unwrap()
     }
 
     pub fn get_line_number(&self) -> usize {
-        if let Some(debug_map) = &self.debug_map {
-            debug_map.get(&(self.state.pc as u64 - self.entry_point.unwrap() as u64)).map(|debug_info| debug_info.line_number).unwrap_or(0)
-        } else {
-            0
-        }
+        self.location().line.unwrap_or(0)
     }
 
     pub fn get_rodata(&self) -> Vec<(String, usize, String)> {
@@ -218,3 +847,236 @@ impl VM {
         self.state.registers.to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchpoint::{WatchAccess, WatchChange};
+    use codespan_reporting::files::SimpleFile;
+
+    fn load_source(source: &str) -> VM {
+        let file = SimpleFile::new("test.s".to_string(), source.to_string());
+        let tokens = sbpf_assembler::tokenize(source).unwrap();
+        let parse_result = sbpf_assembler::Parser::new(tokens, &file).parse().unwrap();
+        let program = sbpf_assembler::Program::from_parse_result(parse_result);
+        let mut vm = VM::new();
+        vm.load_program(program.emit_bytecode()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_set_instruction_data_keeps_accounts() {
+        let mut vm = VM::new();
+        vm.load_input_data(2, b"old");
+        let keys: Vec<Pubkey> = vm.get_accounts().iter().map(|account| account.key).collect();
+        vm.set_instruction_data(b"new data");
+        assert_eq!(vm.get_accounts().iter().map(|account| account.key).collect::<Vec<_>>(), keys);
+        assert_eq!(vm.get_instruction_data(), b"new data");
+        // the input region is reserialized with both
+        let input = &vm.state.memory.region(RegionType::Input).data;
+        let expected = serialize_parameters(vm.get_accounts(), b"new data", &vm.state.program_id);
+        assert_eq!(input, &expected);
+    }
+
+    #[test]
+    fn test_heap_size_bounds() {
+        let mut vm = VM::new();
+        assert!(vm.set_heap_size(MIN_HEAP_FRAME_BYTES - HEAP_FRAME_BYTES_MULTIPLE).is_err());
+        assert!(vm.set_heap_size(MAX_HEAP_FRAME_BYTES + HEAP_FRAME_BYTES_MULTIPLE).is_err());
+        assert!(vm.set_heap_size(33 * 1024 + 1).is_err());
+        assert_eq!(vm.get_heap_size(), MIN_HEAP_FRAME_BYTES);
+
+        vm.set_heap_size(MAX_HEAP_FRAME_BYTES).unwrap();
+        assert_eq!(vm.state.memory.region(RegionType::Heap).data.len(), MAX_HEAP_FRAME_BYTES);
+        vm.set_heap_size(33 * 1024).unwrap();
+        assert_eq!(vm.get_heap_size(), 33 * 1024);
+    }
+
+    #[test]
+    fn test_compute_budget_runs_out_at_the_instruction() {
+        let source = ".globl e\ne:\n  mov64 r1, 1\n  add64 r0, r1\n  add64 r0, r1\n  exit\n";
+        let mut vm = load_source(source);
+        vm.set_compute_unit_limit(4);
+        assert_eq!(vm.run().unwrap(), 2);
+        assert_eq!(vm.get_compute_units_consumed(), 4);
+
+        // the exit is the first instruction over the limit and does not run
+        let mut vm = load_source(source);
+        vm.set_compute_unit_limit(3);
+        assert!(matches!(vm.run().unwrap_err(), VmError::BudgetExceeded { limit: 3, .. }));
+        assert_eq!(vm.get_compute_units_consumed(), 3);
+        assert_eq!(vm.state.pc, vm.entry_point.unwrap() + 24);
+        assert!(!vm.is_exited());
+    }
+
+    struct Add;
+
+    impl Syscall for Add {
+        fn execute(&self, _vm: &mut VMState, args: [u64; 5]) -> Result<u64, VmError> {
+            Ok(args.iter().sum())
+        }
+    }
+
+    #[test]
+    fn test_syscalls_dispatch_by_name_hash() {
+        let source = ".globl e\ne:\n  mov64 r1, 2\n  mov64 r5, 3\n  call sol_add\n  exit\n.extern sol_add\n";
+        let mut vm = load_source(source);
+        vm.register_syscall("sol_add", Add);
+        assert_eq!(vm.run().unwrap(), 5);
+
+        // programs calling a syscall that was never registered fail
+        let mut vm = load_source(source);
+        assert!(vm.run().is_err());
+    }
+
+    #[test]
+    fn test_program_reads_back_configured_sysvars() {
+        // returns clock.unix_timestamp and adds rent.burn_percent
+        let source = ".globl e\ne:\n  mov64 r1, r10\n  sub64 r1, 40\n  call sol_get_clock_sysvar\n  ldxdw r6, [r10-8]\n  mov64 r1, r10\n  sub64 r1, 64\n  call sol_get_rent_sysvar\n  ldxb r0, [r10-48]\n  add64 r0, r6\n  exit\n";
+        let mut vm = load_source(source);
+        vm.set_sysvars(Sysvars::from_toml("[clock]\nunix_timestamp = 1700000000\n[rent]\nburn_percent = 7\n").unwrap());
+        assert_eq!(vm.run().unwrap(), 1_700_000_007);
+    }
+
+    #[test]
+    fn test_breakpoints_stop_where_their_condition_holds() {
+        let source = ".globl e\ne:\n  mov64 r3, 0\nloop:\n  add64 r3, 1\n  jne r3, 5, loop\n  mov64 r0, r3\n  exit\n";
+        let mut vm = load_source(source);
+        vm.load_label_map(HashMap::from([("loop".to_string(), 8)]));
+        let entry = vm.get_entry_point();
+        let in_loop = vm.add_breakpoint(BreakpointLocation::Label("loop".to_string()), Some("r3 >= 2".parse().unwrap()));
+        let after_loop = vm.add_breakpoint(BreakpointLocation::Address(entry + 24), None);
+        let never = vm.add_breakpoint(BreakpointLocation::Address(entry + 24), Some("r3 < 5".parse().unwrap()));
+        assert_eq!(vm.check_breakpoints(), None);
+
+        // the loop is entered with r3 = 0 to 4, the condition skips the first two
+        let mut stops = Vec::new();
+        loop {
+            match vm.continue_execution().unwrap() {
+                StopReason::Breakpoint { id, location } => stops.push((id, location.pc, vm.get_registers()[3].value)),
+                StopReason::Exit { code } => {
+                    assert_eq!(code, 5);
+                    break;
+                }
+                other => panic!("unexpected stop {:?}", other),
+            }
+        }
+        assert_eq!(stops, [(in_loop, entry + 8, 2), (in_loop, entry + 8, 3), (in_loop, entry + 8, 4), (after_loop, entry + 24, 5)]);
+        let hit_counts: Vec<(usize, u64)> = vm.get_breakpoints().iter().map(|breakpoint| (breakpoint.id, breakpoint.hit_count)).collect();
+        assert_eq!(hit_counts, [(in_loop, 3), (after_loop, 1), (never, 0)]);
+    }
+
+    #[test]
+    fn test_disabled_breakpoints_do_not_stop() {
+        let mut vm = load_source(".globl e\ne:\n  mov64 r0, 1\n  mov64 r0, 2\n  exit\n");
+        let entry = vm.get_entry_point();
+        let id = vm.add_breakpoint(BreakpointLocation::Address(entry + 8), None);
+        vm.set_breakpoint_enabled(id, false);
+        assert_eq!(vm.continue_execution().unwrap(), StopReason::Exit { code: 2 });
+        assert_eq!(vm.get_breakpoints()[0].hit_count, 0);
+    }
+
+    #[test]
+    fn test_watchpoints_report_old_and_new_values() {
+        let source = ".globl e\ne:\n  mov64 r1, 0x11\n  stxdw [r10-8], r1\n  mov64 r1, 0x22\n  stxw [r10-4], r1\n  ldxdw r0, [r10-8]\n  mov64 r0, 7\n  exit\n";
+        let mut vm = load_source(source);
+        let entry = vm.get_entry_point();
+        let slot = vm.get_registers()[10].value - 8;
+        let memory = vm.add_watchpoint(Watch::Memory { addr: slot, len: 8, access: WatchAccess::Write }).unwrap();
+        let register = vm.add_watchpoint(Watch::Register { register: 0, value: Some(7) }).unwrap();
+
+        let mut stops = Vec::new();
+        loop {
+            match vm.continue_execution().unwrap() {
+                StopReason::Watchpoint { id, location, change } => stops.push((id, location.pc - entry, change)),
+                StopReason::Exit { code } => {
+                    assert_eq!(code, 7);
+                    break;
+                }
+                other => panic!("unexpected stop {:?}", other),
+            }
+        }
+        // the second store only overlaps the upper half, the load is not watched
+        // and r0 changing to the loaded value does not match the watched value
+        assert_eq!(stops, [
+            (memory, 8, WatchChange::Memory { addr: slot, access: AccessType::Store, old: vec![0; 8], new: 0x11u64.to_le_bytes().to_vec() }),
+            (memory, 24, WatchChange::Memory { addr: slot + 4, access: AccessType::Store, old: vec![0; 4], new: 0x22u32.to_le_bytes().to_vec() }),
+            (register, 40, WatchChange::Register { register: 0, old: 0x2200000011, new: 7 }),
+        ]);
+    }
+
+    // pc, registers, the top 16 bytes of the stack and compute units used
+    fn machine_state(vm: &VM, stack_top: u64) -> (usize, Vec<u64>, Vec<u8>, u64) {
+        let registers = vm.get_registers().iter().map(|register| register.value).collect();
+        let stack = vm.state.read_memory(stack_top - 16, 16).unwrap().to_vec();
+        (vm.state.pc, registers, stack, vm.get_compute_units_consumed())
+    }
+
+    const HISTORY_SOURCE: &str = ".globl e\ne:\n  mov64 r1, 0x11\n  stxdw [r10-8], r1\n  mov64 r1, 0x22\n  stxw [r10-4], r1\n  call f\n  exit\nf:\n  stxdw [r10-16], r1\n  ldxdw r0, [r10-4104]\n  exit\n";
+
+    #[test]
+    fn test_step_back_restores_memory_and_registers() {
+        let mut vm = load_source(HISTORY_SOURCE);
+        vm.set_history_limit(crate::history::DEFAULT_HISTORY_LIMIT);
+        let stack_top = vm.get_registers()[10].value;
+        let mut states = vec![machine_state(&vm, stack_top)];
+        while !vm.is_exited() {
+            vm.step_instruction().unwrap();
+            states.push(machine_state(&vm, stack_top));
+        }
+        assert_eq!(vm.get_registers()[0].value, 0x22_0000_0011);
+        assert_eq!(vm.get_history_len(), states.len() - 1);
+
+        // overlapping stores, the call frame and the exit are all undone
+        states.pop();
+        while let Some(state) = states.pop() {
+            assert!(vm.step_back());
+            assert_eq!(machine_state(&vm, stack_top), state);
+            assert!(!vm.is_exited());
+        }
+        assert!(!vm.step_back());
+        assert_eq!(vm.get_instruction_count(), 0);
+        // and replaying gives the same result
+        assert_eq!(vm.run().unwrap(), 0x22_0000_0011);
+    }
+
+    #[test]
+    fn test_reverse_continue_and_goto_instruction() {
+        let mut vm = load_source(HISTORY_SOURCE);
+        vm.set_history_limit(crate::history::DEFAULT_HISTORY_LIMIT);
+        let entry = vm.get_entry_point();
+        let stack_top = vm.get_registers()[10].value;
+        vm.goto_instruction(3).unwrap();
+        let third = machine_state(&vm, stack_top);
+        // stops at the exit when asked to go past it
+        assert_eq!(vm.goto_instruction(100).unwrap(), 9);
+        assert!(vm.is_exited());
+
+        let id = vm.add_breakpoint(BreakpointLocation::Address(entry + 24), None);
+        assert!(matches!(vm.reverse_continue(), StopReason::Breakpoint { id: hit, .. } if hit == id));
+        assert_eq!(vm.get_instruction_count(), 3);
+        assert_eq!(machine_state(&vm, stack_top), third);
+        vm.clear_breakpoints();
+        assert!(matches!(vm.reverse_continue(), StopReason::HistoryStart { .. }));
+        assert_eq!(vm.get_instruction_count(), 0);
+    }
+
+    #[test]
+    fn test_history_limit_drops_the_oldest_steps() {
+        let mut vm = load_source(HISTORY_SOURCE);
+        vm.set_history_limit(crate::history::DEFAULT_HISTORY_LIMIT);
+        vm.run().unwrap();
+        let full = vm.get_history_len();
+        let stack_top = vm.get_registers()[10].value;
+
+        vm.set_history_limit(512);
+        let len = vm.get_history_len();
+        assert!(len > 0 && len < full);
+        // going back further than the history reaches stops at its start
+        assert_eq!(vm.goto_instruction(0).unwrap(), (full - len) as u64);
+        let start = machine_state(&vm, stack_top);
+        let mut replay = load_source(HISTORY_SOURCE);
+        replay.goto_instruction((full - len) as u64).unwrap();
+        assert_eq!(machine_state(&replay, stack_top), start);
+    }
+}
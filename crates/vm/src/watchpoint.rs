@@ -0,0 +1,176 @@
+use crate::instruction::InstructionType;
+use crate::memory::AccessType;
+use crate::observer::{ExecutionObserver, MemoryAccess};
+use crate::vm::VMState;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchAccess {
+    fn matches(self, access: AccessType) -> bool {
+        matches!(
+            (self, access),
+            (WatchAccess::ReadWrite, _) | (WatchAccess::Read, AccessType::Load) | (WatchAccess::Write, AccessType::Store)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Watch {
+    // loads and/or stores overlapping [addr, addr + len)
+    Memory { addr: u64, len: u64, access: WatchAccess },
+    // any change of the register, or a change to `value`
+    Register { register: usize, value: Option<u64> },
+}
+
+impl Watch {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Watch::Memory { addr, len, .. } if len == 0 || addr.checked_add(len).is_none() => {
+                Err(format!("Invalid watched range: {} bytes at 0x{:x}", len, addr))
+            }
+            Watch::Register { register, .. } if register > 10 => Err(format!("Invalid register r{}", register)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub watch: Watch,
+    pub enabled: bool,
+    pub hit_count: u64,
+}
+
+// What triggered a watchpoint. Reads report the bytes read as both values,
+// memory is clipped to the watched range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum WatchChange {
+    Memory { addr: u64, access: AccessType, old: Vec<u8>, new: Vec<u8> },
+    Register { register: usize, old: u64, new: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub id: usize,
+    // of the instruction that triggered it
+    pub pc: usize,
+    pub change: WatchChange,
+}
+
+// Checks the watchpoints as an observer of the VM, hits are kept until the
+// next instruction starts
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+    // register values before the current instruction
+    registers: [u64; 11],
+    hits: Vec<WatchpointHit>,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, watch: Watch) -> usize {
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint { id: self.next_id, watch, enabled: true, hit_count: 0 });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        self.watchpoints.len() != len
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        self.watchpoints.iter_mut()
+            .find(|watchpoint| watchpoint.id == id)
+            .map(|watchpoint| watchpoint.enabled = enabled)
+            .is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+        self.hits.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    // Watchpoints triggered by the last executed instruction
+    pub fn hits(&self) -> &[WatchpointHit] {
+        &self.hits
+    }
+}
+
+impl ExecutionObserver for Watchpoints {
+    fn before_instruction(&mut self, vm: &VMState, _pc: usize, _instruction: &InstructionType) {
+        self.hits.clear();
+        self.registers = std::array::from_fn(|i| vm.registers[i].value);
+    }
+
+    fn after_instruction(&mut self, vm: &VMState, pc: usize, _instruction: &InstructionType) {
+        for watchpoint in self.watchpoints.iter_mut().filter(|watchpoint| watchpoint.enabled) {
+            let Watch::Register { register, value } = watchpoint.watch else {
+                continue;
+            };
+            let (old, new) = (self.registers[register], vm.registers[register].value);
+            if old != new && value.is_none_or(|value| value == new) {
+                watchpoint.hit_count += 1;
+                self.hits.push(WatchpointHit { id: watchpoint.id, pc, change: WatchChange::Register { register, old, new } });
+            }
+        }
+    }
+
+    fn on_memory_access(&mut self, vm: &VMState, access: &MemoryAccess) {
+        let access_end = access.addr + access.data.len() as u64;
+        for watchpoint in self.watchpoints.iter_mut().filter(|watchpoint| watchpoint.enabled) {
+            let Watch::Memory { addr, len, access: watched } = watchpoint.watch else {
+                continue;
+            };
+            let (start, end) = (addr.max(access.addr), (addr + len).min(access_end));
+            if start >= end || !watched.matches(access.access) {
+                continue;
+            }
+            let range = (start - access.addr) as usize..(end - access.addr) as usize;
+            let new = access.data[range.clone()].to_vec();
+            let old = access.old_data.as_ref().map_or_else(|| new.clone(), |old| old[range].to_vec());
+            watchpoint.hit_count += 1;
+            self.hits.push(WatchpointHit {
+                id: watchpoint.id,
+                pc: vm.pc,
+                change: WatchChange::Memory { addr: start, access: access.access, old, new },
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_watch_is_clipped() {
+        let vm = VMState::new();
+        let mut watchpoints = Watchpoints::default();
+        let id = watchpoints.add(Watch::Memory { addr: 0x102, len: 4, access: WatchAccess::Write });
+        let load = MemoryAccess { addr: 0x100, access: AccessType::Load, data: vec![0; 8], old_data: None };
+        watchpoints.on_memory_access(&vm, &load);
+        assert!(watchpoints.hits().is_empty());
+
+        let store = MemoryAccess { addr: 0x100, access: AccessType::Store, data: vec![1; 4], old_data: Some(vec![0; 4]) };
+        watchpoints.on_memory_access(&vm, &store);
+        let change = WatchChange::Memory { addr: 0x102, access: AccessType::Store, old: vec![0; 2], new: vec![1; 2] };
+        assert_eq!(watchpoints.hits(), [WatchpointHit { id, pc: vm.pc, change }]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use clap::Args;
+use codespan_reporting::files::SimpleFile;
 
 use crate::error::CommandError;
 
@@ -28,8 +29,9 @@ impl Command {
         let tokens = sbpf_assembler::tokenize(&source_code)
             .map_err(|e| Error::Tokenize { source: e })?;
 
-        // Parse the tokens into an AST.
-        let parse_result = sbpf_assembler::Parser::new(tokens)
+        // Parse the tokens into an AST, the file is used for diagnostics.
+        let file = SimpleFile::new(source_file_path.to_string_lossy().to_string(), source_code.clone());
+        let parse_result = sbpf_assembler::Parser::new(tokens, &file)
             .parse()
             .map_err(|e| Error::Parse { source: e })?;
 
@@ -49,7 +51,6 @@ pub enum Error {
     WriteFile { file_path: PathBuf, source: std::io::Error },
     Tokenize { source: String },
     Parse { source: String },
-    ConstructProgram { source: String },
 }
 
 impl std::fmt::Display for Error {
@@ -67,9 +68,6 @@ impl std::fmt::Display for Error {
             Error::Parse { source } => {
                 write!(f, "Failed to parse source code: {}", source)
             }
-            Error::ConstructProgram { source } => {
-                write!(f, "Failed to construct program: {}", source)
-            }
         }
     }
 }
@@ -79,7 +77,7 @@ impl std::error::Error for Error {}
 impl CommandError for Error {
     fn exit_code(&self) -> exitcode::ExitCode {
         match self {
-            Self::Tokenize { .. } | Self::Parse { .. } | Self::ConstructProgram { .. } => {
+            Self::Tokenize { .. } | Self::Parse { .. } => {
                 exitcode::DATAERR
             }
             Self::ReadFile { .. } | Self::WriteFile { .. } => exitcode::IOERR,
@@ -48,7 +48,7 @@ pub enum Commands {
     #[command(about = "Run a program")]
     Run {
         #[clap(flatten)]
-        command: run::Command,
+        command: Box<run::Command>,
     },
 }
 
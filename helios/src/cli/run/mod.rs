@@ -1,21 +1,338 @@
 use std::path::PathBuf;
 
+use base64::prelude::*;
 use clap::Args;
 
 use crate::error::CommandError;
+use helios_vm::breakpoint::{BreakpointLocation, Condition, StopReason};
+use helios_vm::error::{Location, VmError};
+use helios_vm::syscalls::pda::{MAX_SEEDS, MAX_SEED_LEN};
+use helios_vm::trace::TraceFormat;
+use helios_vm::watchpoint::{Watch, WatchAccess, WatchChange};
 
 #[derive(Args)]
 pub struct Command {
-    #[arg(name = "input-file-path")]
-    source_file_path: PathBuf,
+    /// Program to run: .s files are assembled, anything else is loaded as an ELF
+    #[arg(name = "input-file-path", required_unless_present = "resume")]
+    source_file_path: Option<PathBuf>,
+
+    /// Compute units the program may consume
+    #[arg(long, default_value_t = helios_vm::compute_budget::DEFAULT_COMPUTE_UNIT_LIMIT)]
+    compute_unit_limit: u64,
+
+    /// Heap size in bytes, a multiple of 1024 between 32 KiB and 256 KiB
+    #[arg(long, default_value_t = helios_vm::vm::MIN_HEAP_FRAME_BYTES)]
+    heap_size: usize,
+
+    /// Id of the program being run, used for PDAs and return data
+    #[arg(long, value_name = "BASE58")]
+    program_id: Option<String>,
+
+    /// Sysvar fixture, JSON or TOML picked by extension
+    #[arg(long, value_name = "FIXTURE")]
+    sysvars: Option<PathBuf>,
+
+    /// PDA seeds, UTF-8 unless prefixed with hex: or base58:. Passed to the
+    /// program as its instruction data, each seed after a length byte.
+    #[arg(long, value_delimiter = ',', value_name = "SEED")]
+    seeds: Vec<String>,
+
+    /// SBPF version .s files are assembled for, v0 to v3. ELF files carry
+    /// their own in e_flags.
+    #[arg(long, default_value = "v0")]
+    sbpf_version: String,
+
+    /// CPI callee: .s files are assembled, anything else is loaded as an ELF
+    #[arg(long = "program", value_name = "BASE58=PATH")]
+    programs: Vec<String>,
+
+    /// Print the registers whenever execution reaches a source line, a pc
+    /// (0x...) or a label, e.g. --break "loop if r3 > 10"
+    #[arg(long = "break", value_name = "LINE|0xPC|LABEL[ if CONDITION]")]
+    breakpoints: Vec<String>,
+
+    /// Stop on a register change (r3), a register value (r3=10) or a store
+    /// to memory (0x400000010:8, append :read or :readwrite for loads)
+    #[arg(long = "watch", value_name = "rN[=VALUE]|0xADDR:LEN[:ACCESS]")]
+    watchpoints: Vec<String>,
+
+    /// Write every executed instruction with its registers and memory accesses
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// jsonl, or text for the solana-sbpf interpreter trace layout
+    #[arg(long, default_value = "jsonl", requires = "trace")]
+    trace_format: String,
+
+    /// Save the VM state where execution first reaches a breakpoint or
+    /// watchpoint, or where the run ended if there is none
+    #[arg(long, value_name = "FILE")]
+    save_snapshot: Option<PathBuf>,
+
+    /// Continue from a saved snapshot instead of loading a program. CPI
+    /// callees have to be passed again with --program.
+    #[arg(
+        long,
+        value_name = "SNAPSHOT",
+        conflicts_with_all = ["input-file-path", "compute_unit_limit", "heap_size", "program_id", "sysvars", "seeds"]
+    )]
+    resume: Option<PathBuf>,
+}
+
+fn decode_pubkey(pubkey: &str) -> Option<Vec<u8>> {
+    bs58::decode(pubkey).into_vec().ok().filter(|bytes| bytes.len() == 32)
+}
+
+fn register_program(spec: &str) -> Result<(), Error> {
+    let (program_id, path) = spec.split_once('=').ok_or_else(|| Error::InvalidProgram { spec: spec.to_string() })?;
+    let program_id = decode_pubkey(program_id).ok_or_else(|| Error::InvalidProgramId { program_id: program_id.to_string() })?;
+    let path = PathBuf::from(path);
+    let result = if path.extension().is_some_and(|ext| ext == "s") {
+        let source = std::fs::read_to_string(&path).map_err(|e| Error::ReadFile { file_path: path.clone(), source: e })?;
+        helios_vm::register_program(&program_id, &source, &path.to_string_lossy())
+    } else {
+        let elf = std::fs::read(&path).map_err(|e| Error::ReadFile { file_path: path.clone(), source: e })?;
+        helios_vm::register_program_elf(&program_id, &elf)
+    };
+    result.map_err(|e| Error::InvalidCallee { file_path: path, source: e })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_seed(spec: &str) -> Result<Vec<u8>, Error> {
+    let invalid = |source: String| Error::InvalidSeed { seed: spec.to_string(), source };
+    let seed = if let Some(hex) = spec.strip_prefix("hex:") {
+        decode_hex(hex).ok_or_else(|| invalid("expected an even number of hex digits".to_string()))?
+    } else if let Some(base58) = spec.strip_prefix("base58:") {
+        bs58::decode(base58).into_vec().map_err(|e| invalid(e.to_string()))?
+    } else {
+        spec.as_bytes().to_vec()
+    };
+    if seed.len() > MAX_SEED_LEN {
+        return Err(invalid(format!("{} bytes, seeds are at most {}", seed.len(), MAX_SEED_LEN)));
+    }
+    Ok(seed)
+}
+
+// Each seed after its length byte, so the program can split them again
+fn encode_seeds(seeds: &[Vec<u8>]) -> Vec<u8> {
+    seeds.iter().flat_map(|seed| std::iter::once(seed.len() as u8).chain(seed.iter().copied())).collect()
+}
+
+fn parse_breakpoint(spec: &str) -> Result<(BreakpointLocation, Option<Condition>), Error> {
+    let (location, condition) = match spec.split_once(" if ") {
+        Some((location, condition)) => (location.trim(), Some(condition)),
+        None => (spec.trim(), None),
+    };
+    let invalid = |source: String| Error::InvalidBreakpoint { spec: spec.to_string(), source };
+    let location = if let Ok(line) = location.parse() {
+        BreakpointLocation::Line(line)
+    } else if let Some(pc) = location.strip_prefix("0x") {
+        BreakpointLocation::Address(usize::from_str_radix(pc, 16).map_err(|e| invalid(e.to_string()))?)
+    } else if !location.is_empty() {
+        BreakpointLocation::Label(location.to_string())
+    } else {
+        return Err(invalid("missing location".to_string()));
+    };
+    let condition = condition.map(str::parse).transpose().map_err(invalid)?;
+    Ok((location, condition))
+}
+
+fn parse_watchpoint(spec: &str) -> Result<Watch, Error> {
+    let invalid = |source: String| Error::InvalidWatchpoint { spec: spec.to_string(), source };
+    let parse_u64 = |value: &str| match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    if let Some(register) = spec.strip_prefix('r') {
+        let (register, value) = match register.split_once('=') {
+            Some((register, value)) => (register, Some(parse_u64(value.trim()).map_err(|e| invalid(e.to_string()))?)),
+            None => (register, None),
+        };
+        let register = register.trim().parse().map_err(|_| invalid("expected a register r0-r10".to_string()))?;
+        let watch = Watch::Register { register, value };
+        watch.validate().map_err(invalid)?;
+        return Ok(watch);
+    }
+    let mut parts = spec.split(':');
+    let (Some(addr), Some(len)) = (parts.next(), parts.next()) else {
+        return Err(invalid("expected <rN[=VALUE]> or <0xADDR:LEN[:ACCESS]>".to_string()));
+    };
+    let addr = parse_u64(addr).map_err(|e| invalid(e.to_string()))?;
+    let len = parse_u64(len).map_err(|e| invalid(e.to_string()))?;
+    let access = match parts.next() {
+        None | Some("write") => WatchAccess::Write,
+        Some("read") => WatchAccess::Read,
+        Some("readwrite") => WatchAccess::ReadWrite,
+        Some(access) => return Err(invalid(format!("unknown access {}, expected read, write or readwrite", access))),
+    };
+    let watch = Watch::Memory { addr, len, access };
+    watch.validate().map_err(invalid)?;
+    Ok(watch)
+}
+
+fn describe(location: &Location) -> String {
+    match location.line {
+        Some(line) => format!("line {} (pc 0x{:x})", line, location.pc),
+        None => format!("pc 0x{:x}", location.pc),
+    }
+}
+
+// Runs the loaded program like `helios_vm::run`, printing the registers at
+// every breakpoint and what changed at every watchpoint. With `stop_early`
+// it returns None at the first of them instead of continuing.
+fn run_with_breakpoints(breakpoints: Vec<(BreakpointLocation, Option<Condition>)>, watchpoints: Vec<Watch>, stop_early: bool) -> Result<Option<u64>, VmError> {
+    helios_vm::with_vm(|vm| {
+        for (location, condition) in breakpoints {
+            let id = vm.add_breakpoint(location.clone(), condition);
+            if vm.resolve_breakpoint(&location).is_none() {
+                eprintln!("Breakpoint {} at {} will never be hit", id, location);
+            }
+        }
+        for watch in watchpoints {
+            vm.add_watchpoint(watch).expect("watchpoints are validated when parsed");
+        }
+        // a resumed run already stopped where it was saved
+        let mut entry_stop = if vm.get_instruction_count() == 0 { vm.check_breakpoints() } else { None };
+        loop {
+            let stop = match entry_stop.take() {
+                Some(stop) => stop,
+                None => vm.continue_execution()?,
+            };
+            // keep the program log in order with the breakpoint output
+            print!("{}", helios_vm::log_buffer::get_log());
+            helios_vm::log_buffer::clear_log();
+            match stop {
+                StopReason::Exit { code } => return Ok(Some(code)),
+                StopReason::Breakpoint { id, location } => {
+                    println!("Breakpoint {} hit at {}", id, describe(&location));
+                }
+                StopReason::Watchpoint { id, location, change: WatchChange::Register { register, old, new } } => {
+                    println!("Watchpoint {} hit at {}: r{} 0x{:x} -> 0x{:x}", id, describe(&location), register, old, new);
+                }
+                StopReason::Watchpoint { id, location, change: WatchChange::Memory { addr, access, old, new } } => {
+                    println!(
+                        "Watchpoint {} hit at {}: {:?} at 0x{:x} {} -> {}",
+                        id, describe(&location), access, addr, helios_vm::to_hex(&old), helios_vm::to_hex(&new)
+                    );
+                }
+                StopReason::HistoryStart { location } => {
+                    println!("Reached the start of the history at {}", describe(&location));
+                }
+            }
+            let registers: Vec<String> = vm.get_registers().iter()
+                .map(|register| format!("{}=0x{:x}", register.name, register.value))
+                .collect();
+            println!("  {}", registers.join(" "));
+            if stop_early {
+                return Ok(None);
+            }
+        }
+    })
 }
 
 impl Command {
     pub fn run(self) -> Result<(), Error> {
-        let Self { source_file_path } = self;
-        let source_code = std::fs::read_to_string(&source_file_path).map_err(|e| Error::ReadFile { file_path: source_file_path.clone(), source: e })?;
-        let ret = helios_vm::run(&source_code).map_err(|e| Error::RunBytecode { source: e })?;
+        let Self {
+            source_file_path, compute_unit_limit, heap_size, program_id, sysvars, seeds, sbpf_version, programs,
+            breakpoints, watchpoints, trace, trace_format, save_snapshot, resume,
+        } = self;
+        let trace_format: TraceFormat = trace_format.parse().map_err(|e| Error::InvalidTraceFormat { source: e })?;
+        let breakpoints = breakpoints.iter().map(|spec| parse_breakpoint(spec)).collect::<Result<Vec<_>, _>>()?;
+        let watchpoints = watchpoints.iter().map(|spec| parse_watchpoint(spec)).collect::<Result<Vec<_>, _>>()?;
+        helios_vm::set_compute_unit_limit(compute_unit_limit);
+        helios_vm::set_heap_size(heap_size).map_err(|e| Error::InvalidHeapSize { source: e })?;
+        helios_vm::set_sbpf_version(&sbpf_version).map_err(|e| Error::InvalidSbpfVersion { source: e })?;
+
+        if let Some(sysvars_path) = sysvars {
+            let fixture = std::fs::read_to_string(&sysvars_path).map_err(|e| Error::ReadFile { file_path: sysvars_path.clone(), source: e })?;
+            let format = if sysvars_path.extension().is_some_and(|ext| ext == "toml") { "toml" } else { "json" };
+            helios_vm::set_sysvars(&fixture, format).map_err(|e| Error::InvalidSysvars { source: e })?;
+        }
+
+        for spec in &programs {
+            register_program(spec)?;
+        }
+
+        let program_id = match program_id {
+            Some(program_id) => {
+                let bytes = decode_pubkey(&program_id)
+                    .ok_or_else(|| Error::InvalidProgramId { program_id: program_id.clone() })?;
+                helios_vm::set_program_id(&bytes).map_err(|_| Error::InvalidProgramId { program_id: program_id.clone() })?;
+                bytes.try_into().unwrap()
+            }
+            None => [0u8; 32],
+        };
+        if !seeds.is_empty() {
+            // the bump seed takes the last slot
+            if seeds.len() >= MAX_SEEDS {
+                return Err(Error::InvalidSeed { seed: seeds.join(","), source: format!("at most {} seeds besides the bump", MAX_SEEDS - 1) });
+            }
+            let seeds = seeds.iter().map(|seed| parse_seed(seed)).collect::<Result<Vec<_>, _>>()?;
+            // the configured accounts stay, only the instruction data changes
+            helios_vm::set_instruction_data(&encode_seeds(&seeds));
+            let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+            // what a correct PDA check in the program should arrive at
+            match helios_vm::syscalls::pda::find_program_address(&seeds, &program_id) {
+                Some((address, bump)) => println!("Expected PDA: {} (bump {})", bs58::encode(address).into_string(), bump),
+                None => println!("No valid PDA for the given seeds"),
+            }
+        }
+        if trace.is_some() {
+            helios_vm::with_vm(|vm| vm.enable_trace());
+        }
+        let result = if let Some(snapshot_path) = resume {
+            let snapshot = std::fs::read(&snapshot_path).map_err(|e| Error::ReadFile { file_path: snapshot_path.clone(), source: e })?;
+            helios_vm::restore_snapshot(&snapshot).map_err(|e| Error::InvalidSnapshot { file_path: snapshot_path, source: e })?;
+            run_with_breakpoints(breakpoints, watchpoints, save_snapshot.is_some())
+        } else {
+            let source_file_path = source_file_path.expect("required without --resume");
+            if source_file_path.extension().is_some_and(|ext| ext == "s") {
+                let source_code = std::fs::read_to_string(&source_file_path).map_err(|e| Error::ReadFile { file_path: source_file_path.clone(), source: e })?;
+                let path = source_file_path.to_string_lossy();
+                if breakpoints.is_empty() && watchpoints.is_empty() {
+                    helios_vm::run(&source_code, &path).map(Some)
+                } else {
+                    helios_vm::initialize(&source_code, &path)
+                        .and_then(|_| run_with_breakpoints(breakpoints, watchpoints, save_snapshot.is_some()))
+                }
+            } else {
+                let elf = std::fs::read(&source_file_path).map_err(|e| Error::ReadFile { file_path: source_file_path.clone(), source: e })?;
+                helios_vm::initialize_elf(&elf)
+                    .and_then(|_| run_with_breakpoints(breakpoints, watchpoints, save_snapshot.is_some()))
+            }
+        };
+        print!("{}", helios_vm::log_buffer::get_log());
+        // reported even if the program failed, like the runtime does
+        println!("consumed {} of {} compute units", helios_vm::get_compute_units_consumed(), helios_vm::get_compute_unit_limit());
+        // written even if the program failed, that is when it is most useful
+        if let Some(trace_path) = trace {
+            let write = |file| helios_vm::with_vm(|vm| vm.write_trace(trace_format, &mut std::io::BufWriter::new(file)));
+            std::fs::File::create(&trace_path)
+                .and_then(write)
+                .map_err(|e| Error::WriteFile { file_path: trace_path.clone(), source: e })?;
+        }
+        if let Some(snapshot_path) = &save_snapshot {
+            std::fs::write(snapshot_path, helios_vm::save_snapshot())
+                .map_err(|e| Error::WriteFile { file_path: snapshot_path.clone(), source: e })?;
+        }
+        let Some(ret) = result.map_err(|e| Error::RunBytecode { source: e })? else {
+            let snapshot_path = save_snapshot.expect("only stops early to save a snapshot");
+            println!("Saved snapshot to {}, continue with --resume {0}", snapshot_path.display());
+            return Ok(());
+        };
         println!("Return value: {}", ret);
+        let return_data = helios_vm::get_return_data();
+        if !return_data.is_empty() {
+            let program_id = bs58::encode(helios_vm::get_return_data_program_id()).into_string();
+            println!("Program return: {} {}", program_id, BASE64_STANDARD.encode(&return_data));
+            println!("Return data (hex): {}", helios_vm::to_hex(&return_data));
+        }
         Ok(())
     }
 }
@@ -23,7 +340,19 @@ impl Command {
 #[derive(Debug)]
 pub enum Error {
     ReadFile { file_path: PathBuf, source: std::io::Error },
-    RunBytecode { source: String },
+    RunBytecode { source: VmError },
+    InvalidProgramId { program_id: String },
+    InvalidHeapSize { source: VmError },
+    InvalidSysvars { source: VmError },
+    InvalidProgram { spec: String },
+    InvalidCallee { file_path: PathBuf, source: VmError },
+    InvalidBreakpoint { spec: String, source: String },
+    InvalidWatchpoint { spec: String, source: String },
+    InvalidTraceFormat { source: String },
+    InvalidSeed { seed: String, source: String },
+    InvalidSbpfVersion { source: VmError },
+    InvalidSnapshot { file_path: PathBuf, source: VmError },
+    WriteFile { file_path: PathBuf, source: std::io::Error },
 }
 
 impl std::fmt::Display for Error {
@@ -35,6 +364,36 @@ impl std::fmt::Display for Error {
             Error::RunBytecode { source } => {
                 write!(f, "Failed to run bytecode: {}", source)
             }
+            Error::InvalidProgramId { program_id } => {
+                write!(f, "Invalid program id {}, expected 32 base58 encoded bytes", program_id)
+            }
+            Error::WriteFile { file_path, source } => {
+                write!(f, "Failed to write file {}, error: {}", file_path.display(), source)
+            }
+            Error::InvalidHeapSize { source } | Error::InvalidSysvars { source } | Error::InvalidSbpfVersion { source } => {
+                write!(f, "{}", source)
+            }
+            Error::InvalidTraceFormat { source } => {
+                write!(f, "{}", source)
+            }
+            Error::InvalidProgram { spec } => {
+                write!(f, "Invalid program {}, expected <BASE58>=<PATH>", spec)
+            }
+            Error::InvalidCallee { file_path, source } => {
+                write!(f, "Failed to load program {}, error: {}", file_path.display(), source)
+            }
+            Error::InvalidBreakpoint { spec, source } => {
+                write!(f, "Invalid breakpoint {}, error: {}", spec, source)
+            }
+            Error::InvalidWatchpoint { spec, source } => {
+                write!(f, "Invalid watchpoint {}, error: {}", spec, source)
+            }
+            Error::InvalidSeed { seed, source } => {
+                write!(f, "Invalid seed {}, error: {}", seed, source)
+            }
+            Error::InvalidSnapshot { file_path, source } => {
+                write!(f, "Failed to restore snapshot {}, error: {}", file_path.display(), source)
+            }
         }
     }
 }
@@ -44,10 +403,33 @@ impl std::error::Error for Error {}
 impl CommandError for Error {
     fn exit_code(&self) -> exitcode::ExitCode {
         match self {
-            Self::RunBytecode { .. } => {
-                exitcode::DATAERR
-            }
+            Self::RunBytecode { source } => match source {
+                // the program could not be built or loaded
+                VmError::AssemblyError { .. } | VmError::ElfError { .. } => exitcode::DATAERR,
+                VmError::UnsupportedSyscall { .. } => exitcode::UNAVAILABLE,
+                // may succeed with a higher --compute-unit-limit
+                VmError::BudgetExceeded { .. } => exitcode::TEMPFAIL,
+                _ => exitcode::SOFTWARE,
+            },
             Self::ReadFile { .. } => exitcode::IOERR,
+            Self::WriteFile { .. } => exitcode::CANTCREAT,
+            Self::InvalidProgramId { .. } | Self::InvalidHeapSize { .. } | Self::InvalidProgram { .. }
+            | Self::InvalidBreakpoint { .. } | Self::InvalidWatchpoint { .. } | Self::InvalidTraceFormat { .. }
+            | Self::InvalidSeed { .. } | Self::InvalidSbpfVersion { .. } => exitcode::USAGE,
+            Self::InvalidSysvars { .. } | Self::InvalidCallee { .. } | Self::InvalidSnapshot { .. } => exitcode::DATAERR,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeds_are_length_prefixed() {
+        let seeds = ["vault", "hex:00ff", "base58:2"].map(|seed| parse_seed(seed).unwrap());
+        assert_eq!(encode_seeds(&seeds), [5, b'v', b'a', b'u', b'l', b't', 2, 0x00, 0xff, 1, 1]);
+        assert!(parse_seed("hex:abc").is_err());
+        assert!(parse_seed(&"x".repeat(MAX_SEED_LEN + 1)).is_err());
+    }
+}
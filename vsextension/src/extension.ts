@@ -61,12 +61,28 @@ export async function activate(context: vscode.ExtensionContext) {
             const code = editor.document.getText();
             const result = heliosVM.run(code, editor.document.uri.fsPath); // assuming `run` takes code string
             vscode.window.showInformationMessage(`VM output: ${result}`);
-        } catch (err) {
-            vscode.window.showErrorMessage(`Error running VM: ${err}`);
+        } catch (err: any) {
+            // VM errors are { kind, message, location: { pc, offset, line } }
+            const line = err?.location?.line ? ` (line ${err.location.line})` : '';
+            vscode.window.showErrorMessage(`Error running VM: ${err?.message ?? err}${line}`);
         }
     });
     
-    context.subscriptions.push(generateDisposable, runDisposable);
+    // the debug adapter runs in this process and shares the VM instance
+    let snapshotDisposable = vscode.commands.registerCommand('helios-sbpf-analyzer.saveSnapshot', async () => {
+        if (vscode.debug.activeDebugSession?.type !== 'helios-sbpf') {
+            vscode.window.showErrorMessage('No SBPF debug session is running');
+            return;
+        }
+        const uri = await vscode.window.showSaveDialog({ filters: { 'Helios snapshot': ['bin'] } });
+        if (!uri) {
+            return;
+        }
+        fs.writeFileSync(uri.fsPath, heliosVM.save_snapshot());
+        vscode.window.showInformationMessage(`Snapshot saved to ${uri.fsPath}`);
+    });
+
+    context.subscriptions.push(generateDisposable, runDisposable, snapshotDisposable);
 }
 
 export function deactivate() {} 
\ No newline at end of file
@@ -1,4 +1,5 @@
 import * as vscode from 'vscode';
+import * as fs from 'fs';
 import {
   DebugSession,
   InitializedEvent,
@@ -13,6 +14,14 @@ import * as heliosVM from '../wasm/helios_vm';
 interface LaunchRequestArguments extends DebugProtocol.LaunchRequestArguments {
   accountNumber?: number;
   instructionData?: number[] | string;
+  // bytes kept for stepping back, 64 MiB by default
+  historyLimit?: number;
+  // continue from a file saved with "Save SBPF Debug Snapshot"
+  snapshot?: string;
+  // compiled .so to debug instead of the assembly in the active editor
+  program?: string;
+  // "v0" to "v3", the version the active editor's assembly targets
+  sbpfVersion?: string;
 }
 
 class SBPFDebugSession extends DebugSession {
@@ -45,8 +54,188 @@ class SBPFDebugSession extends DebugSession {
     response.body.supportsConfigurationDoneRequest = true;
     response.body.supportsEvaluateForHovers = true;
     response.body.supportsStepInTargetsRequest = true;
+    response.body.supportsConditionalBreakpoints = true;
+    response.body.supportsDataBreakpoints = true;
+    response.body.supportsStepBack = true;
+    this.sendResponse(response);
+  }
+
+  protected stepBackRequest(
+    response: DebugProtocol.StepBackResponse,
+    args: DebugProtocol.StepBackArguments
+  ): void {
+    if (!heliosVM.step_back()) {
+      this.sendEvent(new OutputEvent('Reached the start of the recorded history\n'));
+    }
+    this.stopAfterStepBack('step');
+    this.sendResponse(response);
+  }
+
+  protected reverseContinueRequest(
+    response: DebugProtocol.ReverseContinueResponse,
+    args: DebugProtocol.ReverseContinueArguments
+  ): void {
+    const stop: { reason: 'breakpoint' | 'history_start', id?: number } = heliosVM.reverse_continue();
+    if (stop.reason === 'history_start') {
+      this.sendEvent(new OutputEvent('Reached the start of the recorded history\n'));
+    }
+    this.stopAfterStepBack(stop.reason === 'breakpoint' ? 'breakpoint' : 'step', stop.id);
     this.sendResponse(response);
-    this.sendEvent(new InitializedEvent());
+  }
+
+  private stopAfterStepBack(reason: string, breakpointId?: number): void {
+    this._currentRegisters = heliosVM.get_registers();
+    this._currentRodata = heliosVM.get_rodata();
+    this._currentMemory = heliosVM.get_memory();
+
+    const editor = vscode.window.activeTextEditor;
+    const stoppedEvent = new StoppedEvent(reason, 1);
+    (stoppedEvent as any).body = {
+      reason,
+      threadId: 1,
+      hitBreakpointIds: breakpointId === undefined ? undefined : [breakpointId],
+      source: {
+        name: 'program.sbpf',
+        path: editor?.document.uri.fsPath
+      },
+      line: heliosVM.get_line_number(),
+      column: 1
+    };
+    this.sendEvent(stoppedEvent);
+  }
+
+  protected dataBreakpointInfoRequest(
+    response: DebugProtocol.DataBreakpointInfoResponse,
+    args: DebugProtocol.DataBreakpointInfoArguments
+  ): void {
+    // registers can be watched for changes
+    if (args.variablesReference === this._registersVarRef) {
+      response.body = {
+        dataId: args.name,
+        description: `${args.name} changes`,
+        accessTypes: ['write']
+      };
+    } else {
+      response.body = { dataId: null, description: 'Only registers can be watched' };
+    }
+    this.sendResponse(response);
+  }
+
+  protected setDataBreakpointsRequest(
+    response: DebugProtocol.SetDataBreakpointsResponse,
+    args: DebugProtocol.SetDataBreakpointsArguments
+  ): void {
+    heliosVM.clear_watchpoints();
+    response.body = {
+      breakpoints: args.breakpoints.map(bp => {
+        // a condition such as "10" stops when the register changes to that value
+        const value = bp.condition ? BigInt(bp.condition) : undefined;
+        const id = heliosVM.add_register_watchpoint(parseInt(bp.dataId.slice(1)), value);
+        return { id, verified: true };
+      })
+    };
+    this.sendResponse(response);
+  }
+
+  protected setBreakPointsRequest(
+    response: DebugProtocol.SetBreakpointsResponse,
+    args: DebugProtocol.SetBreakpointsArguments
+  ): void {
+    // VS Code sends every breakpoint of the file, replace the VM's set
+    heliosVM.clear_breakpoints();
+    const ids = (args.breakpoints ?? []).map(bp => {
+      try {
+        return heliosVM.add_line_breakpoint(bp.line, bp.condition);
+      } catch (err: any) {
+        this.sendEvent(new OutputEvent(`${err?.message ?? err}\n`, 'stderr'));
+        return undefined;
+      }
+    });
+    const resolved: { id: number, pc: number | null }[] = heliosVM.get_breakpoints();
+    response.body = {
+      breakpoints: (args.breakpoints ?? []).map((bp, i) => ({
+        id: ids[i],
+        line: bp.line,
+        verified: resolved.some(r => r.id === ids[i] && r.pc !== null)
+      }))
+    };
+    this.sendResponse(response);
+  }
+
+  protected continueRequest(
+    response: DebugProtocol.ContinueResponse,
+    args: DebugProtocol.ContinueArguments
+  ): void {
+    let stop: {
+      reason: 'breakpoint' | 'watchpoint' | 'exit',
+      id?: number,
+      location?: { line: number | null },
+      change?: { kind: 'register' | 'memory', register?: number, addr?: number, old: any, new: any }
+    };
+    try {
+      stop = heliosVM.continue_execution();
+    } catch (err: any) {
+      this.reportError(err);
+      this.sendResponse(response);
+      return;
+    }
+
+    this._currentRegisters = heliosVM.get_registers();
+    this._currentRodata = heliosVM.get_rodata();
+    this._currentMemory = heliosVM.get_memory();
+
+    if (stop.reason === 'exit') {
+      this.sendEvent(new TerminatedEvent());
+      this.sendEvent(new OutputEvent(`Program output: ${heliosVM.get_log()}\n`));
+      this.sendResponse(response);
+      return;
+    }
+
+    if (heliosVM.get_log() !== '') {
+      this.sendEvent(new OutputEvent(`${heliosVM.get_log()}\n`));
+      heliosVM.clear_log();
+    }
+
+    const editor = vscode.window.activeTextEditor;
+    const reason = stop.reason === 'watchpoint' ? 'data breakpoint' : 'breakpoint';
+    const change = stop.change;
+    const stoppedEvent = new StoppedEvent(reason, 1);
+    (stoppedEvent as any).body = {
+      reason,
+      description: change?.kind === 'register'
+        ? `r${change.register}: ${change.old} -> ${change.new}`
+        : undefined,
+      threadId: 1,
+      hitBreakpointIds: [stop.id],
+      source: {
+        name: 'program.sbpf',
+        path: editor?.document.uri.fsPath
+      },
+      // the current line, a watchpoint reports the line that triggered it
+      line: heliosVM.get_line_number(),
+      column: 1
+    };
+    this.sendEvent(stoppedEvent);
+    this.sendResponse(response);
+  }
+
+  // Stops on the faulting line of a VmError
+  private reportError(err: any): void {
+    const editor = vscode.window.activeTextEditor;
+    this.sendEvent(new OutputEvent(`${err?.kind}: ${err?.message ?? err}\n`, 'stderr'));
+    const stoppedEvent = new StoppedEvent('exception', 1);
+    (stoppedEvent as any).body = {
+      reason: 'exception',
+      description: err?.message,
+      threadId: 1,
+      source: {
+        name: 'program.sbpf',
+        path: editor?.document.uri.fsPath
+      },
+      line: err?.location?.line ?? heliosVM.get_line_number(),
+      column: 1
+    };
+    this.sendEvent(stoppedEvent);
   }
 
   protected scopesRequest(
@@ -123,8 +312,14 @@ class SBPFDebugSession extends DebugSession {
     response: DebugProtocol.NextResponse,
     args: DebugProtocol.NextArguments
   ): void {
-    // Execute one instruction
-    heliosVM.step();
+    // Execute one instruction, stopping on the faulting line if it fails
+    try {
+      heliosVM.step();
+    } catch (err: any) {
+      this.reportError(err);
+      this.sendResponse(response);
+      return;
+    }
 
     // Update state after execution
     this._currentRegisters = heliosVM.get_registers();
@@ -168,24 +363,46 @@ class SBPFDebugSession extends DebugSession {
     const editor = vscode.window.activeTextEditor;
     const code = editor?.document.getText() || '';
 
+    const { accountNumber = 0, instructionData = [], historyLimit, snapshot, program, sbpfVersion = 'v0' } = args as LaunchRequestArguments;
+
     heliosVM.clear_log();
-    heliosVM.initialize(code, editor?.document.uri.fsPath || '');
-    
-    const { accountNumber = 0, instructionData = [] } = args as LaunchRequestArguments;
-    
-    let instructionBytes: Uint8Array;
-    let instructionDataType: 'string' | 'number';
-    if (typeof instructionData === 'string') {
-      // Convert string to Uint8Array (treating each character as a byte)
-      instructionBytes = new Uint8Array(instructionData.split('').map(char => char.charCodeAt(0)));
-      instructionDataType = 'string';
+    try {
+      heliosVM.set_sbpf_version(sbpfVersion);
+    } catch (err: any) {
+      this.sendErrorResponse(response, 1, `${err?.message ?? err}`);
+      return;
+    }
+    if (snapshot) {
+      try {
+        heliosVM.restore_snapshot(fs.readFileSync(snapshot));
+      } catch (err: any) {
+        this.sendErrorResponse(response, 1, `Failed to restore snapshot ${snapshot}: ${err?.message ?? err}`);
+        return;
+      }
     } else {
-      // instructionData is already a number array
-      instructionBytes = new Uint8Array(instructionData);
-      instructionDataType = 'number';
+      if (program) {
+        try {
+          heliosVM.initialize_elf(fs.readFileSync(program));
+        } catch (err: any) {
+          this.sendErrorResponse(response, 1, `Failed to load program ${program}: ${err?.message ?? err}`);
+          return;
+        }
+      } else {
+        heliosVM.initialize(code, editor?.document.uri.fsPath || '');
+      }
+
+      let instructionBytes: Uint8Array;
+      if (typeof instructionData === 'string') {
+        // Convert string to Uint8Array (treating each character as a byte)
+        instructionBytes = new Uint8Array(instructionData.split('').map(char => char.charCodeAt(0)));
+      } else {
+        // instructionData is already a number array
+        instructionBytes = new Uint8Array(instructionData);
+      }
+
+      heliosVM.load_input_data(BigInt(accountNumber), instructionBytes);
     }
-    
-    heliosVM.load_input_data(BigInt(accountNumber), instructionBytes, instructionDataType);
+    heliosVM.set_history_limit(historyLimit);
 
     // Get initial state
     this._currentRegisters = heliosVM.get_registers();
@@ -207,6 +424,8 @@ class SBPFDebugSession extends DebugSession {
     this.sendEvent(stoppedEvent);
 
     this.sendResponse(response);
+    // breakpoints are resolved against the program, so ask for them now
+    this.sendEvent(new InitializedEvent());
   }
 
   protected disconnectRequest(